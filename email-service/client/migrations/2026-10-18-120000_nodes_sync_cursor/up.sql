@@ -0,0 +1,4 @@
+-- # Explanation of some fields
+--
+-- `.sync_cursor` = identifier of the last email processed from the node
+ALTER TABLE nodes ADD COLUMN sync_cursor INTEGER NOT NULL DEFAULT 0
@@ -0,0 +1 @@
+ALTER TABLE nodes DROP COLUMN encrypted_identity_key;
@@ -0,0 +1,6 @@
+-- # Explanation of some fields
+--
+-- aes key = sha256(current user password, current user username)
+--
+-- `.encrypted_identity_key` = aes[aes key](pinned node identity key)
+ALTER TABLE nodes ADD COLUMN encrypted_identity_key BYTEA
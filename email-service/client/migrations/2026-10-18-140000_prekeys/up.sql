@@ -0,0 +1,13 @@
+-- # Explanation of some fields
+--
+-- aes key = sha256(current user password, current user username)
+--
+-- `.public_key_hash` = sha256(prekey raw public key)
+-- `.encrypted_private_key` = aes[aes key](prekey raw private key)
+CREATE TABLE prekeys (
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	public_key_hash BYTEA NOT NULL UNIQUE,
+	encrypted_private_key BYTEA NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW()
+)
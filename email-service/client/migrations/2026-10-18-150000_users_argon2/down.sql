@@ -0,0 +1,4 @@
+ALTER TABLE users
+	DROP COLUMN argon2_memory_cost,
+	DROP COLUMN argon2_time_cost,
+	DROP COLUMN argon2_parallelism;
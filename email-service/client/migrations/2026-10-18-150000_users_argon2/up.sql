@@ -0,0 +1,11 @@
+-- # Explanation of some fields
+--
+-- password keys = argon2id[params](current user password, current user salt)
+--
+-- `.password_hash` = password keys verifier, or sha256(current user password,
+-- current user salt) if params are NULL
+-- `.argon2_*` = params, NULL until the user logs in after this migration
+ALTER TABLE users
+	ADD COLUMN argon2_memory_cost INTEGER,
+	ADD COLUMN argon2_time_cost INTEGER,
+	ADD COLUMN argon2_parallelism INTEGER
@@ -0,0 +1,9 @@
+-- # Explanation of some fields
+--
+-- password keys = argon2id[params](current user password, current user salt)
+-- data key = random key that encrypts the data of the current user
+--
+-- `.wrapped_data_key` = aes[password keys data key](data key), NULL until the
+-- user logs in after this migration
+ALTER TABLE users
+	ADD COLUMN wrapped_data_key BYTEA
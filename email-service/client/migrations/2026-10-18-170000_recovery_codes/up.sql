@@ -0,0 +1,18 @@
+-- # Explanation of some fields
+--
+-- code keys = argon2id[params](recovery code, current user salt)
+-- data key = random key that encrypts the data of the current user
+--
+-- `.code_hash` = code keys verifier
+-- `.wrapped_data_key` = aes[code keys data key, `.code_hash`](data key)
+-- `.argon2_*` = params
+CREATE TABLE recovery_codes (
+	id SERIAL PRIMARY KEY,
+	user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+	code_hash BYTEA NOT NULL,
+	wrapped_data_key BYTEA NOT NULL,
+	argon2_memory_cost INTEGER NOT NULL,
+	argon2_time_cost INTEGER NOT NULL,
+	argon2_parallelism INTEGER NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW()
+)
@@ -0,0 +1,5 @@
+-- # Explanation of some fields
+--
+-- `.last_resort` - whether the prekey is published as the last-resort one of
+-- the user, it is never deleted, since nodes keep giving it.
+ALTER TABLE prekeys ADD COLUMN last_resort BOOLEAN NOT NULL DEFAULT FALSE
@@ -0,0 +1,6 @@
+-- # Explanation of some fields
+--
+-- `.session_generation` = number of the password changes and recoveries of the
+-- user, sessions of older generations are logged out
+ALTER TABLE users
+	ADD COLUMN session_generation INTEGER NOT NULL DEFAULT 0
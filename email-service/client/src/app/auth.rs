@@ -1,8 +1,35 @@
 use super::error::{
-	GetCurrentUserError, LoginUserError, ValidateLoggedInError,
-	ValidateLoggedOutError,
+	CheckCurrentUserError, GetCurrentUserError, LoginUserError,
+	ValidateLoggedInError, ValidateLoggedOutError,
 };
 
+/// Logs the current user out if the session is no longer valid, for example
+/// the user was deleted or changed the password. It is called for every
+/// request by
+/// [`middleware::CheckCurrentUser`](super::middleware::CheckCurrentUser), so
+/// [`get_current_user`] returns only valid users.
+pub(super) async fn check_current_user(
+	r: &actix_web::HttpRequest,
+) -> Result<(), CheckCurrentUserError> {
+	use actix_identity::IdentityExt as _;
+
+	let Some(user) = get_current_user(r)? else {
+		return Ok(());
+	};
+	let s = r.app_data::<actix_web::web::Data<crate::state::State>>().unwrap();
+	if !s
+		.db()
+		.check_user_session(&user)
+		.await
+		.map_err(CheckCurrentUserError::CheckUserSession)?
+	{
+		if let Ok(id) = r.get_identity() {
+			id.logout();
+		}
+	}
+	Ok(())
+}
+
 pub(super) fn get_current_user(
 	r: &actix_web::HttpRequest,
 ) -> Result<Option<crate::raw_models::User>, GetCurrentUserError> {
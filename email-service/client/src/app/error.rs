@@ -88,6 +88,45 @@ pub(crate) enum AddNodePostError {
 	ValidateLoggedIn(#[from] ValidateLoggedInError),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum AuthenticateNodeMailboxError {
+	#[error("Failed to convert public key to PEM.")]
+	PublicKeyToPem(#[source] openssl::error::ErrorStack),
+	#[error("Failed to sign a challenge.")]
+	SignChallenge(#[from] common::error::SignChallengeError),
+}
+
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum ChangePasswordGetError {
+	#[error("Failed to render.")]
+	Render(#[from] RenderError),
+	#[error("Failed to validate that user is logged in.")]
+	ValidateLoggedIn(#[from] ValidateLoggedInError),
+}
+
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum ChangePasswordPostError {
+	#[error("Failed to change the password of a user.")]
+	ChangePassword(#[from] anyhow::Error),
+	#[error("Failed to check the password of a user.")]
+	CheckUserPassword(#[source] anyhow::Error),
+	#[error("Failed to flash.")]
+	Flash(#[from] AddFlashError),
+	#[error("Failed to get a current user.")]
+	GetCurrentUser(#[from] GetCurrentUserError),
+	#[error("Failed to login a user.")]
+	LoginUser(#[from] LoginUserError),
+	#[error("Failed to make a static redirect.")]
+	RedirectStatic(#[from] RedirectStaticError),
+	#[error("Failed to render a form errors.")]
+	RenderFormErrors(#[from] RenderFormErrorsError),
+	#[error("Failed to validate that user is logged in.")]
+	ValidateLoggedIn(#[from] ValidateLoggedInError),
+}
+
 #[derive(thiserror::Error)]
 #[non_exhaustive]
 pub(crate) enum CheckCsrfTokenError {
@@ -99,13 +138,22 @@ pub(crate) enum CheckCsrfTokenError {
 	Parse(#[from] actix_web::cookie::ParseError),
 }
 
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum CheckCurrentUserError {
+	#[error("Failed to check the user session.")]
+	CheckUserSession(#[source] anyhow::Error),
+	#[error("Failed to get the current user.")]
+	GetCurrentUser(#[from] GetCurrentUserError),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub(crate) enum ConvertPemBase64ToPrivateKeyError {
 	#[error("Failed to decode base64.")]
 	Base64(#[from] base64::DecodeError),
 	#[error("Failed to build a private key.")]
-	Build(#[from] openssl::error::ErrorStack),
+	Build(#[from] common::error::KeyFromPemError),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -114,7 +162,7 @@ pub(crate) enum ConvertPemBase64ToPublicKeyError {
 	#[error("Failed to decode base64.")]
 	Base64(#[from] base64::DecodeError),
 	#[error("Failed to build a public key.")]
-	Build(#[from] openssl::error::ErrorStack),
+	Build(#[from] common::error::KeyFromPemError),
 }
 
 #[derive(thiserror::Error)]
@@ -131,6 +179,8 @@ pub(crate) enum DeleteAccountGetError {
 pub(crate) enum DeleteAccountPostError {
 	#[error("Failed to delete a user.")]
 	Delete(#[from] anyhow::Error),
+	#[error("Failed to check the password of a user.")]
+	CheckUserPassword(#[source] anyhow::Error),
 	#[error("Failed to flash.")]
 	Flash(#[from] AddFlashError),
 	#[error("Failed to get a current user.")]
@@ -283,9 +333,16 @@ pub(crate) enum GetCurrentUserError {
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
-pub(crate) enum GetNodeEmailsCountError {
-	#[error("Failed to convert public key to PEM.")]
-	PublicKeyToPem(#[from] openssl::error::ErrorStack),
+pub(crate) enum GetNodeEmailIdsError {
+	#[error("Failed to convert a request to bytes.")]
+	RequestToBytes(#[from] bincode::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum GetNodeEmailsError {
+	#[error("Failed to convert a request to bytes.")]
+	RequestToBytes(#[from] bincode::Error),
 }
 
 #[derive(thiserror::Error)]
@@ -323,18 +380,30 @@ pub(crate) enum LoadEmailsError {
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub(crate) enum LoadNodeEmailsError {
+	#[error("Failed to authenticate the mailbox on node.")]
+	AuthenticateNodeMailbox(#[from] AuthenticateNodeMailboxError),
 	#[error("Failed to check that email exists.")]
 	CheckEmailExists(#[source] anyhow::Error),
 	#[error("Failed to check that friend exists by public key.")]
 	CheckFriendExistsByPublicKey(#[source] anyhow::Error),
 	#[error("Failed to check user f2f status.")]
 	CheckUserF2f(#[source] anyhow::Error),
-	#[error("Failed to get emails count from node.")]
-	GetNodeEmailsCount(#[from] GetNodeEmailsCountError),
+	#[error("Failed to delete a used prekey.")]
+	DeletePrekey(#[source] anyhow::Error),
+	#[error("Failed to get email identifiers from node.")]
+	GetNodeEmailIds(#[from] GetNodeEmailIdsError),
+	#[error("Failed to get emails from node.")]
+	GetNodeEmails(#[from] GetNodeEmailsError),
+	#[error("Failed to get a prekey.")]
+	GetPrekey(#[source] anyhow::Error),
 	#[error("Failed to convert public key to PEM.")]
 	PublicKeyToPem(#[from] openssl::error::ErrorStack),
+	#[error("Failed to publish prekeys to node.")]
+	PublishNodePrekeys(#[from] PublishNodePrekeysError),
 	#[error("Failed to convert a request to bytes.")]
 	RequestToBytes(#[from] bincode::Error),
+	#[error("Failed to set node sync cursor.")]
+	SetNodeSyncCursor(#[source] anyhow::Error),
 }
 
 #[derive(thiserror::Error)]
@@ -357,6 +426,8 @@ pub(crate) enum LoginPostError {
 	LoginUser(#[from] LoginUserError),
 	#[error("Failed to make a static redirect.")]
 	RedirectStatic(#[from] RedirectStaticError),
+	#[error("Failed to re-encrypt user data.")]
+	ReencryptUserData(#[source] anyhow::Error),
 	#[error("Failed to render.")]
 	Render(#[from] RenderError),
 	#[error("Failed to render a form errors.")]
@@ -448,6 +519,84 @@ pub(crate) enum ProfileError {
 	ValidateLoggedIn(#[from] ValidateLoggedInError),
 }
 
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum ProgressError {
+	#[error("Failed to get a current user.")]
+	GetCurrentUser(#[from] GetCurrentUserError),
+	#[error("Failed to validate that user is logged out..")]
+	ValidateLoggedIn(#[from] ValidateLoggedInError),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum PublishNodePrekeysError {
+	#[error("Failed to save prekeys.")]
+	AddPrekeys(#[source] anyhow::Error),
+	#[error("Failed to generate a prekey.")]
+	GeneratePrekey(#[source] openssl::error::ErrorStack),
+	#[error("Failed to get the last-resort prekey.")]
+	GetLastResortPrekey(#[source] anyhow::Error),
+	#[error("Failed to convert public key to PEM.")]
+	PublicKeyToPem(#[source] openssl::error::ErrorStack),
+	#[error("Failed to convert a request to bytes.")]
+	RequestToBytes(#[from] bincode::Error),
+	#[error("Failed to sign a prekey.")]
+	SignPrekey(#[source] openssl::error::ErrorStack),
+}
+
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum RecoverGetError {
+	#[error("Failed to render.")]
+	Render(#[from] RenderError),
+	#[error("Failed to validate logged out.")]
+	ValidateLoggedOut(#[from] ValidateLoggedOutError),
+}
+
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum RecoverPostError {
+	#[error("Failed to flash the message.")]
+	Flash(#[from] AddFlashError),
+	#[error("Failed to make a static redirect.")]
+	RedirectStatic(#[from] RedirectStaticError),
+	#[error("Failed to recover a user.")]
+	RecoverUser(#[from] anyhow::Error),
+	#[error("Failed to render a form errors.")]
+	RenderFormErrors(#[from] RenderFormErrorsError),
+	#[error("Failed to validate logged out.")]
+	ValidateLoggedOut(#[from] ValidateLoggedOutError),
+}
+
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum RecoveryCodesGetError {
+	#[error("Failed to render.")]
+	Render(#[from] RenderError),
+	#[error("Failed to validate that user is logged in.")]
+	ValidateLoggedIn(#[from] ValidateLoggedInError),
+}
+
+#[derive(thiserror::Error)]
+#[non_exhaustive]
+pub(crate) enum RecoveryCodesPostError {
+	#[error("Failed to check the password of a user.")]
+	CheckUserPassword(#[source] anyhow::Error),
+	#[error("Failed to make a flash.")]
+	Flash(#[from] AddFlashError),
+	#[error("Failed to get a current user.")]
+	GetCurrentUser(#[from] GetCurrentUserError),
+	#[error("Failed to render.")]
+	Render(#[from] RenderError),
+	#[error("Failed to render a form errors.")]
+	RenderFormErrors(#[from] RenderFormErrorsError),
+	#[error("Failed to replace the recovery codes of a user.")]
+	ReplaceRecoveryCodes(#[from] anyhow::Error),
+	#[error("Failed to validate that user is logged in.")]
+	ValidateLoggedIn(#[from] ValidateLoggedInError),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub(crate) enum RedirectError {
@@ -479,9 +628,7 @@ pub(crate) enum RegisterPostError {
 	#[error("Failed to make a flash.")]
 	Flash(#[from] AddFlashError),
 	#[error("Failed to generate a private key.")]
-	GeneratePrivateKey(#[from] openssl::error::ErrorStack),
-	#[error("Failed to make a static redirect.")]
-	RedirectStatic(#[from] RedirectStaticError),
+	GeneratePrivateKey(#[from] common::error::GeneratePrivateKeyError),
 	#[error("Failed to render.")]
 	Render(#[from] RenderError),
 	#[error("Failed to render form's errors.")]
@@ -550,16 +697,24 @@ pub(crate) enum SendEmailPostError {
 	EmailIsTooBigFlash(#[source] AddFlashError),
 	#[error("Failed to convert an email to bytes.")]
 	EmailToBytes(#[from] bincode::Error),
+	#[error("Failed to encrypt an email session.")]
+	EncryptEmailSession(#[from] common::error::EncryptEmailSessionError),
 	#[error("Failed to extract multipart.")]
 	ExtractMultipart(#[from] ExtractMultipartError),
+	#[error("Failed to generate a proof-of-work.")]
+	GenerateProofOfWork(#[from] common::error::GenerateProofOfWorkError),
 	#[error("Failed to get a current user.")]
 	GetCurrentUser(#[from] GetCurrentUserError),
 	#[error("Failed to get friends.")]
 	GetFriends(#[source] anyhow::Error),
 	#[error("Failed to get nodes.")]
 	GetNodes(#[source] anyhow::Error),
+	#[error("Failed to get a recipient prekey.")]
+	GetRecipientPrekey(#[source] openssl::error::ErrorStack),
 	#[error("Failed to get a user private key.")]
 	GetUserPrivateKey(#[source] anyhow::Error),
+	#[error("Failed to join a task.")]
+	Join(#[from] tokio::task::JoinError),
 	#[error("Failed to create a new email.")]
 	NewEmail(#[from] common::error::NewEmailError),
 	#[error("Failed to make a not sent flash.")]
@@ -633,6 +788,15 @@ impl_error!(
 	CheckCsrfTokenError:
 	Self::Invalid | Self::NotFound => BAD_REQUEST
 );
+impl_error!(CheckCurrentUserError);
+impl_error!(
+	ChangePasswordGetError:
+	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
+);
+impl_error!(
+	ChangePasswordPostError:
+	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
+);
 impl_error!(
 	DeleteAccountGetError:
 	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
@@ -698,6 +862,26 @@ impl_error!(
 	ProfileError:
 	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
 );
+impl_error!(
+	ProgressError:
+	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
+);
+impl_error!(
+	RecoverGetError:
+	Self::ValidateLoggedOut(ValidateLoggedOutError::LoggedIn) => FORBIDDEN
+);
+impl_error!(
+	RecoverPostError:
+	Self::ValidateLoggedOut(ValidateLoggedOutError::LoggedIn) => FORBIDDEN
+);
+impl_error!(
+	RecoveryCodesGetError:
+	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
+);
+impl_error!(
+	RecoveryCodesPostError:
+	Self::ValidateLoggedIn(ValidateLoggedInError::LoggedOut) => UNAUTHORIZED
+);
 impl_error!(
 	RegisterGetError:
 	Self::ValidateLoggedOut(ValidateLoggedOutError::LoggedIn) => FORBIDDEN
@@ -31,6 +31,39 @@ pub(crate) struct Login {
 	csrf_token: String,
 }
 
+#[derive(serde::Deserialize, validator::Validate)]
+pub(crate) struct Recover {
+	#[validate(length(
+		min = 3,
+		max = 45,
+		message = "Username length must be >= 3 and <= 45."
+	))]
+	pub username: String,
+	#[validate(length(
+		min = 1,
+		max = 64,
+		message = "Recovery code length must be >= 1 and <= 64."
+	))]
+	pub recovery_code: String,
+	#[validate(length(
+		min = 6,
+		max = 50,
+		message = "New password length must be >= 6 and <= 50."
+	))]
+	pub new_password: String,
+	#[validate(must_match(
+		other = "new_password",
+		message = "New passwords must match."
+	))]
+	new_password_confirm: String,
+	#[validate(custom(
+		function = "validate_csrf_token",
+		arg = "&'v_a actix_web::HttpRequest",
+		message = "CSRF token is invalid."
+	))]
+	csrf_token: String,
+}
+
 #[derive(serde::Deserialize, validator::Validate)]
 pub(crate) struct Register {
 	#[validate(
@@ -72,9 +105,7 @@ pub(crate) struct Register {
 
 impl Register {
 	#[must_use]
-	pub fn get_private_key(
-		&self,
-	) -> Option<openssl::rsa::Rsa<openssl::pkey::Private>> {
+	pub fn get_private_key(&self) -> Option<common::crypto::PrivateKey> {
 		// Can use `Option::unwrap`, because private key validated in
 		// `self.validate_private_key_pem_base64`.
 		self.private_key_pem_base64.as_ref().map(|s| {
@@ -100,21 +131,41 @@ impl Register {
 	}
 }
 
+#[derive(serde::Deserialize, validator::Validate)]
+pub(crate) struct ChangePassword {
+	#[validate(length(
+		min = 6,
+		max = 50,
+		message = "Password length must be >= 6 and <= 50."
+	))]
+	pub password: String,
+	#[validate(length(
+		min = 6,
+		max = 50,
+		message = "New password length must be >= 6 and <= 50."
+	))]
+	pub new_password: String,
+	#[validate(must_match(
+		other = "new_password",
+		message = "New passwords must match."
+	))]
+	new_password_confirm: String,
+	#[validate(custom(
+		function = "validate_csrf_token",
+		arg = "&'v_a actix_web::HttpRequest",
+		message = "CSRF token is invalid."
+	))]
+	csrf_token: String,
+}
+
 #[derive(serde::Deserialize, validator::Validate)]
 pub(crate) struct DeleteAccount {
-	#[validate(
-		custom(
-			function = "Self::validate_password",
-			arg = "&'v_a crate::raw_models::User",
-			message = "Invalid password."
-		),
-		length(
-			min = 6,
-			max = 50,
-			message = "Password length must be >= 6 and <= 50."
-		)
-	)]
-	password: String,
+	#[validate(length(
+		min = 6,
+		max = 50,
+		message = "Password length must be >= 6 and <= 50."
+	))]
+	pub password: String,
 	#[validate(custom(
 		function = "validate_csrf_token",
 		arg = "&'v_a actix_web::HttpRequest",
@@ -123,16 +174,20 @@ pub(crate) struct DeleteAccount {
 	csrf_token: String,
 }
 
-impl DeleteAccount {
-	fn validate_password(
-		s: &str,
-		user: &crate::raw_models::User,
-	) -> ValidationResult {
-		if s == user.password() {
-			return Ok(());
-		}
-		Err(ValidationError::new("invalid"))
-	}
+#[derive(serde::Deserialize, validator::Validate)]
+pub(crate) struct RecoveryCodes {
+	#[validate(length(
+		min = 6,
+		max = 50,
+		message = "Password length must be >= 6 and <= 50."
+	))]
+	pub password: String,
+	#[validate(custom(
+		function = "validate_csrf_token",
+		arg = "&'v_a actix_web::HttpRequest",
+		message = "CSRF token is invalid."
+	))]
+	csrf_token: String,
 }
 
 #[derive(serde::Deserialize, validator::Validate)]
@@ -215,6 +270,11 @@ pub(crate) struct Node {
 	)]
 	pub address: String,
 	pub password: String,
+	#[validate(custom(
+		function = "Self::validate_identity_key",
+		message = "Identity key is invalid."
+	))]
+	pub identity_key: String,
 	#[validate(custom(
 		function = "validate_csrf_token",
 		arg = "&'v_a actix_web::HttpRequest",
@@ -230,6 +290,17 @@ impl Node {
 		Ok(())
 	}
 
+	/// Empty or raw X25519 public key encoded into base64.
+	fn validate_identity_key(s: &str) -> ValidationResult {
+		if s.is_empty() {
+			return Ok(());
+		}
+		match base64::decode(s) {
+			Ok(k) if k.len() == 32 => Ok(()),
+			_ => Err(ValidationError::new("invalid")),
+		}
+	}
+
 	fn validate_address_unique(
 		s: &str,
 		data: (&crate::db::Db, &crate::raw_models::User),
@@ -271,7 +342,7 @@ impl Email {
 	#[must_use]
 	pub(super) fn get_recipient_public_key(
 		&self,
-	) -> openssl::rsa::Rsa<openssl::pkey::Public> {
+	) -> common::crypto::PublicKey {
 		// It must be validated in
 		// [`validate_private_key`](Email::validate_private_key).
 		// Therefore, we can use `Result::unwrap`.
@@ -3,27 +3,21 @@ use super::error::{
 };
 
 /// Converts the Base-64 encoded PEM into a
-/// [`private key`](openssl::rsa::Rsa<openssl::pkey::Private>).
+/// [`private key`](common::crypto::PrivateKey).
 pub(super) fn convert_pem_base64_to_private_key(
 	s: &str,
-) -> Result<
-	openssl::rsa::Rsa<openssl::pkey::Private>,
-	ConvertPemBase64ToPrivateKeyError,
-> {
+) -> Result<common::crypto::PrivateKey, ConvertPemBase64ToPrivateKeyError> {
 	let pem = base64::decode(s)?;
-	let key = openssl::rsa::Rsa::private_key_from_pem(&pem)?;
+	let key = common::crypto::PrivateKey::from_pem(&pem)?;
 	Ok(key)
 }
 
 /// Converts the Base-64 encoded PEM into a
-/// [`public key`](openssl::rsa::Rsa<openssl::pkey::Public>).
+/// [`public key`](common::crypto::PublicKey).
 pub(super) fn convert_pem_base64_to_public_key(
 	s: &str,
-) -> Result<
-	openssl::rsa::Rsa<openssl::pkey::Public>,
-	ConvertPemBase64ToPublicKeyError,
-> {
+) -> Result<common::crypto::PublicKey, ConvertPemBase64ToPublicKeyError> {
 	let pem = base64::decode(s)?;
-	let key = openssl::rsa::Rsa::public_key_from_pem(&pem)?;
+	let key = common::crypto::PublicKey::from_pem(&pem)?;
 	Ok(key)
 }
@@ -1,21 +1,101 @@
-/// Returns [`actix_session::CookieSession`] with the secret key from the
-/// config
+/// Returns [`actix_session::SessionMiddleware`] with the secret key from the
+/// config, that keeps session states in the `store`. Session states expire
+/// after `consts::SESSION_IDLE_TIMEOUT` without requests.
 #[must_use]
 pub(crate) fn make_session_middleware(
 	config: &crate::config::Config,
-) -> actix_session::SessionMiddleware<actix_session::storage::CookieSessionStore>
-{
+	store: super::session::Store,
+) -> actix_session::SessionMiddleware<super::session::Store> {
+	use actix_session::config::{BrowserSession, TtlExtensionPolicy};
+
 	let secret_key_bytes = config.secret_key().as_bytes();
-	actix_session::SessionMiddleware::new(
-		actix_session::storage::CookieSessionStore::default(),
+	// Can't wrap, see `consts::SESSION_IDLE_TIMEOUT`
+	#[allow(clippy::cast_possible_wrap)]
+	let lifecycle = BrowserSession::default()
+		.state_ttl(actix_web::cookie::time::Duration::seconds(
+			crate::consts::SESSION_IDLE_TIMEOUT as i64,
+		))
+		.state_ttl_extension_policy(TtlExtensionPolicy::OnEveryRequest);
+	actix_session::SessionMiddleware::builder(
+		store,
 		actix_web::cookie::Key::from(secret_key_bytes),
 	)
+	.session_lifecycle(lifecycle)
+	.build()
 }
 
-/// Returns [`actix_identity::IdentityMiddleware`]
-#[inline]
+/// Returns [`actix_identity::IdentityMiddleware`] that logs users out after
+/// `consts::SESSION_IDLE_TIMEOUT` without requests or
+/// `consts::SESSION_ABSOLUTE_TIMEOUT` after login.
 #[must_use]
 pub(crate) fn make_identity_middleware() -> actix_identity::IdentityMiddleware
 {
-	actix_identity::IdentityMiddleware::default()
+	use std::time::Duration;
+	actix_identity::IdentityMiddleware::builder()
+		.visit_deadline(Some(Duration::from_secs(
+			crate::consts::SESSION_IDLE_TIMEOUT,
+		)))
+		.login_deadline(Some(Duration::from_secs(
+			crate::consts::SESSION_ABSOLUTE_TIMEOUT,
+		)))
+		.build()
+}
+
+/// Checks the current user for every request with
+/// `auth::check_current_user`, so sessions of deleted users and of older
+/// generations end. Must be wrapped by the session and identity middlewares.
+pub(crate) struct CheckCurrentUser;
+
+impl<S, B> actix_web::dev::Transform<S, actix_web::dev::ServiceRequest>
+	for CheckCurrentUser
+where
+	S: actix_web::dev::Service<
+			actix_web::dev::ServiceRequest,
+			Response = actix_web::dev::ServiceResponse<B>,
+			Error = actix_web::Error,
+		> + 'static,
+{
+	type Error = actix_web::Error;
+	type Future = std::future::Ready<Result<Self::Transform, Self::InitError>>;
+	type InitError = ();
+	type Response = actix_web::dev::ServiceResponse<B>;
+	type Transform = CheckCurrentUserMiddleware<S>;
+
+	fn new_transform(&self, service: S) -> Self::Future {
+		std::future::ready(Ok(CheckCurrentUserMiddleware {
+			service: std::rc::Rc::new(service),
+		}))
+	}
+}
+
+/// See [`CheckCurrentUser`].
+pub(crate) struct CheckCurrentUserMiddleware<S> {
+	service: std::rc::Rc<S>,
+}
+
+impl<S, B> actix_web::dev::Service<actix_web::dev::ServiceRequest>
+	for CheckCurrentUserMiddleware<S>
+where
+	S: actix_web::dev::Service<
+			actix_web::dev::ServiceRequest,
+			Response = actix_web::dev::ServiceResponse<B>,
+			Error = actix_web::Error,
+		> + 'static,
+{
+	type Error = actix_web::Error;
+	type Future = futures::future::LocalBoxFuture<
+		'static,
+		Result<Self::Response, Self::Error>,
+	>;
+	type Response = actix_web::dev::ServiceResponse<B>;
+
+	actix_web::dev::forward_ready!(service);
+
+	fn call(&self, r: actix_web::dev::ServiceRequest) -> Self::Future {
+		let service = std::rc::Rc::clone(&self.service);
+		Box::pin(async move {
+			super::auth::check_current_user(r.request()).await?;
+			service.call(r).await
+		})
+	}
 }
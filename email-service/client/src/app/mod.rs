@@ -10,9 +10,11 @@ mod keys;
 pub(crate) mod middleware;
 mod multipart;
 pub(crate) mod pagination;
+pub(crate) mod progress;
 mod qrcode;
 mod request_node;
 mod response;
 #[allow(clippy::unused_async)]
 pub(crate) mod service;
+pub(crate) mod session;
 pub(crate) mod tera;
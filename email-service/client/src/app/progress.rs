@@ -0,0 +1,83 @@
+/// Progress of a long request of a user, like sending an email, shown by
+/// `service::progress` to the page that made the request.
+#[derive(Clone, Debug, serde::Serialize)]
+pub(crate) struct Progress {
+	message: String,
+	percent: Option<u8>,
+}
+
+type Map = std::collections::HashMap<i32, Option<Progress>>;
+
+/// Progresses of requests by identifiers of their users.
+///
+/// Clones share the same progresses, so one can be used by all workers.
+#[derive(Clone, Default)]
+pub(crate) struct Progresses(std::sync::Arc<std::sync::Mutex<Map>>);
+
+impl Progresses {
+	pub(crate) fn get(&self, user_id: i32) -> Option<Progress> {
+		self.progresses().get(&user_id).cloned().flatten()
+	}
+
+	/// Sets the progress of the user, if it is
+	/// [tracked](Progresses::track).
+	pub(crate) fn set(
+		&self,
+		user_id: i32,
+		message: String,
+		percent: Option<u8>,
+	) {
+		if let Some(p) = self.progresses().get_mut(&user_id) {
+			*p = Some(Progress { message, percent });
+		}
+	}
+
+	/// Sets the transfer `progress` with the `message` followed by
+	/// transferred and total kibibytes.
+	pub(crate) fn set_transfer(
+		&self,
+		user_id: i32,
+		message: &str,
+		progress: common::package::Progress,
+	) {
+		use std::convert::TryFrom as _;
+
+		let percent = (progress.transferred() * 100)
+			.checked_div(progress.total())
+			.and_then(|p| u8::try_from(p).ok());
+		self.set(
+			user_id,
+			format!(
+				"{message} {} of {} KiB.",
+				progress.transferred() / 1024,
+				progress.total() / 1024
+			),
+			percent,
+		);
+	}
+
+	/// Tracks the progress of the user until the returned guard is dropped,
+	/// so it's removed even if the request fails or is cancelled, and late
+	/// updates are ignored.
+	#[must_use]
+	pub(crate) fn track(&self, user_id: i32) -> TrackGuard {
+		self.progresses().entry(user_id).or_default();
+		TrackGuard { progresses: self.clone(), user_id }
+	}
+
+	fn progresses(&self) -> std::sync::MutexGuard<'_, Map> {
+		self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+	}
+}
+
+/// Removes the progress of the user on drop, see [`Progresses::track`].
+pub(crate) struct TrackGuard {
+	progresses: Progresses,
+	user_id: i32,
+}
+
+impl Drop for TrackGuard {
+	fn drop(&mut self) {
+		self.progresses.progresses().remove(&self.user_id);
+	}
+}
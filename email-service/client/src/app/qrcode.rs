@@ -1,15 +1,14 @@
 /// Makes a QR code from the `data`, then encodes it in PNG format in the
 /// buffer.
 ///
-/// # Panic
-///
-/// If `data` is too long.
+/// Returns [`None`] if `data` is too long for a QR code, like a private key
+/// with ML-KEM.
 #[must_use]
-pub(super) fn make_png_bytes<T: AsRef<[u8]>>(data: T) -> Vec<u8> {
+pub(super) fn make_png_bytes<T: AsRef<[u8]>>(data: T) -> Option<Vec<u8>> {
 	use image::ImageEncoder as _;
 
 	// Build QR code image
-	let qrcode = qrcode::QrCode::new(data).unwrap();
+	let qrcode = qrcode::QrCode::new(data).ok()?;
 	let image = qrcode.render::<image::Luma<u8>>().build();
 
 	// Extract QR code image info
@@ -20,5 +19,5 @@ pub(super) fn make_png_bytes<T: AsRef<[u8]>>(data: T) -> Vec<u8> {
 	let mut buffer = vec![];
 	let encoder = image::codecs::png::PngEncoder::new(&mut buffer);
 	encoder.write_image(&bytes, width, height, image::ColorType::L8).unwrap();
-	buffer
+	Some(buffer)
 }
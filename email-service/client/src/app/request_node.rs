@@ -1,118 +1,138 @@
-use super::error::{GetNodeEmailsCountError, LoadNodeEmailsError};
+use super::error::{
+	AuthenticateNodeMailboxError, GetNodeEmailIdsError, GetNodeEmailsError,
+	LoadNodeEmailsError, PublishNodePrekeysError,
+};
+
+/// Request actions that a node must support to load emails from it.
+const LOAD_EMAILS_ACTIONS: [common::package::Action; 4] = [
+	common::package::Action::AuthenticateMailbox,
+	common::package::Action::GetEmailIds,
+	common::package::Action::GetEmails,
+	common::package::Action::GetMailboxChallenge,
+];
 
 /// Used in `app::service::load_emails` in multi-threaded mode to load
 /// emails from each node. Returns the number of loaded emails.
+///
+/// The receive progress is shown as the transfer `i` of the progress sum.
 pub(super) async fn load_emails(
 	node: crate::raw_models::Node,
 	s: actix_web::web::Data<crate::state::State>,
 	user: std::sync::Arc<crate::raw_models::User>,
-	private_key: std::sync::Arc<openssl::rsa::Rsa<openssl::pkey::Private>>,
+	private_key: std::sync::Arc<common::crypto::PrivateKey>,
+	(i, progress_sum): (usize, common::package::ProgressSum),
 ) -> Result<u8, LoadNodeEmailsError> {
-	// Get emails count
+	// Open a session
 	let node = std::sync::Arc::new(node);
-	let mut stream = common::connect_or_else!(
-		node.address(),
-		s.config().proxy(),
-		return Ok(0),
-	);
-	let count = get_emails_count(&node, &private_key, &mut stream).await?;
-	if count == 0 {
+	let Some(mut session) =
+		open_mailbox_session(&node, &s, &private_key).await?
+	else {
 		return Ok(0);
-	}
+	};
+	let (progresses, user_id) = (s.progresses().clone(), user.id());
+	common::package::Progress::forward(
+		session.watch_receive_progress(),
+		move |p| {
+			progresses.set_transfer(
+				user_id,
+				"Receiving emails:",
+				progress_sum.update(i, p),
+			);
+		},
+	);
 
-	// Request to add each email until we reach the limit
+	// Top up our prekeys on the node
+	publish_prekeys(&node, &s, &user, &private_key, &mut session).await?;
+
+	// Request identifiers of emails after the sync cursor and then batches of
+	// emails through the same session, and add each email until we reach the
+	// limit
 	let public_key_hash =
 		common::crypto::hash(private_key.public_key_to_pem()?);
 	let mut added_count = 0u8;
-	for index in 0..count {
-		let mut stream = common::connect_or_else!(
-			node.address(),
-			s.config().proxy(),
-			return Ok(0),
-		);
-
-		// Send a request
-		let package = common::package::Package::new(
-			node.password(),
-			common::package::Action::GetEmail,
-			bincode::serialize(&(index, &public_key_hash))?,
-		);
-		common::send_package_or_else!(
-			package,
-			&mut stream,
-			node.address(),
-			continue,
-		);
+	let mut cursor = node.sync_cursor();
+	'ids: loop {
+		let mut ids =
+			get_email_ids(&node, public_key_hash, cursor, &mut session)
+				.await?;
 
-		// Receive and validate a response
-		let response = common::receive_package_or_else!(
-			&mut stream,
-			node.address(),
-			None,
-			Some(common::set![
-				common::package::Action::GetEmailSuccess,
-				common::package::Action::GetEmailFail,
-			]),
-			return Ok(0),
-		);
-		if response.action() == common::package::Action::GetEmailFail {
-			common::debug!("Failed to get email from {}.", node.address());
-			continue;
-		}
-
-		// Deserialize, decrypt and validate an email
-		let Ok(mut email) =
-			bincode::deserialize::<common::email::Email>(response.data())
-		else {
-			continue;
+		// Don't trust the node to return sorted identifiers after the cursor
+		ids.retain(|&id| id > cursor);
+		ids.sort_unstable();
+		ids.dedup();
+		let Some(&last_id) = ids.last() else {
+			break;
 		};
-		if email.decrypt(&private_key).is_err()
-			|| !matches!(email.check_decrypted_integrity(), Ok(true))
-			|| s.db()
-				.check_email_exists(&user, &email)
-				.await
-				.map_err(LoadNodeEmailsError::CheckEmailExists)?
-		{
-			continue;
-		}
 
-		// Check F2F
-		//
-		// We can use `Option::unwrap` because integrity check.
-		let sender_public_key_pem = email.sender_public_key_pem().unwrap();
-		let f2f = s
-			.db()
-			.check_user_f2f(&user)
-			.await
-			.map_err(LoadNodeEmailsError::CheckUserF2f)?;
-		let sender_public_key_pem_base64 =
-			&base64::encode(sender_public_key_pem);
-		let friend_exists_by_public_key = s
-			.db()
-			.check_friend_exists_by_public_key(
-				&user,
-				sender_public_key_pem_base64,
+		while !ids.is_empty() {
+			let previous_cursor = cursor;
+			let batch_ids: Vec<i32> = ids
+				.iter()
+				.copied()
+				.take(crate::consts::EMAILS_BATCH_LIMIT)
+				.collect();
+			let Some(emails) = get_emails(
+				&node,
+				public_key_hash,
+				batch_ids.clone(),
+				&mut session,
 			)
-			.await
-			.map_err(LoadNodeEmailsError::CheckFriendExistsByPublicKey)?;
-		if f2f && !friend_exists_by_public_key {
-			continue;
-		}
+			.await?
+			else {
+				break 'ids;
+			};
 
-		// Add a new email
-		if s.db().add_email(&user, &email).await.is_err() {
-			common::debug!(
-				"Failed to load an email from {} to the database.",
-				node.address()
-			);
-			continue;
-		};
-		added_count += 1;
-		if added_count == crate::consts::NEW_EMAILS_FROM_NODE_LIMIT {
-			break;
+			// Nothing received means the remaining emails were deleted from
+			// the node
+			if emails.is_empty() {
+				cursor = last_id;
+				break;
+			}
+
+			// Add each requested email from the batch and move the cursor
+			// after it, so it's not requested again
+			for (id, email_bytes) in emails {
+				if id <= cursor || !batch_ids.contains(&id) {
+					continue;
+				}
+				let added =
+					add_email(&node, &s, &user, &private_key, &email_bytes)
+						.await?;
+				cursor = id;
+				if added {
+					added_count += 1;
+					if added_count
+						== s.config().limits().new_emails_from_node_limit()
+					{
+						break 'ids;
+					}
+				}
+			}
+
+			// A node that returns no new emails would make us request the
+			// same ones forever
+			if cursor == previous_cursor {
+				common::debug!(
+					"Received no new emails from {}.",
+					node.address()
+				);
+				break 'ids;
+			}
+
+			// Skip requested identifiers up to the last received one, since
+			// missing emails were deleted from the node
+			ids.retain(|&id| id > cursor);
 		}
 	}
 
+	// Save the sync cursor
+	if cursor != node.sync_cursor() {
+		s.db()
+			.set_node_sync_cursor(&user, node.id(), cursor)
+			.await
+			.map_err(LoadNodeEmailsError::SetNodeSyncCursor)?;
+	}
+
 	common::debug!(
 		"{} new emails loaded from {}.",
 		added_count,
@@ -121,6 +141,253 @@ pub(super) async fn load_emails(
 	Ok(added_count)
 }
 
+/// Deserializes, decrypts and validates an email received from `node`, then
+/// adds it to the database. Returns `true` if the email was added.
+async fn add_email(
+	node: &crate::raw_models::Node,
+	s: &crate::state::State,
+	user: &crate::raw_models::User,
+	private_key: &common::crypto::PrivateKey,
+	email_bytes: &[u8],
+) -> Result<bool, LoadNodeEmailsError> {
+	// Deserialize an email and find the prekey it is encrypted to
+	let Ok(mut email) = common::email::Email::from_stored_bytes(email_bytes)
+	else {
+		return Ok(false);
+	};
+	let prekey = match email.prekey_public_key() {
+		Some(k) => {
+			match s
+				.db()
+				.get_prekey(user, k)
+				.await
+				.map_err(LoadNodeEmailsError::GetPrekey)?
+			{
+				Some(p) => Some(p),
+				// Deleted, so the email was already added or can't be
+				// decrypted anymore
+				None => return Ok(false),
+			}
+		}
+		None => None,
+	};
+
+	// Decrypt and validate the email, then delete the used prekey
+	if email.decrypt(private_key, prekey.as_ref()).is_err()
+		|| !matches!(email.check_decrypted_integrity(), Ok(true))
+	{
+		return Ok(false);
+	}
+	if let Some(k) = email.prekey_public_key() {
+		s.db()
+			.delete_prekey(user, k)
+			.await
+			.map_err(LoadNodeEmailsError::DeletePrekey)?;
+	}
+	if s.db()
+		.check_email_exists(user, &email)
+		.await
+		.map_err(LoadNodeEmailsError::CheckEmailExists)?
+	{
+		return Ok(false);
+	}
+
+	// Check F2F
+	//
+	// We can use `Option::unwrap` because integrity check.
+	let sender_public_key_pem = email.sender_public_key_pem().unwrap();
+	let f2f = s
+		.db()
+		.check_user_f2f(user)
+		.await
+		.map_err(LoadNodeEmailsError::CheckUserF2f)?;
+	let sender_public_key_pem_base64 = &base64::encode(sender_public_key_pem);
+	let friend_exists_by_public_key = s
+		.db()
+		.check_friend_exists_by_public_key(user, sender_public_key_pem_base64)
+		.await
+		.map_err(LoadNodeEmailsError::CheckFriendExistsByPublicKey)?;
+	if f2f && !friend_exists_by_public_key {
+		return Ok(false);
+	}
+
+	// Add a new email
+	if s.db().add_email(user, &email).await.is_err() {
+		common::debug!(
+			"Failed to load an email from {} to the database.",
+			node.address()
+		);
+		return Ok(false);
+	};
+	Ok(true)
+}
+
+/// Publishes new signed one-time prekeys to the node through the
+/// authenticated mailbox `session`, so it has `consts::PREKEYS_PER_NODE` of
+/// them, and the last-resort prekey of the user, if the node doesn't have it.
+/// Private prekeys are saved before publishing. Nothing is published if the
+/// node doesn't support prekeys.
+async fn publish_prekeys(
+	node: &crate::raw_models::Node,
+	s: &crate::state::State,
+	user: &crate::raw_models::User,
+	private_key: &common::crypto::PrivateKey,
+	session: &mut common::package::Session,
+) -> Result<(), PublishNodePrekeysError> {
+	// Get the number of remaining prekeys
+	let public_key_hash = common::crypto::hash(
+		private_key
+			.public_key_to_pem()
+			.map_err(PublishNodePrekeysError::PublicKeyToPem)?,
+	);
+	let package = common::package::Package::new(
+		common::package::Action::GetPrekeysCount,
+		public_key_hash,
+	);
+	let response = common::request_package_or_else!(
+		session,
+		package,
+		node.address(),
+		Some(common::set![
+			common::package::Action::GetPrekeysCountSuccess,
+			common::package::Action::GetPrekeysCountFail,
+		]),
+		return Ok(()),
+	);
+	let Ok(count) = common::helpers::deserialize::<
+		common::package::PrekeysCountData,
+	>(response.data()) else {
+		common::debug!("Failed to get prekeys count from {}.", node.address());
+		return Ok(());
+	};
+	let missing_count = <usize as std::convert::TryFrom<i64>>::try_from(
+		crate::consts::PREKEYS_PER_NODE.saturating_sub(count.count()),
+	)
+	.unwrap_or(0);
+	if missing_count == 0 && count.has_last_resort_prekey() {
+		return Ok(());
+	}
+
+	// Generate and save new prekeys, then publish them signed
+	let prekeys = (0..missing_count)
+		.map(|_| common::crypto::Prekey::generate())
+		.collect::<Result<Vec<_>, _>>()
+		.map_err(PublishNodePrekeysError::GeneratePrekey)?;
+	s.db()
+		.add_prekeys(user, &prekeys, false)
+		.await
+		.map_err(PublishNodePrekeysError::AddPrekeys)?;
+	let signed_prekeys = prekeys
+		.iter()
+		.map(|p| p.sign(private_key))
+		.collect::<Result<_, _>>()
+		.map_err(PublishNodePrekeysError::SignPrekey)?;
+	let last_resort_prekey = if count.has_last_resort_prekey() {
+		None
+	} else {
+		Some(
+			get_last_resort_prekey(s, user)
+				.await?
+				.sign(private_key)
+				.map_err(PublishNodePrekeysError::SignPrekey)?,
+		)
+	};
+	let package = common::package::Package::new(
+		common::package::Action::PublishPrekeys,
+		bincode::serialize(&common::package::PublishPrekeysData::new(
+			public_key_hash,
+			signed_prekeys,
+			last_resort_prekey,
+		))?,
+	);
+	let response = common::request_package_or_else!(
+		session,
+		package,
+		node.address(),
+		Some(common::set![
+			common::package::Action::PublishPrekeysSuccess,
+			common::package::Action::PublishPrekeysFail,
+		]),
+		return Ok(()),
+	);
+	if response.action() == common::package::Action::PublishPrekeysFail {
+		common::debug!("Failed to publish prekeys to {}.", node.address());
+	}
+	Ok(())
+}
+
+/// Returns the last-resort prekey of the user, generating and saving it
+/// first if the user doesn't have one.
+async fn get_last_resort_prekey(
+	s: &crate::state::State,
+	user: &crate::raw_models::User,
+) -> Result<common::crypto::Prekey, PublishNodePrekeysError> {
+	if let Some(p) = s
+		.db()
+		.get_last_resort_prekey(user)
+		.await
+		.map_err(PublishNodePrekeysError::GetLastResortPrekey)?
+	{
+		return Ok(p);
+	}
+	let prekey = common::crypto::Prekey::generate()
+		.map_err(PublishNodePrekeysError::GeneratePrekey)?;
+	s.db()
+		.add_prekeys(user, std::slice::from_ref(&prekey), true)
+		.await
+		.map_err(PublishNodePrekeysError::AddPrekeys)?;
+	Ok(prekey)
+}
+
+/// Used in `app::service::send_email_post` to take a signed prekey of the
+/// recipient from one of the `nodes`. Nodes are asked one by one, since each
+/// taken prekey is deleted. Returns `None` if no node has a prekey signed by
+/// the recipient.
+pub(super) async fn get_recipient_prekey(
+	s: &crate::state::State,
+	nodes: &[crate::raw_models::Node],
+	recipient_public_key: &common::crypto::PublicKey,
+) -> Result<Option<common::crypto::SignedPrekey>, openssl::error::ErrorStack> {
+	let recipient_public_key_hash =
+		common::crypto::hash(recipient_public_key.to_pem()?);
+	for node in nodes {
+		let Some(mut session) =
+			open_session(node, s, &[common::package::Action::GetPrekey]).await
+		else {
+			continue;
+		};
+		let package = common::package::Package::new(
+			common::package::Action::GetPrekey,
+			recipient_public_key_hash,
+		);
+		let response = common::request_package_or_else!(
+			&mut session,
+			package,
+			node.address(),
+			Some(common::set![
+				common::package::Action::GetPrekeySuccess,
+				common::package::Action::GetPrekeyFail,
+			]),
+			continue,
+		);
+		if response.action() == common::package::Action::GetPrekeyFail {
+			continue;
+		}
+		match common::helpers::deserialize::<common::crypto::SignedPrekey>(
+			response.data(),
+		) {
+			Ok(p) if matches!(p.verify(recipient_public_key), Ok(true)) => {
+				return Ok(Some(p));
+			}
+			_ => common::debug!(
+				"Received an invalid prekey from {}.",
+				node.address()
+			),
+		}
+	}
+	Ok(None)
+}
+
 /// Used in `app::service::nodes_post` to check connection with each
 /// node. Returns the address, the status of the check, and the reason why the
 /// check failed.
@@ -128,85 +395,323 @@ pub(super) async fn check_connection(
 	s: actix_web::web::Data<crate::state::State>,
 	node: crate::raw_models::Node,
 ) -> (std::net::SocketAddr, Option<&'static str>) {
-	let mut stream = common::connect_or_else!(
+	let stream = common::connect_or_else!(
 		node.address(),
 		s.config().proxy(),
 		return (node.address(), Some("Failed to connect.")),
 	);
-	// Make and send the package
+	let mut session = common::package::Session::with_limits(
+		stream,
+		*s.config().limits().common(),
+	);
+	common::handshake_or_else!(
+		&mut session,
+		node.address(),
+		node.identity_key(),
+		return (node.address(), Some("Failed to encrypt the connection.")),
+	);
+	// Check the protocol
+	match session.hello(std::collections::HashSet::new()).await {
+		Ok(h) if h.supports(&LOAD_EMAILS_ACTIONS) => {}
+		Ok(_) => return (node.address(), Some("Node is outdated.")),
+		Err(e) => {
+			common::debug!("Failed to say hello to {}: {}", node.address(), e);
+			return (node.address(), Some("Unsupported protocol."));
+		}
+	}
+	// Authenticate the password
+	if let Some(p) = node.password() {
+		match session.authenticate(p).await {
+			Ok(()) => {}
+			Err(common::error::AuthenticateSessionError::InvalidPassword) => {
+				return (node.address(), Some("Invalid password."));
+			}
+			Err(e) => {
+				common::debug!(
+					"Failed to authenticate the session with {}: {}",
+					node.address(),
+					e
+				);
+				return (node.address(), Some("Failed to connect."));
+			}
+		}
+	}
+	// Make the package and receive a response
 	let package = common::package::Package::new(
-		node.password(),
 		common::package::Action::CheckConnection,
 		vec![],
 	);
-	common::send_package_or_else!(
+	let response = common::request_package_or_else!(
+		&mut session,
 		package,
-		&mut stream,
-		node.address(),
-		return (node.address(), Some("Failed to connect.")),
-	);
-	// Receive a response
-	let response = common::receive_package_or_else!(
-		&mut stream,
 		node.address(),
-		None,
 		Some(common::set![
-			common::package::Action::InvalidPassword,
+			common::package::Action::PasswordRequired,
 			common::package::Action::CheckConnectionSuccess,
 		]),
 		return (node.address(), Some("Failed to connect.")),
 	);
 	// Return status
 	match response.action() {
-		common::package::Action::InvalidPassword => {
-			(node.address(), Some("Invalid password."))
+		common::package::Action::PasswordRequired => {
+			(node.address(), Some("Password is required."))
 		}
 		_ => (node.address(), None),
 	}
 }
 
-async fn get_emails_count(
-	node: &crate::raw_models::Node,
-	private_key: &openssl::rsa::Rsa<openssl::pkey::Private>,
-	stream: &mut tokio::net::TcpStream,
-) -> Result<i64, GetNodeEmailsCountError> {
-	let public_key_hash =
-		common::crypto::hash(private_key.public_key_to_pem()?);
+/// Used in `app::service::send_email_post` to get the strictest
+/// proof-of-work difficulties among our own and the `nodes` ones.
+pub(super) async fn get_strictest_proof_of_work_difficulty(
+	s: &actix_web::web::Data<crate::state::State>,
+	nodes: &[crate::raw_models::Node],
+) -> Result<common::email::ProofOfWorkDifficulty, tokio::task::JoinError> {
+	let mut futures = Vec::with_capacity(nodes.len());
+	for node in nodes {
+		futures.push(tokio::spawn(get_proof_of_work_difficulty(
+			s.clone(),
+			node.clone(),
+		)));
+	}
+	let mut difficulty =
+		s.config().limits().common().proof_of_work_difficulty();
+	for rd in futures::future::join_all(futures).await {
+		if let Some(d) = rd? {
+			difficulty = difficulty.max(d);
+		}
+	}
+	Ok(difficulty)
+}
+
+/// Gets the proof-of-work difficulties required by `node`. Returns `None` if
+/// the node is unavailable or doesn't advertise them.
+async fn get_proof_of_work_difficulty(
+	s: actix_web::web::Data<crate::state::State>,
+	node: crate::raw_models::Node,
+) -> Option<common::email::ProofOfWorkDifficulty> {
+	let mut session = open_session(&node, &s, &[
+		common::package::Action::GetProofOfWorkDifficulty,
+	])
+	.await?;
 	let package = common::package::Package::new(
-		node.password(),
-		common::package::Action::GetEmailsCount,
-		public_key_hash,
+		common::package::Action::GetProofOfWorkDifficulty,
+		vec![],
 	);
-	common::send_package_or_else!(
+	let response = common::request_package_or_else!(
+		&mut session,
 		package,
-		stream,
 		node.address(),
-		return Ok::<_, GetNodeEmailsCountError>(0),
+		Some(common::set![
+			common::package::Action::GetProofOfWorkDifficultySuccess
+		]),
+		return None,
 	);
-	let response = common::receive_package_or_else!(
+	common::helpers::deserialize(response.data()).ok()
+}
+
+/// Opens a session with `node`, encrypts it if the node identity key is
+/// pinned, checks that the node supports `LOAD_EMAILS_ACTIONS`,
+/// authenticates the password if it is set and authenticates the mailbox.
+/// Returns `None` if the node is unavailable or rejected us.
+async fn open_mailbox_session(
+	node: &crate::raw_models::Node,
+	s: &crate::state::State,
+	private_key: &common::crypto::PrivateKey,
+) -> Result<Option<common::package::Session>, AuthenticateNodeMailboxError> {
+	let Some(mut session) = open_session(node, s, &LOAD_EMAILS_ACTIONS).await
+	else {
+		return Ok(None);
+	};
+	if !authenticate_mailbox(node, private_key, &mut session).await? {
+		return Ok(None);
+	}
+	Ok(Some(session))
+}
+
+/// Opens a session with `node`, encrypts it if the node identity key is
+/// pinned, checks that the node supports `required_actions` and
+/// authenticates the password if it is set. Returns `None` if the node is
+/// unavailable or rejected us.
+async fn open_session(
+	node: &crate::raw_models::Node,
+	s: &crate::state::State,
+	required_actions: &[common::package::Action],
+) -> Option<common::package::Session> {
+	let stream = common::connect_or_else!(
+		node.address(),
+		s.config().proxy(),
+		return None,
+	);
+	let mut session = common::package::Session::with_limits(
 		stream,
+		*s.config().limits().common(),
+	);
+	common::handshake_or_else!(
+		&mut session,
+		node.address(),
+		node.identity_key(),
+		return None,
+	);
+	match session.hello(std::collections::HashSet::new()).await {
+		Ok(h) if h.supports(required_actions) => {}
+		Ok(h) => {
+			common::debug!(
+				"{} doesn't support required actions (protocol version {}).",
+				node.address(),
+				h.protocol_version()
+			);
+			return None;
+		}
+		Err(e) => {
+			common::debug!("Failed to say hello to {}: {}", node.address(), e);
+			return None;
+		}
+	}
+	common::authenticate_or_else!(
+		&mut session,
+		node.address(),
+		node.password(),
+		return None,
+	);
+	Some(session)
+}
+
+/// Proves to the node that we own the private key, so it allows to list and
+/// get emails of our mailbox in the session. Returns `false` if the node
+/// rejected authentication.
+async fn authenticate_mailbox(
+	node: &crate::raw_models::Node,
+	private_key: &common::crypto::PrivateKey,
+	session: &mut common::package::Session,
+) -> Result<bool, AuthenticateNodeMailboxError> {
+	// Get a challenge
+	let package = common::package::Package::new(
+		common::package::Action::GetMailboxChallenge,
+		private_key
+			.public_key_to_pem()
+			.map_err(AuthenticateNodeMailboxError::PublicKeyToPem)?,
+	);
+	let response = common::request_package_or_else!(
+		session,
+		package,
 		node.address(),
-		None,
 		Some(common::set![
-			common::package::Action::GetEmailsCountSuccess,
-			common::package::Action::GetEmailsCountFail,
+			common::package::Action::GetMailboxChallengeSuccess,
+			common::package::Action::GetMailboxChallengeFail,
 		]),
-		return Ok(0),
+		return Ok(false),
 	);
-	if response.action() == common::package::Action::GetEmailsCountFail {
+	if response.action() == common::package::Action::GetMailboxChallengeFail {
 		common::debug!(
-			"Failed to get the count of emails from {}.",
+			"Failed to get a mailbox challenge from {}.",
 			node.address()
 		);
-		return Ok(0);
+		return Ok(false);
+	}
+
+	// Sign the nonce and authenticate
+	let package = common::package::Package::new(
+		common::package::Action::AuthenticateMailbox,
+		common::crypto::sign_challenge(private_key, response.data())?,
+	);
+	let response = common::request_package_or_else!(
+		session,
+		package,
+		node.address(),
+		Some(common::set![
+			common::package::Action::AuthenticateMailboxSuccess,
+			common::package::Action::AuthenticateMailboxFail,
+		]),
+		return Ok(false),
+	);
+	if response.action() == common::package::Action::AuthenticateMailboxFail {
+		common::debug!(
+			"Failed to authenticate the mailbox on {}.",
+			node.address()
+		);
+		return Ok(false);
+	}
+	Ok(true)
+}
+
+async fn get_email_ids(
+	node: &crate::raw_models::Node,
+	public_key_hash: [u8; 32],
+	after_id: i32,
+	session: &mut common::package::Session,
+) -> Result<Vec<i32>, GetNodeEmailIdsError> {
+	let package = common::package::Package::new(
+		common::package::Action::GetEmailIds,
+		bincode::serialize(&common::package::GetEmailIdsData::new(
+			public_key_hash,
+			after_id,
+			crate::consts::EMAIL_IDS_LIMIT,
+		))?,
+	);
+	let response = common::request_package_or_else!(
+		session,
+		package,
+		node.address(),
+		Some(common::set![
+			common::package::Action::GetEmailIdsSuccess,
+			common::package::Action::GetEmailIdsFail,
+		]),
+		return Ok(vec![]),
+	);
+	if response.action() == common::package::Action::GetEmailIdsFail {
+		common::debug!(
+			"Failed to get email identifiers from {}.",
+			node.address()
+		);
+		return Ok(vec![]);
+	}
+	if let Ok(ids) = common::helpers::deserialize(response.data()) {
+		Ok(ids)
+	} else {
+		common::debug!(
+			"Received invalid package data from {}.",
+			node.address()
+		);
+		Ok(vec![])
+	}
+}
+
+/// Returns `None` if the node failed to return the emails.
+async fn get_emails(
+	node: &crate::raw_models::Node,
+	public_key_hash: [u8; 32],
+	ids: Vec<i32>,
+	session: &mut common::package::Session,
+) -> Result<Option<Vec<(i32, Vec<u8>)>>, GetNodeEmailsError> {
+	let package = common::package::Package::new(
+		common::package::Action::GetEmails,
+		bincode::serialize(&common::package::GetEmailsData::new(
+			public_key_hash,
+			ids,
+			crate::consts::EMAILS_BATCH_MAX_SIZE,
+		))?,
+	);
+	let response = common::request_package_or_else!(
+		session,
+		package,
+		node.address(),
+		Some(common::set![
+			common::package::Action::GetEmailsSuccess,
+			common::package::Action::GetEmailsFail,
+		]),
+		return Ok(None),
+	);
+	if response.action() == common::package::Action::GetEmailsFail {
+		common::debug!("Failed to get emails from {}.", node.address());
+		return Ok(None);
 	}
-	if let Ok(c) = bincode::deserialize(response.data()) {
-		Ok(c)
+	if let Ok(emails) = common::helpers::deserialize(response.data()) {
+		Ok(Some(emails))
 	} else {
 		common::debug!(
 			"Received invalid package data from {}.",
 			node.address()
 		);
-		Ok(0)
+		Ok(None)
 	}
 }
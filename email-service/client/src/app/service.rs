@@ -1,10 +1,12 @@
 use super::error::{
 	AddFriendGetError, AddFriendPostError, AddNodeGetError, AddNodePostError,
-	DeleteAccountGetError, DeleteAccountPostError, DeleteFriendError,
-	DeleteNodeError, EmailError, EmailsError, FriendsError, IndexError,
-	LoadEmailsError, LoginGetError, LoginPostError, LogoutError,
-	NodesGetError, NodesPostError, ProfileError, RegisterGetError,
-	RegisterPostError, SendEmailGetError, SendEmailPostError, SwitchF2fError,
+	ChangePasswordGetError, ChangePasswordPostError, DeleteAccountGetError,
+	DeleteAccountPostError, DeleteFriendError, DeleteNodeError, EmailError,
+	EmailsError, FriendsError, IndexError, LoadEmailsError, LoginGetError,
+	LoginPostError, LogoutError, NodesGetError, NodesPostError, ProfileError,
+	ProgressError, RecoverGetError, RecoverPostError, RecoveryCodesGetError,
+	RecoveryCodesPostError, RegisterGetError, RegisterPostError,
+	SendEmailGetError, SendEmailPostError, SwitchF2fError,
 };
 
 macro_rules! validate_at_least_one_friend_and_one_node {
@@ -107,7 +109,12 @@ pub(crate) async fn add_node_post(
 	} else {
 		Some(form.password.as_str())
 	};
-	s.db().add_node(&user, &form.address, password).await?;
+	let identity_key = if form.identity_key.is_empty() {
+		None
+	} else {
+		Some(form.identity_key.as_str())
+	};
+	s.db().add_node(&user, &form.address, password, identity_key).await?;
 	super::flash::add(
 		&r,
 		"Your node has been successfully added.",
@@ -116,6 +123,71 @@ pub(crate) async fn add_node_post(
 	Ok(super::response::redirect_static(&r, "nodes_get")?)
 }
 
+#[actix_web::get("/change-password/")]
+pub(crate) async fn change_password_get(
+	r: actix_web::HttpRequest,
+) -> Result<actix_web::HttpResponse, ChangePasswordGetError> {
+	super::auth::validate_logged_in(&r)?;
+	Ok(super::response::render(
+		&r,
+		"change-password.html",
+		None,
+		actix_web::http::StatusCode::OK,
+		true,
+	)?)
+}
+
+#[actix_web::post("/change-password/")]
+pub(crate) async fn change_password_post(
+	s: actix_web::web::Data<crate::state::State>,
+	r: actix_web::HttpRequest,
+	form: actix_web::web::Form<super::forms::ChangePassword>,
+) -> Result<actix_web::HttpResponse, ChangePasswordPostError> {
+	use validator::ValidateArgs as _;
+	super::auth::validate_logged_in(&r)?;
+
+	// Get user and validate the form
+	//
+	// We can use `Option::unwrap` because of `super::auth::validate_logged_in`
+	let user = super::auth::get_current_user(&r)?.unwrap();
+	if let Err(ref errors) = form.validate_args(&r) {
+		return Ok(super::response::render_form_errors(
+			&r,
+			"change-password.html",
+			errors,
+			None,
+		)?);
+	}
+
+	// Check the password here, since the derivation is slow by design
+	if !s
+		.db()
+		.check_user_password(&user, &form.password)
+		.await
+		.map_err(ChangePasswordPostError::CheckUserPassword)?
+	{
+		let errors = validation_errors! {"invalid" => "Invalid password"};
+		return Ok(super::response::render_form_errors(
+			&r,
+			"change-password.html",
+			&errors,
+			None,
+		)?);
+	}
+
+	// Change the password and renew the session, logging out other ones
+	let session_generation =
+		s.db().change_user_password(&user, &form.new_password).await?;
+	super::auth::login_user(
+		&r,
+		&user.with_session_generation(session_generation),
+	)?;
+
+	// Flash the message and redirect
+	super::flash::add(&r, "You have changed your password.", "success")?;
+	Ok(super::response::redirect_static(&r, "profile")?)
+}
+
 #[actix_web::get("/delete-account/")]
 pub(crate) async fn delete_account_get(
 	r: actix_web::HttpRequest,
@@ -144,7 +216,7 @@ pub(crate) async fn delete_account_post(
 	//
 	// We can use `Option::unwrap` because of `super::auth::validate_logged_in`
 	let user = super::auth::get_current_user(&r)?.unwrap();
-	if let Err(ref errors) = form.validate_args((&user, &r)) {
+	if let Err(ref errors) = form.validate_args(&r) {
 		return Ok(super::response::render_form_errors(
 			&r,
 			"delete-account.html",
@@ -153,6 +225,22 @@ pub(crate) async fn delete_account_post(
 		)?);
 	}
 
+	// Check the password
+	if !s
+		.db()
+		.check_user_password(&user, &form.password)
+		.await
+		.map_err(DeleteAccountPostError::CheckUserPassword)?
+	{
+		let errors = validation_errors! {"invalid" => "Invalid password"};
+		return Ok(super::response::render_form_errors(
+			&r,
+			"delete-account.html",
+			&errors,
+			None,
+		)?);
+	}
+
 	// Delete user from the database and from the identity
 	s.db().delete_user(&user).await?;
 	identity.logout();
@@ -263,7 +351,10 @@ pub(crate) async fn emails(
 	let page = query
 		.page()
 		.unwrap_or(unsafe { std::num::NonZeroU64::new_unchecked(1) });
-	let pagination = s.db().get_emails(&user, page).await?;
+	let pagination = s
+		.db()
+		.get_emails(&user, page, s.config().limits().emails_per_page())
+		.await?;
 	// On the first page, if there are no items,  we can place a corresponding
 	// inscription.
 	if pagination.items().is_empty() && u64::from(page) > 1 {
@@ -272,7 +363,8 @@ pub(crate) async fn emails(
 
 	let context = context! {
 		"pagination" => &pagination,
-		"emails_max_age_secs" => &common::consts::EMAILS_MAX_AGE.as_secs(),
+		"emails_max_age_secs"
+			=> &s.config().limits().common().emails_max_age().as_secs(),
 	};
 	Ok(super::response::render(
 		&r,
@@ -347,14 +439,17 @@ pub(crate) async fn load_emails(
 	let nodes =
 		s.db().get_nodes(&user).await.map_err(LoadEmailsError::GetNodes)?;
 
-	// Spawn load futures
+	// Spawn load futures, which show the sum of their progress
+	let _progress_guard = s.progresses().track(user.id());
+	let progress_sum = common::package::ProgressSum::default();
 	let mut futures = Vec::with_capacity(nodes.len());
-	for node in nodes {
+	for (i, node) in nodes.into_iter().enumerate() {
 		futures.push(tokio::spawn(super::request_node::load_emails(
 			node,
 			s.clone(),
 			user.clone(),
 			private_key.clone(),
+			(i, progress_sum.clone()),
 		)));
 	}
 
@@ -404,6 +499,12 @@ pub(crate) async fn login_post(
 	}
 	match s.db().get_user(form.username.clone(), form.password.clone()).await {
 		Ok(u) => {
+			// Move data written in outdated envelopes to the current one
+			s.db()
+				.reencrypt_user_data(&u)
+				.await
+				.map_err(LoginPostError::ReencryptUserData)?;
+
 			// Login user, flash the message and redirect to index
 			super::auth::login_user(&r, &u)?;
 			super::flash::add(&r, "You are logged into account.", "success")?;
@@ -493,6 +594,19 @@ pub(crate) async fn nodes_post(
 	)?)
 }
 
+/// Responds with the JSON of the `progress::Progress` of the current request
+/// of the user, or `null`.
+#[actix_web::get("/progress/")]
+pub(crate) async fn progress(
+	s: actix_web::web::Data<crate::state::State>,
+	r: actix_web::HttpRequest,
+) -> Result<actix_web::HttpResponse, ProgressError> {
+	super::auth::validate_logged_in(&r)?;
+	// We can use `Option::unwrap` because of `super::auth::validate_logged_in`
+	let user = super::auth::get_current_user(&r)?.unwrap();
+	Ok(actix_web::HttpResponse::Ok().json(s.progresses().get(user.id())))
+}
+
 #[actix_web::get("/profile/")]
 pub(crate) async fn profile(
 	s: actix_web::web::Data<crate::state::State>,
@@ -518,16 +632,18 @@ pub(crate) async fn profile(
 	let private_key_pem = private_key.private_key_to_pem()?;
 	let public_key_pem = private_key.public_key_to_pem()?;
 
-	// Make QR codes
-	let private_key_qrcode = super::qrcode::make_png_bytes(&private_key_pem);
-	let public_key_qrcode = super::qrcode::make_png_bytes(&public_key_pem);
+	// Make QR codes, if keys fit
+	let private_key_qrcode =
+		super::qrcode::make_png_bytes(&private_key_pem).map(base64::encode);
+	let public_key_qrcode =
+		super::qrcode::make_png_bytes(&public_key_pem).map(base64::encode);
 
 	let context = context! {
 		"f2f_enabled" => &f2f_enabled,
 		"private_key_pem_base64" => &base64::encode(private_key_pem),
 		"public_key_pem_base64" => &base64::encode(public_key_pem),
-		"private_key_qrcode" => &base64::encode(private_key_qrcode),
-		"public_key_qrcode" => &base64::encode(public_key_qrcode),
+		"private_key_qrcode" => &private_key_qrcode,
+		"public_key_qrcode" => &public_key_qrcode,
 	};
 	Ok(super::response::render(
 		&r,
@@ -538,6 +654,133 @@ pub(crate) async fn profile(
 	)?)
 }
 
+#[actix_web::get("/recover/")]
+pub(crate) async fn recover_get(
+	r: actix_web::HttpRequest,
+) -> Result<actix_web::HttpResponse, RecoverGetError> {
+	super::auth::validate_logged_out(&r)?;
+	Ok(super::response::render(
+		&r,
+		"recover.html",
+		None,
+		actix_web::http::StatusCode::OK,
+		true,
+	)?)
+}
+
+#[actix_web::post("/recover/")]
+pub(crate) async fn recover_post(
+	s: actix_web::web::Data<crate::state::State>,
+	r: actix_web::HttpRequest,
+	form: actix_web::web::Form<super::forms::Recover>,
+) -> Result<actix_web::HttpResponse, RecoverPostError> {
+	use validator::ValidateArgs as _;
+	super::auth::validate_logged_out(&r)?;
+
+	// Validate the form
+	if let Err(ref errors) = form.validate_args(&r) {
+		return Ok(super::response::render_form_errors(
+			&r,
+			"recover.html",
+			errors,
+			None,
+		)?);
+	}
+	match s
+		.db()
+		.recover_user(&form.username, &form.recovery_code, &form.new_password)
+		.await
+	{
+		Ok(()) => {
+			super::flash::add(&r, "You have set a new password.", "success")?;
+			Ok(super::response::redirect_static(&r, "login_get")?)
+		}
+		Err(ref e) if super::error::check_diesel_not_found_down(e) => {
+			// Create an error, add it to the list and render the template
+			let errors = validation_errors! {
+				"invalid" => "Invalid username or recovery code",
+			};
+			Ok(super::response::render_form_errors(
+				&r,
+				"recover.html",
+				&errors,
+				None,
+			)?)
+		}
+		Err(e) => Err(e.into()),
+	}
+}
+
+#[actix_web::get("/recovery-codes/")]
+pub(crate) async fn recovery_codes_get(
+	r: actix_web::HttpRequest,
+) -> Result<actix_web::HttpResponse, RecoveryCodesGetError> {
+	super::auth::validate_logged_in(&r)?;
+	Ok(super::response::render(
+		&r,
+		"new-recovery-codes.html",
+		None,
+		actix_web::http::StatusCode::OK,
+		true,
+	)?)
+}
+
+#[actix_web::post("/recovery-codes/")]
+pub(crate) async fn recovery_codes_post(
+	s: actix_web::web::Data<crate::state::State>,
+	r: actix_web::HttpRequest,
+	form: actix_web::web::Form<super::forms::RecoveryCodes>,
+) -> Result<actix_web::HttpResponse, RecoveryCodesPostError> {
+	use validator::ValidateArgs as _;
+	super::auth::validate_logged_in(&r)?;
+
+	// Get user and validate the form
+	//
+	// We can use `Option::unwrap` because of `super::auth::validate_logged_in`
+	let user = super::auth::get_current_user(&r)?.unwrap();
+	if let Err(ref errors) = form.validate_args(&r) {
+		return Ok(super::response::render_form_errors(
+			&r,
+			"new-recovery-codes.html",
+			errors,
+			None,
+		)?);
+	}
+
+	// Check the password
+	if !s
+		.db()
+		.check_user_password(&user, &form.password)
+		.await
+		.map_err(RecoveryCodesPostError::CheckUserPassword)?
+	{
+		let errors = validation_errors! {"invalid" => "Invalid password"};
+		return Ok(super::response::render_form_errors(
+			&r,
+			"new-recovery-codes.html",
+			&errors,
+			None,
+		)?);
+	}
+	let recovery_codes = s.db().replace_recovery_codes(&user).await?;
+	super::flash::add(
+		&r,
+		"You have generated new recovery codes, the old ones are no longer \
+		 valid.",
+		"success",
+	)?;
+
+	// Show the recovery codes once, they are not stored in plain text
+	let context = context! {"recovery_codes" => &recovery_codes};
+	Ok(super::response::render(
+		&r,
+		"recovery-codes.html",
+		Some(context),
+		actix_web::http::StatusCode::OK,
+		false,
+	)?)
+}
+
 #[actix_web::get("/register/")]
 pub(crate) async fn register_get(
 	r: actix_web::HttpRequest,
@@ -572,11 +815,23 @@ pub(crate) async fn register_post(
 	let private_key = if let Some(k) = form.get_private_key() {
 		k
 	} else {
-		openssl::rsa::Rsa::generate(crate::consts::RSA_KEY_SIZE)?
+		common::crypto::PrivateKey::generate(s.config().key_type())?
 	};
-	s.db().create_user(&form.username, &form.password, &private_key).await?;
+	let recovery_codes = s
+		.db()
+		.create_user(&form.username, &form.password, &private_key)
+		.await?;
 	super::flash::add(&r, "You have registered.", "success")?;
-	Ok(super::response::redirect_static(&r, "login_get")?)
+
+	// Show the recovery codes once, they are not stored in plain text
+	let context = context! {"recovery_codes" => &recovery_codes};
+	Ok(super::response::render(
+		&r,
+		"recovery-codes.html",
+		Some(context),
+		actix_web::http::StatusCode::OK,
+		false,
+	)?)
 }
 
 #[actix_web::get("/emails/send/")]
@@ -651,33 +906,40 @@ pub(crate) async fn send_email_post(
 	}
 
 	// Get public and private keys
-	let recipient_public_key = form.get_recipient_public_key();
+	let recipient_public_key =
+		std::sync::Arc::new(form.get_recipient_public_key());
 	let private_key = s
 		.db()
 		.get_user_private_key(&user)
 		.await
 		.map_err(SendEmailPostError::GetUserPrivateKey)?;
 
-	// Make new email and get it's size
-	let user_username = user.username().to_owned();
-	let email_bytes = actix_web::web::block(move || {
-		// Make and serialize an encrypted email package
-		let d = form.into_email_data(user_username);
-		let mut e = common::email::Email::new(&recipient_public_key, d)?;
-		e.generate_proof_of_work();
-		e.sign(&private_key)?;
-		let rv = bincode::serialize(&e)?;
-		Ok::<_, SendEmailPostError>(rv)
-	})
-	.await??;
+	// Make new email with a proof-of-work, showing the progress
+	let _progress_guard = s.progresses().track(user.id());
+	let d = form.into_email_data(user.username().to_owned());
+	let mut e =
+		make_email(&s, &user, &nodes, recipient_public_key.clone(), d).await?;
+
+	// Take a prekey of the recipient only now, so it's not wasted if the
+	// proof-of-work fails, then encrypt the email for the recipient and sign
+	// it
+	let recipient_prekey = super::request_node::get_recipient_prekey(
+		&s,
+		&nodes,
+		&recipient_public_key,
+	)
+	.await
+	.map_err(SendEmailPostError::GetRecipientPrekey)?;
+	e.encrypt_session(&recipient_public_key, recipient_prekey.as_ref())?;
+	e.sign(&private_key)?;
+	let email_bytes = e.to_bytes()?;
 
 	// Make package with email bytes and validate it's size
 	let package = common::package::Package::new(
-		None,
 		common::package::Action::SendEmail,
 		email_bytes,
 	);
-	if package.is_too_big()? {
+	if package.is_too_big(s.config().limits().common().package_max_size())? {
 		super::flash::add(&r, "Your email is too big.", "danger")
 			.map_err(SendEmailPostError::EmailIsTooBigFlash)?;
 		return Ok(super::response::render(
@@ -689,14 +951,18 @@ pub(crate) async fn send_email_post(
 		)?);
 	}
 
-	// Send the package with the encrypted email to each node and flash the
-	// message
+	// Send the package with the encrypted email to each node, showing the
+	// progress, and flash the message
+	let (progresses, user_id) = (s.progresses().clone(), user.id());
+	let limits = *s.config().limits().common();
 	let nodes_len = nodes.len();
 	match common::helpers::send_email_to_nodes(
 		package,
 		nodes,
 		nodes_len,
 		s.config().proxy(),
+		limits,
+		move |p| progresses.set_transfer(user_id, "Sending the email:", p),
 	)
 	.await?
 	{
@@ -709,6 +975,58 @@ pub(crate) async fn send_email_post(
 	Ok(super::response::redirect_static(&r, "emails")?)
 }
 
+/// Makes the email with a proof-of-work of the strictest difficulty of the
+/// `nodes`, generated on all available threads. The progress with the
+/// estimated time remaining is shown to the user. The generation is cancelled
+/// if the request is dropped.
+async fn make_email(
+	s: &actix_web::web::Data<crate::state::State>,
+	user: &crate::raw_models::User,
+	nodes: &[crate::raw_models::Node],
+	recipient_public_key: std::sync::Arc<common::crypto::PublicKey>,
+	data: common::email::Data,
+) -> Result<common::email::Email, SendEmailPostError> {
+	let scheme = s.config().limits().proof_of_work_scheme();
+	let difficulty =
+		super::request_node::get_strictest_proof_of_work_difficulty(s, nodes)
+			.await?
+			.get(scheme);
+
+	let canceller = common::email::ProofOfWorkCanceller::default();
+	let _cancel_guard = canceller.cancel_on_drop();
+	let (progresses, user_id) = (s.progresses().clone(), user.id());
+	actix_web::web::block(move || {
+		let mut e = common::email::Email::new(&recipient_public_key, data)?;
+		e.generate_proof_of_work(
+			scheme,
+			difficulty,
+			std::thread::available_parallelism()
+				.unwrap_or(std::num::NonZeroUsize::MIN),
+			&canceller,
+			|p| {
+				common::debug!(
+					"Proof-of-work: {} attempts in {:?}, {:?} remaining.",
+					p.attempts(),
+					p.elapsed(),
+					p.estimated_remaining()
+				);
+				let message = match p.estimated_remaining() {
+					Some(r) => format!(
+						"Generating a proof-of-work: {} attempts, about {} \
+						 seconds remaining.",
+						p.attempts(),
+						r.as_secs()
+					),
+					None => "Generating a proof-of-work...".to_owned(),
+				};
+				progresses.set(user_id, message, None);
+			},
+		)?;
+		Ok(e)
+	})
+	.await?
+}
+
 #[actix_web::post("/switch-f2f/")]
 pub(crate) async fn switch_f2f(
 	s: actix_web::web::Data<crate::state::State>,
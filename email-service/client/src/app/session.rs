@@ -0,0 +1,210 @@
+use {
+	actix_session::storage::{
+		CookieSessionStore, LoadError, RedisSessionStore, SaveError,
+		SessionKey, SessionStore, UpdateError,
+	},
+	actix_web::cookie::time::Duration,
+	std::{collections::HashMap, convert::TryInto as _},
+};
+
+type SessionState = HashMap<String, String>;
+
+/// [`SessionStore`] of the `config::SessionBackend`.
+///
+/// Clones share the same sessions, so one store can be used by all workers.
+#[derive(Clone)]
+pub(crate) enum Store {
+	Cookie,
+	Memory(MemorySessionStore),
+	Redis(RedisSessionStore),
+}
+
+impl Store {
+	pub(crate) async fn new(
+		backend: &crate::config::SessionBackend,
+	) -> anyhow::Result<Self> {
+		use crate::config::SessionBackend;
+		Ok(match backend {
+			SessionBackend::Cookie { .. } => Self::Cookie,
+			SessionBackend::Memory => {
+				Self::Memory(MemorySessionStore::default())
+			}
+			SessionBackend::Redis(url) => {
+				Self::Redis(RedisSessionStore::new(url.as_str()).await?)
+			}
+		})
+	}
+}
+
+/// Calls the `method` of the selected store.
+macro_rules! dispatch {
+	($self:ident.$method:ident($($arg:expr),*)) => {
+		match $self {
+			Self::Cookie => {
+				CookieSessionStore::default().$method($($arg),*).await
+			}
+			Self::Memory(s) => s.$method($($arg),*).await,
+			Self::Redis(s) => s.$method($($arg),*).await,
+		}
+	};
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for Store {
+	async fn load(
+		&self,
+		session_key: &SessionKey,
+	) -> Result<Option<SessionState>, LoadError> {
+		dispatch!(self.load(session_key))
+	}
+
+	async fn save(
+		&self,
+		session_state: SessionState,
+		ttl: &Duration,
+	) -> Result<SessionKey, SaveError> {
+		dispatch!(self.save(session_state, ttl))
+	}
+
+	async fn update(
+		&self,
+		session_key: SessionKey,
+		session_state: SessionState,
+		ttl: &Duration,
+	) -> Result<SessionKey, UpdateError> {
+		dispatch!(self.update(session_key, session_state, ttl))
+	}
+
+	async fn update_ttl(
+		&self,
+		session_key: &SessionKey,
+		ttl: &Duration,
+	) -> anyhow::Result<()> {
+		dispatch!(self.update_ttl(session_key, ttl))
+	}
+
+	async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+		dispatch!(self.delete(session_key))
+	}
+}
+
+/// [`SessionStore`] that keeps session states in the memory of the client,
+/// so the session cookie carries only an opaque session key. Session states
+/// are lost on restart, which logs all users out. It can also stand in for
+/// Redis in tests.
+///
+/// Clones share the same sessions, so one store can be used by all workers.
+#[derive(Clone, Default)]
+pub(crate) struct MemorySessionStore(
+	std::sync::Arc<std::sync::Mutex<HashMap<String, Entry>>>,
+);
+
+struct Entry {
+	state: SessionState,
+	expires_at: std::time::Instant,
+}
+
+impl MemorySessionStore {
+	fn sessions(
+		&self,
+	) -> anyhow::Result<std::sync::MutexGuard<'_, HashMap<String, Entry>>> {
+		self.0.lock().map_err(|_| anyhow::anyhow!("Poisoned sessions."))
+	}
+
+	/// Inserts the `state` under a new session key, removing expired sessions
+	/// meanwhile.
+	fn insert(
+		&self,
+		state: SessionState,
+		ttl: &Duration,
+	) -> anyhow::Result<SessionKey> {
+		let key = generate_session_key()?;
+		let now = std::time::Instant::now();
+		let mut sessions = self.sessions()?;
+		sessions.retain(|_, e| e.expires_at > now);
+		sessions.insert(key.as_ref().to_owned(), Entry {
+			state,
+			expires_at: expires_at(ttl),
+		});
+		Ok(key)
+	}
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for MemorySessionStore {
+	async fn load(
+		&self,
+		session_key: &SessionKey,
+	) -> Result<Option<SessionState>, LoadError> {
+		let mut sessions = self.sessions().map_err(LoadError::Other)?;
+		match sessions.get(session_key.as_ref()) {
+			Some(e) if e.expires_at > std::time::Instant::now() => {
+				Ok(Some(e.state.clone()))
+			}
+			Some(_) => {
+				sessions.remove(session_key.as_ref());
+				Ok(None)
+			}
+			None => Ok(None),
+		}
+	}
+
+	async fn save(
+		&self,
+		session_state: SessionState,
+		ttl: &Duration,
+	) -> Result<SessionKey, SaveError> {
+		self.insert(session_state, ttl).map_err(SaveError::Other)
+	}
+
+	async fn update(
+		&self,
+		session_key: SessionKey,
+		session_state: SessionState,
+		ttl: &Duration,
+	) -> Result<SessionKey, UpdateError> {
+		let mut sessions = self.sessions().map_err(UpdateError::Other)?;
+		if let Some(e) = sessions.get_mut(session_key.as_ref()) {
+			if e.expires_at > std::time::Instant::now() {
+				*e = Entry {
+					state: session_state,
+					expires_at: expires_at(ttl),
+				};
+				return Ok(session_key);
+			}
+		}
+
+		// The session has expired meanwhile, so start a new one
+		drop(sessions);
+		self.insert(session_state, ttl).map_err(UpdateError::Other)
+	}
+
+	async fn update_ttl(
+		&self,
+		session_key: &SessionKey,
+		ttl: &Duration,
+	) -> anyhow::Result<()> {
+		if let Some(e) = self.sessions()?.get_mut(session_key.as_ref()) {
+			if e.expires_at > std::time::Instant::now() {
+				e.expires_at = expires_at(ttl);
+			}
+		}
+		Ok(())
+	}
+
+	async fn delete(&self, session_key: &SessionKey) -> anyhow::Result<()> {
+		self.sessions()?.remove(session_key.as_ref());
+		Ok(())
+	}
+}
+
+/// Generates a random session key of 64 characters, see
+/// <https://cheatsheetseries.owasp.org/cheatsheets/Session_Management_Cheat_Sheet.html#session-id-entropy>.
+fn generate_session_key() -> anyhow::Result<SessionKey> {
+	let bytes = common::crypto::generate_random_bytes(Some(48))?;
+	Ok(base64::encode(bytes).try_into()?)
+}
+
+fn expires_at(ttl: &Duration) -> std::time::Instant {
+	std::time::Instant::now() + *ttl
+}
@@ -7,6 +7,13 @@ pub(crate) struct Config {
 	dark_theme: bool,
 	proxy: Option<std::net::SocketAddr>,
 	secret_key: String,
+	/// The type of keys generated for new accounts.
+	#[serde(default)]
+	key_type: common::crypto::KeyType,
+	#[serde(default)]
+	limits: Limits,
+	#[serde(default)]
+	session_backend: SessionBackend,
 }
 
 impl Config {
@@ -18,6 +25,12 @@ impl Config {
 
 	common::accessor!(& secret_key -> &str);
 
+	common::accessor!(copy key_type -> common::crypto::KeyType);
+
+	common::accessor!(& limits -> &Limits);
+
+	common::accessor!(& session_backend -> &SessionBackend);
+
 	pub async fn load() -> Result<Self> {
 		common::debug!("Loading config...");
 
@@ -31,6 +44,90 @@ impl Config {
 			config.secret_key.len() >= Self::SECRET_KEY_MIN_LENGTH,
 			"The length of the secret key must be >= 64.",
 		);
+		config.limits.validate().context("Invalid limits.")?;
+		if let SessionBackend::Cookie { store_data_key_in_browser } =
+			config.session_backend
+		{
+			anyhow::ensure!(
+				store_data_key_in_browser,
+				"The cookie session backend keeps data keys in browsers, set \
+				 `store_data_key_in_browser` to accept it.",
+			);
+		}
+		anyhow::ensure!(
+			config.key_type.is_supported(),
+			"The key type requires OpenSSL 3.5+ and the `ml-kem` feature.",
+		);
 		Ok(config)
 	}
 }
+
+/// Where session states are stored, see `app::session::Store`.
+#[derive(Default, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[non_exhaustive]
+pub(crate) enum SessionBackend {
+	/// In encrypted cookies, like `{"cookie": {"store_data_key_in_browser":
+	/// true}}`. The session state holds the data key of the user, so it is
+	/// kept by the browser and sent with every request, and a stolen cookie
+	/// stays valid until the session expires even after logout. It is refused
+	/// unless `store_data_key_in_browser` accepts that, use `Memory` or
+	/// `Redis` otherwise.
+	Cookie { store_data_key_in_browser: bool },
+	/// In the memory of the client, so sessions are lost on restart and can't
+	/// be shared by several clients.
+	#[default]
+	Memory,
+	/// In a Redis server with the URL, like `redis://127.0.0.1:6379`, that
+	/// can be shared by several clients behind one load balancer.
+	Redis(String),
+}
+
+/// [`common::config::Limits`] with the client ones.
+#[derive(serde::Deserialize)]
+#[serde(default)]
+pub(crate) struct Limits {
+	#[serde(flatten)]
+	common: common::config::Limits,
+	emails_per_page: u64,
+	new_emails_from_node_limit: u8,
+	proof_of_work_scheme: common::email::ProofOfWorkScheme,
+}
+
+impl Limits {
+	common::accessor!(& common -> &common::config::Limits);
+
+	common::accessor!(copy emails_per_page -> u64);
+
+	common::accessor!(copy new_emails_from_node_limit -> u8);
+
+	common::accessor!(
+		copy proof_of_work_scheme -> common::email::ProofOfWorkScheme
+	);
+
+	fn validate(&self) -> Result<()> {
+		self.common.validate()?;
+		anyhow::ensure!(
+			(1..=i64::MAX as u64).contains(&self.emails_per_page),
+			"`emails_per_page` must be > 0 and <= {}.",
+			i64::MAX,
+		);
+		anyhow::ensure!(
+			self.new_emails_from_node_limit > 0,
+			"`new_emails_from_node_limit` must be > 0.",
+		);
+		Ok(())
+	}
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			common: common::config::Limits::default(),
+			emails_per_page: crate::consts::EMAILS_PER_PAGE,
+			new_emails_from_node_limit:
+				crate::consts::NEW_EMAILS_FROM_NODE_LIMIT,
+			proof_of_work_scheme: common::email::ProofOfWorkScheme::default(),
+		}
+	}
+}
@@ -12,8 +12,16 @@ pub(crate) const CSRF_COOKIE_NAME: &str = "csrf-token";
 pub(crate) const EMAILS_PER_PAGE: u64 = 4;
 common::const_assert!(EMAILS_PER_PAGE < i64::MAX as u64);
 
+pub(crate) const EMAIL_IDS_LIMIT: i64 = 256;
+pub(crate) const EMAILS_BATCH_LIMIT: usize = 16;
+pub(crate) const EMAILS_BATCH_MAX_SIZE: u64 = 1024 * 1024; // 1 MiB
 pub(crate) const NEW_EMAILS_FROM_NODE_LIMIT: u8 = 4;
-pub(crate) const RSA_KEY_SIZE: u32 = 2048;
+pub(crate) const PREKEYS_PER_NODE: i64 = 16;
+pub(crate) const RECOVERY_CODES_PER_USER: usize = 8;
+pub(crate) const RECOVERY_CODE_LENGTH: usize = 10; // bytes
+pub(crate) const SESSION_IDLE_TIMEOUT: u64 = 30 * 60; // 30 minutes
+common::const_assert!(SESSION_IDLE_TIMEOUT < i64::MAX as u64);
+pub(crate) const SESSION_ABSOLUTE_TIMEOUT: u64 = 12 * 60 * 60; // 12 hours
 
 pub(crate) const TERA_DIR_STR: &str =
 	concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*");
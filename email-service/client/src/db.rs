@@ -1,5 +1,30 @@
 use anyhow::{Context as _, Result};
 
+diesel::sql_function! {
+	/// Returns `count` bytes of `bytes` from the 1-based `start`.
+	fn substr<T: diesel::sql_types::SingleValue>(
+		bytes: T,
+		start: diesel::sql_types::Integer,
+		count: diesel::sql_types::Integer,
+	) -> T;
+}
+
+/// See [`outdated`].
+const CURRENT_ENVELOPE_HEADER_SIZE: i32 = 5;
+const CURRENT_ENVELOPE_HEADER: [u8; CURRENT_ENVELOPE_HEADER_SIZE as usize] =
+	common::crypto::Envelope::CURRENT.header();
+
+/// Filters rows where the encrypted `$column` is not in the
+/// [current envelope](common::crypto::Envelope::CURRENT), the same way as
+/// [`common::crypto::Envelope::is_current`], so they are re-encrypted. Rows
+/// where it is `NULL` are filtered out.
+macro_rules! outdated {
+	($column:expr) => {
+		crate::db::substr($column, 1, crate::db::CURRENT_ENVELOPE_HEADER_SIZE)
+			.ne(crate::db::CURRENT_ENVELOPE_HEADER.to_vec())
+	};
+}
+
 pub(crate) struct Db(common::helpers::DbPool);
 
 impl Db {
@@ -13,28 +38,45 @@ impl Db {
 
 	fn decrypt_email(
 		email: &crate::models::Email,
-		cipher: &common::crypto::AesCipher,
+		user: &crate::raw_models::User,
 	) -> Result<crate::raw_models::Email> {
-		let sender_public_key = cipher
-			.decrypt_base64(&email.encrypted_sender_public_key_pem)
+		let sender_public_key = user
+			.decrypt_column(
+				"emails.encrypted_sender_public_key_pem",
+				email.proof_of_work.as_bytes(),
+				&email.encrypted_sender_public_key_pem,
+			)
+			.map(base64::encode)
 			.context("Failed to decrypt base64 sender public key.")?;
-		let data_bytes = cipher
-			.decrypt(&email.encrypted_data_bytes)
+		let data_bytes = user
+			.decrypt_column(
+				"emails.encrypted_data_bytes",
+				email.proof_of_work.as_bytes(),
+				&email.encrypted_data_bytes,
+			)
 			.context("Failed to decrypt data bytes.")?;
-		let data = bincode::deserialize(&data_bytes)
+		let data = common::helpers::deserialize(&data_bytes)
 			.context("Failed to deserialize data bytes.")?;
 		Ok(crate::raw_models::Email::new(email.id, sender_public_key, data))
 	}
 
 	fn decrypt_friend(
 		friend: &crate::models::Friend,
-		cipher: &common::crypto::AesCipher,
+		user: &crate::raw_models::User,
 	) -> Result<crate::raw_models::Friend> {
-		let username = cipher
-			.decrypt_string(&friend.encrypted_username)
+		let username = user
+			.decrypt_column_string(
+				"friends.encrypted_username",
+				&friend.username_hash,
+				&friend.encrypted_username,
+			)
 			.context("Failed to decrypt a username.")?;
-		let public_key = cipher
-			.decrypt_string(&friend.encrypted_public_key_pem_base64)
+		let public_key = user
+			.decrypt_column_string(
+				"friends.encrypted_public_key_pem_base64",
+				&friend.username_hash,
+				&friend.encrypted_public_key_pem_base64,
+			)
 			.context("Failed to decrypt a public key.")?;
 		Ok(crate::raw_models::Friend::new(friend.id, username, public_key))
 	}
@@ -59,18 +101,137 @@ impl Db {
 			.await?;
 
 		// Check the password
-		let password_hash =
-			common::crypto::hash_with_salt(&password, &db_user.salt);
-		if password_hash[..] != db_user.password_hash {
-			return Err(diesel::result::Error::NotFound.into());
+		let password_keys = if let Some(p) = db_user.argon2_params()? {
+			let password_keys =
+				Self::derive_password_keys(&password, &db_user.salt, p)
+					.await?;
+			if password_keys.verifier() != db_user.password_hash {
+				return Err(diesel::result::Error::NotFound.into());
+			}
+			password_keys
+		} else {
+			// Check the password hash from before Argon2id and upgrade it
+			let password_hash =
+				common::crypto::hash_with_salt(&password, &db_user.salt);
+			if password_hash[..] != db_user.password_hash {
+				return Err(diesel::result::Error::NotFound.into());
+			}
+			let params = common::crypto::Argon2Params::default();
+			let password_keys =
+				Self::derive_password_keys(&password, &db_user.salt, params)
+					.await?;
+			let (memory_cost, time_cost, parallelism) =
+				crate::models::argon2_columns(params)?;
+			diesel::update(table.find(db_user.id))
+				.set((
+					dsl::password_hash.eq(password_keys.verifier().to_vec()),
+					dsl::argon2_memory_cost.eq(memory_cost),
+					dsl::argon2_time_cost.eq(time_cost),
+					dsl::argon2_parallelism.eq(parallelism),
+				))
+				.execute(&mut connection)
+				.await?;
+			password_keys
+		};
+		let data_key =
+			Self::get_user_data_key(&mut connection, &db_user, &password_keys)
+				.await?;
+		Ok(crate::raw_models::User::new(
+			db_user.id,
+			username,
+			data_key,
+			db_user.session_generation,
+		)
+		.with_legacy_keys(&password, password_keys.data_key().to_vec()))
+	}
+
+	/// Checks the `password` of the `user`, since it is not stored in the
+	/// session.
+	pub(crate) async fn check_user_password(
+		&self,
+		user: &crate::raw_models::User,
+		password: &str,
+	) -> Result<bool> {
+		use {
+			crate::schema::users::table, diesel::QueryDsl as _,
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		let db_user: crate::models::User =
+			table.find(user.id()).first(&mut connection).await?;
+		if let Some(p) = db_user.argon2_params()? {
+			let password_keys =
+				Self::derive_password_keys(password, &db_user.salt, p).await?;
+			Ok(password_keys.verifier() == db_user.password_hash)
+		} else {
+			let password_hash =
+				common::crypto::hash_with_salt(password, &db_user.salt);
+			Ok(password_hash[..] == db_user.password_hash)
 		}
-		Ok(crate::raw_models::User::new(db_user.id, username, password))
+	}
+
+	/// Unwraps the data key of the `db_user`, or generates one for users from
+	/// before it was added. The data is re-encrypted with it by
+	/// `reencrypt_user_data`.
+	async fn get_user_data_key(
+		connection: &mut diesel_async::AsyncPgConnection,
+		db_user: &crate::models::User,
+		password_keys: &common::crypto::PasswordKeys,
+	) -> Result<Vec<u8>> {
+		use {
+			crate::schema::users::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		if let Some(k) = db_user.unwrap_data_key(password_keys)? {
+			return Ok(k);
+		}
+		let data_key = common::crypto::generate_random_bytes(None)
+			.context("Failed to generate a data key.")?;
+		let wrapped_data_key = crate::models::wrap_data_key(
+			"users.wrapped_data_key",
+			&db_user.username_hash,
+			password_keys,
+			&data_key,
+		)?;
+		let updated = diesel::update(
+			table.find(db_user.id).filter(dsl::wrapped_data_key.is_null()),
+		)
+		.set(dsl::wrapped_data_key.eq(wrapped_data_key))
+		.execute(connection)
+		.await?;
+		if updated == 0 {
+			// Another login has generated one meanwhile
+			let db_user: crate::models::User =
+				table.find(db_user.id).first(connection).await?;
+			return db_user
+				.unwrap_data_key(password_keys)?
+				.context("Failed to get the data key.");
+		}
+		Ok(data_key)
+	}
+
+	/// Derives the password keys in a blocking thread, since it is slow by
+	/// design.
+	async fn derive_password_keys(
+		password: &str,
+		salt: &[u8],
+		params: common::crypto::Argon2Params,
+	) -> Result<common::crypto::PasswordKeys> {
+		let (password, salt) = (password.to_owned(), salt.to_owned());
+		let password_keys = tokio::task::spawn_blocking(move || {
+			common::crypto::derive_password_keys(password, &salt, params)
+		})
+		.await??;
+		Ok(password_keys)
 	}
 
 	pub(crate) async fn get_user_private_key(
 		&self,
 		user: &crate::raw_models::User,
-	) -> Result<openssl::rsa::Rsa<openssl::pkey::Private>> {
+	) -> Result<common::crypto::PrivateKey> {
 		use {
 			crate::schema::users::{dsl, table},
 			diesel::QueryDsl as _,
@@ -84,9 +245,13 @@ impl Db {
 		let encrypted_private_key: Vec<u8> =
 			query.first(&mut connection).await?;
 
-		// Make a cipher and decrypt the private key
-		let pem = user.make_aes_cipher().decrypt(&encrypted_private_key)?;
-		let private_key = openssl::rsa::Rsa::private_key_from_pem(&pem)?;
+		// Decrypt the private key
+		let pem = user.decrypt_column(
+			"users.encrypted_private_key_pem",
+			&common::crypto::hash(user.username()),
+			&encrypted_private_key,
+		)?;
+		let private_key = common::crypto::PrivateKey::from_pem(&pem)?;
 		Ok(private_key)
 	}
 
@@ -115,7 +280,9 @@ impl Db {
 		&self,
 		user: &crate::raw_models::User,
 		current_page: std::num::NonZeroU64,
-	) -> Result<crate::app::pagination::Pagination<crate::raw_models::Email>> {
+		per_page: u64,
+	) -> Result<crate::app::pagination::Pagination<crate::raw_models::Email>>
+	{
 		use {
 			crate::schema::emails::{dsl, table},
 			diesel::{ExpressionMethods as _, QueryDsl as _},
@@ -126,28 +293,26 @@ impl Db {
 		let user_filter = table.filter(dsl::user_id.eq(user.id()));
 
 		// Get database emails
-		let offset =
-			(u64::from(current_page) - 1) * (crate::consts::EMAILS_PER_PAGE);
+		let offset = (u64::from(current_page) - 1) * per_page;
 		#[allow(clippy::cast_possible_wrap)]
 		let db_emails = user_filter
 			.offset(offset as i64)
-			.limit(crate::consts::EMAILS_PER_PAGE as i64)
+			.limit(per_page as i64)
 			.order(dsl::created_at.desc())
 			.load::<crate::models::Email>(&mut connection)
 			.await?;
 
-		// Make a cipher and decrypt database emails
-		let cipher = user.make_aes_cipher();
+		// Decrypt database emails
 		let mut raw_emails = Vec::with_capacity(db_emails.len());
 		for db_email in db_emails {
-			raw_emails.push(Self::decrypt_email(&db_email, &cipher)?);
+			raw_emails.push(Self::decrypt_email(&db_email, user)?);
 		}
 
 		// Get pages count and make pagination
 		let count: i64 =
 			user_filter.count().get_result(&mut connection).await?;
 		#[allow(clippy::cast_sign_loss)]
-		let pages = (count as u64).div_ceil(crate::consts::EMAILS_PER_PAGE);
+		let pages = (count as u64).div_ceil(per_page);
 		Ok(crate::app::pagination::Pagination::new(
 			current_page,
 			pages,
@@ -173,9 +338,8 @@ impl Db {
 			.find(id)
 			.first(&mut connection)
 			.await?;
-		// Make a cipher and decrypt the database email
-		let cipher = user.make_aes_cipher();
-		let raw_email = Self::decrypt_email(&db_email, &cipher)?;
+		// Decrypt the database email
+		let raw_email = Self::decrypt_email(&db_email, user)?;
 		Ok(raw_email)
 	}
 
@@ -214,23 +378,320 @@ impl Db {
 		Ok(exists)
 	}
 
+	/// Checks that the user of a session still exists and the session is of
+	/// its current generation, so users deleted in any way and other sessions
+	/// of users who changed their password are logged out, see
+	/// `app::auth::check_current_user`.
+	pub(crate) async fn check_user_session(
+		&self,
+		user: &crate::raw_models::User,
+	) -> Result<bool> {
+		use {
+			crate::schema::users::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+		let mut connection = self.0.get().await?;
+		let filter = table
+			.find(user.id())
+			.filter(dsl::session_generation.eq(user.session_generation()));
+		let exists = diesel::select(diesel::dsl::exists(filter))
+			.get_result(&mut connection)
+			.await?;
+		Ok(exists)
+	}
+
+	/// Creates a user and its recovery codes, returning them.
 	pub(crate) async fn create_user(
 		&self,
 		username: &str,
 		password: &str,
-		private_key: &openssl::rsa::Rsa<openssl::pkey::Private>,
-	) -> Result<()> {
-		use {crate::schema::users::table, diesel_async::RunQueryDsl as _};
+		private_key: &common::crypto::PrivateKey,
+	) -> Result<Vec<String>> {
+		use {
+			crate::schema::{recovery_codes, users},
+			diesel_async::{
+				scoped_futures::ScopedFutureExt as _, AsyncConnection as _,
+				RunQueryDsl as _,
+			},
+		};
 		debug_assert!(!self.check_user_exists(username).await?);
 
-		let new_user =
-			crate::models::NewUser::new(username, password, private_key)?;
+		let salt = common::crypto::generate_random_bytes(None)
+			.context("Failed to generate a salt.")?;
+		let params = common::crypto::Argon2Params::default();
+		let password_keys =
+			Self::derive_password_keys(password, &salt, params).await?;
+		let data_key = common::crypto::generate_random_bytes(None)
+			.context("Failed to generate a data key.")?;
+
+		let (codes, code_keys) =
+			Self::generate_recovery_codes(&salt, params).await?;
+
+		let new_user = crate::models::NewUser::new(
+			username,
+			salt,
+			params,
+			&password_keys,
+			&data_key,
+			private_key,
+		)?;
 		let mut connection = self.0.get().await?;
-		diesel::insert_into(table)
-			.values(new_user)
-			.execute(&mut connection)
+		connection
+			.transaction::<_, anyhow::Error, _>(|c| {
+				async move {
+					let user_id: i32 = diesel::insert_into(users::table)
+						.values(new_user)
+						.returning(users::dsl::id)
+						.get_result(c)
+						.await?;
+					let new_codes = code_keys
+						.iter()
+						.map(|k| {
+							crate::models::NewRecoveryCode::new(
+								user_id, params, k, &data_key,
+							)
+						})
+						.collect::<Result<Vec<_>>>()?;
+					diesel::insert_into(recovery_codes::table)
+						.values(new_codes)
+						.execute(c)
+						.await?;
+					Ok(())
+				}
+				.scope_boxed()
+			})
 			.await?;
-		Ok(())
+		Ok(codes)
+	}
+
+	/// Sets the `new_password` of the user with the `username` by one of its
+	/// recovery codes, that is deleted after, and logs out its sessions.
+	/// Returns
+	/// [`diesel::result::Error::NotFound`] if the user or the code is not
+	/// found.
+	pub(crate) async fn recover_user(
+		&self,
+		username: &str,
+		code: &str,
+		new_password: &str,
+	) -> Result<()> {
+		use {
+			crate::schema::{recovery_codes, users},
+			diesel::{
+				ExpressionMethods as _, OptionalExtension as _, QueryDsl as _,
+			},
+			diesel_async::{
+				scoped_futures::ScopedFutureExt as _, AsyncConnection as _,
+				RunQueryDsl as _,
+			},
+		};
+
+		// Find user and its recovery codes
+		let username_hash = common::crypto::hash(username).to_vec();
+		let mut connection = self.0.get().await?;
+		let db_user: Option<crate::models::User> = users::table
+			.filter(users::dsl::username_hash.eq(username_hash))
+			.first(&mut connection)
+			.await
+			.optional()?;
+		let db_codes = match db_user {
+			Some(ref u) => {
+				recovery_codes::table
+					.filter(recovery_codes::dsl::user_id.eq(u.id))
+					.load::<crate::models::RecoveryCode>(&mut connection)
+					.await?
+			}
+			None => Vec::new(),
+		};
+		let code = Self::normalize_recovery_code(code);
+		let db_user = match db_user {
+			Some(u) if !db_codes.is_empty() => u,
+			_ => {
+				// Derive the keys anyway, so unknown usernames can't be told
+				// from invalid codes by the response time
+				let params = common::crypto::Argon2Params::default();
+				Self::derive_password_keys(&code, &[0; 32], params).await?;
+				return Err(diesel::result::Error::NotFound.into());
+			}
+		};
+
+		// Check the code, deriving its keys once per params
+		let mut code_keys = None;
+		let mut found = None;
+		for c in db_codes {
+			let params = c.argon2_params()?;
+			let keys = match code_keys {
+				Some((p, ref k)) if p == params => k,
+				_ => {
+					let k = Self::derive_password_keys(
+						&code,
+						&db_user.salt,
+						params,
+					)
+					.await?;
+					&code_keys.insert((params, k)).1
+				}
+			};
+			if keys.verifier() == c.code_hash {
+				found = Some(c.unwrap_data_key(keys).map(|k| (c.id, k))?);
+				break;
+			}
+		}
+		let (code_id, data_key) =
+			found.ok_or(diesel::result::Error::NotFound)?;
+
+		// Rewrap the data key by the new password keys
+		let params = common::crypto::Argon2Params::default();
+		let password_keys =
+			Self::derive_password_keys(new_password, &db_user.salt, params)
+				.await?;
+		let (memory_cost, time_cost, parallelism) =
+			crate::models::argon2_columns(params)?;
+		let wrapped_data_key = crate::models::wrap_data_key(
+			"users.wrapped_data_key",
+			&db_user.username_hash,
+			&password_keys,
+			&data_key,
+		)?;
+		connection
+			.transaction::<_, anyhow::Error, _>(|c| {
+				async move {
+					// The code is one-time, so another recovery may have
+					// used it meanwhile
+					let deleted =
+						diesel::delete(recovery_codes::table.find(code_id))
+							.execute(c)
+							.await?;
+					if deleted == 0 {
+						return Err(diesel::result::Error::NotFound.into());
+					}
+					diesel::update(users::table.find(db_user.id))
+						.set((
+							users::dsl::password_hash
+								.eq(password_keys.verifier().to_vec()),
+							users::dsl::argon2_memory_cost.eq(memory_cost),
+							users::dsl::argon2_time_cost.eq(time_cost),
+							users::dsl::argon2_parallelism.eq(parallelism),
+							users::dsl::wrapped_data_key.eq(wrapped_data_key),
+							users::dsl::session_generation
+								.eq(users::dsl::session_generation + 1),
+						))
+						.execute(c)
+						.await?;
+					Ok(())
+				}
+				.scope_boxed()
+			})
+			.await
+	}
+
+	/// Replaces the recovery codes of the `user` by new ones, returning them.
+	/// It is also the way to get recovery codes for users registered before
+	/// they were added.
+	pub(crate) async fn replace_recovery_codes(
+		&self,
+		user: &crate::raw_models::User,
+	) -> Result<Vec<String>> {
+		use {
+			crate::schema::{recovery_codes, users},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::{
+				scoped_futures::ScopedFutureExt as _, AsyncConnection as _,
+				RunQueryDsl as _,
+			},
+		};
+
+		// Get a salt and generate recovery codes
+		let mut connection = self.0.get().await?;
+		let salt: Vec<u8> = users::table
+			.find(user.id())
+			.select(users::dsl::salt)
+			.first(&mut connection)
+			.await?;
+		let params = common::crypto::Argon2Params::default();
+		let (codes, code_keys) =
+			Self::generate_recovery_codes(&salt, params).await?;
+		let new_codes = code_keys
+			.iter()
+			.map(|k| {
+				crate::models::NewRecoveryCode::new(
+					user.id(),
+					params,
+					k,
+					user.data_key(),
+				)
+			})
+			.collect::<Result<Vec<_>>>()?;
+
+		// Replace the old codes
+		connection
+			.transaction::<_, anyhow::Error, _>(|c| {
+				async move {
+					diesel::delete(
+						recovery_codes::table.filter(
+							recovery_codes::dsl::user_id.eq(user.id()),
+						),
+					)
+					.execute(c)
+					.await?;
+					diesel::insert_into(recovery_codes::table)
+						.values(new_codes)
+						.execute(c)
+						.await?;
+					Ok(())
+				}
+				.scope_boxed()
+			})
+			.await?;
+		Ok(codes)
+	}
+
+	/// Generates `consts::RECOVERY_CODES_PER_USER` recovery codes, returning
+	/// them and their keys derived with the `salt` and the `params`.
+	async fn generate_recovery_codes(
+		salt: &[u8],
+		params: common::crypto::Argon2Params,
+	) -> Result<(Vec<String>, Vec<common::crypto::PasswordKeys>)> {
+		let mut codes =
+			Vec::with_capacity(crate::consts::RECOVERY_CODES_PER_USER);
+		let mut code_keys = Vec::with_capacity(codes.capacity());
+		for _ in 0..codes.capacity() {
+			let code = Self::generate_recovery_code()?;
+			code_keys
+				.push(Self::derive_password_keys(&code, salt, params).await?);
+			codes.push(code);
+		}
+		Ok((codes, code_keys))
+	}
+
+	/// Generates a random recovery code, like `0123-4567-89ab-cdef-0123`.
+	fn generate_recovery_code() -> Result<String> {
+		use std::fmt::Write as _;
+		let bytes = common::crypto::generate_random_bytes(Some(
+			crate::consts::RECOVERY_CODE_LENGTH,
+		))
+		.context("Failed to generate a recovery code.")?;
+		let mut code = String::with_capacity(bytes.len() * 5 / 2);
+		for (i, b) in bytes.iter().enumerate() {
+			if i != 0 && i % 2 == 0 {
+				code.push('-');
+			}
+			write!(code, "{b:02x}")?;
+		}
+		Ok(code)
+	}
+
+	/// Removes separators and whitespace from the user input of a recovery
+	/// code and makes it lowercase, so it can be derived like a generated one.
+	fn normalize_recovery_code(code: &str) -> String {
+		let hex = code
+			.chars()
+			.filter(char::is_ascii_alphanumeric)
+			.map(|c| c.to_ascii_lowercase())
+			.collect::<Vec<_>>();
+		let groups = hex.chunks(4).map(|g| g.iter().collect::<String>());
+		groups.collect::<Vec<_>>().join("-")
 	}
 
 	pub(crate) async fn switch_user_f2f(
@@ -310,11 +771,10 @@ impl Db {
 			.load::<crate::models::Friend>(&mut connection)
 			.await?;
 
-		// Make a cipher and decrypt database friends
-		let cipher = user.make_aes_cipher();
+		// Decrypt database friends
 		let mut raw_friends = Vec::with_capacity(db_friends.len());
 		for db_friend in db_friends {
-			raw_friends.push(Self::decrypt_friend(&db_friend, &cipher)?);
+			raw_friends.push(Self::decrypt_friend(&db_friend, user)?);
 		}
 
 		// Sort by username and return
@@ -349,9 +809,8 @@ impl Db {
 			.first(&mut connection)
 			.await?;
 
-		// Make a cipher and decrypt the database friend
-		let cipher = user.make_aes_cipher();
-		let raw_friend = Self::decrypt_friend(&db_friend, &cipher)?;
+		// Decrypt the database friend
+		let raw_friend = Self::decrypt_friend(&db_friend, user)?;
 		Ok(raw_friend)
 	}
 
@@ -372,19 +831,39 @@ impl Db {
 			.order(dsl::created_at.desc())
 			.load::<crate::models::Node>(&mut connection)
 			.await?;
-		// Make a cipher and decrypt database nodes
-		let cipher = user.make_aes_cipher();
+		// Decrypt database nodes
 		let mut raw_nodes = Vec::with_capacity(db_nodes.len());
 		for db_node in db_nodes {
 			// Decrypt data
-			let address =
-				cipher.decrypt_string(&db_node.encrypted_address)?.parse()?;
+			let address = user
+				.decrypt_column_string(
+					"nodes.encrypted_address",
+					&db_node.address_hash,
+					&db_node.encrypted_address,
+				)?
+				.parse()?;
 			let password = match db_node.encrypted_password {
-				Some(ep) => Some(cipher.decrypt_string(&ep)?),
+				Some(ep) => Some(user.decrypt_column_string(
+					"nodes.encrypted_password",
+					&db_node.address_hash,
+					&ep,
+				)?),
+				None => None,
+			};
+			let identity_key = match db_node.encrypted_identity_key {
+				Some(eik) => Some(user.decrypt_column_string(
+					"nodes.encrypted_identity_key",
+					&db_node.address_hash,
+					&eik,
+				)?),
 				None => None,
 			};
 			raw_nodes.push(crate::raw_models::Node::new(
-				db_node.id, address, password,
+				db_node.id,
+				address,
+				password,
+				identity_key,
+				db_node.sync_cursor,
 			));
 		}
 		Ok(raw_nodes)
@@ -547,12 +1026,19 @@ impl Db {
 		user: &crate::raw_models::User,
 		host: &str,
 		password: Option<&str>,
+		identity_key: Option<&str>,
 	) -> Result<()> {
 		use {crate::schema::nodes::table, diesel_async::RunQueryDsl as _};
 		debug_assert!(!self.check_node_exists(user, host).await?);
 
-		let new_node =
-			crate::models::NewNode::new(self, user, host, password).await?;
+		let new_node = crate::models::NewNode::new(
+			self,
+			user,
+			host,
+			password,
+			identity_key,
+		)
+		.await?;
 		let mut connection = self.0.get().await?;
 		diesel::insert_into(table)
 			.values(new_node)
@@ -561,6 +1047,30 @@ impl Db {
 		Ok(())
 	}
 
+	pub(crate) async fn set_node_sync_cursor(
+		&self,
+		user: &crate::raw_models::User,
+		id: i32,
+		sync_cursor: i32,
+	) -> Result<()> {
+		use {
+			crate::schema::nodes::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		let updated_rows = diesel::update(table.find(id))
+			.filter(dsl::user_id.eq(user.id()))
+			.set(dsl::sync_cursor.eq(sync_cursor))
+			.execute(&mut connection)
+			.await?;
+		if updated_rows == 0 {
+			return Err(diesel::result::Error::NotFound.into());
+		}
+		Ok(())
+	}
+
 	pub(crate) async fn delete_node(
 		&self,
 		user: &crate::raw_models::User,
@@ -599,4 +1109,483 @@ impl Db {
 		diesel::delete(filter).execute(&mut connection).await?;
 		Ok(())
 	}
+
+	/// Saves private `prekeys`, so emails encrypted to them can be decrypted.
+	/// A `last_resort` prekey is never deleted.
+	pub(crate) async fn add_prekeys(
+		&self,
+		user: &crate::raw_models::User,
+		prekeys: &[common::crypto::Prekey],
+		last_resort: bool,
+	) -> Result<()> {
+		use {crate::schema::prekeys::table, diesel_async::RunQueryDsl as _};
+
+		let new_prekeys = prekeys
+			.iter()
+			.map(|p| crate::models::NewPrekey::new(user, p, last_resort))
+			.collect::<Result<Vec<_>>>()?;
+		let mut connection = self.0.get().await?;
+		diesel::insert_into(table)
+			.values(new_prekeys)
+			.execute(&mut connection)
+			.await?;
+		Ok(())
+	}
+
+	/// Returns the private prekey with the raw `public_key`, if it is not
+	/// deleted.
+	pub(crate) async fn get_prekey(
+		&self,
+		user: &crate::raw_models::User,
+		public_key: &[u8],
+	) -> Result<Option<common::crypto::Prekey>> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{
+				ExpressionMethods as _, OptionalExtension as _, QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		let public_key_hash = common::crypto::hash(public_key);
+		let encrypted_private_key: Option<Vec<u8>> = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(dsl::public_key_hash.eq(public_key_hash.to_vec()))
+			.select(dsl::encrypted_private_key)
+			.first(&mut connection)
+			.await
+			.optional()?;
+		let Some(encrypted_private_key) = encrypted_private_key else {
+			return Ok(None);
+		};
+		let private_key = user.decrypt_column(
+			"prekeys.encrypted_private_key",
+			&public_key_hash,
+			&encrypted_private_key,
+		)?;
+		Ok(Some(common::crypto::Prekey::from_raw_private_key(&private_key)?))
+	}
+
+	/// Returns the newest last-resort private prekey of the user.
+	pub(crate) async fn get_last_resort_prekey(
+		&self,
+		user: &crate::raw_models::User,
+	) -> Result<Option<common::crypto::Prekey>> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{
+				ExpressionMethods as _, OptionalExtension as _, QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		let prekey: Option<(Vec<u8>, Vec<u8>)> = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(dsl::last_resort.eq(true))
+			.order(dsl::id.desc())
+			.select((dsl::public_key_hash, dsl::encrypted_private_key))
+			.first(&mut connection)
+			.await
+			.optional()?;
+		let Some((public_key_hash, encrypted_private_key)) = prekey else {
+			return Ok(None);
+		};
+		let private_key = user.decrypt_column(
+			"prekeys.encrypted_private_key",
+			&public_key_hash,
+			&encrypted_private_key,
+		)?;
+		Ok(Some(common::crypto::Prekey::from_raw_private_key(&private_key)?))
+	}
+
+	/// Deletes the used one-time prekey with the raw `public_key`, so emails
+	/// encrypted to it can't be decrypted anymore.
+	pub(crate) async fn delete_prekey(
+		&self,
+		user: &crate::raw_models::User,
+		public_key: &[u8],
+	) -> Result<()> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		let filter = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(
+				dsl::public_key_hash
+					.eq(common::crypto::hash(public_key).to_vec()),
+			)
+			.filter(dsl::last_resort.eq(false));
+		diesel::delete(filter).execute(&mut connection).await?;
+		Ok(())
+	}
+
+	/// Deletes one-time prekeys older than `than`.
+	pub(crate) async fn delete_old_prekeys(
+		&self,
+		than: std::time::Duration,
+	) -> Result<()> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		let filter = table
+			.filter(dsl::created_at.lt(std::time::SystemTime::now() - than))
+			.filter(dsl::last_resort.eq(false));
+		diesel::delete(filter).execute(&mut connection).await?;
+		Ok(())
+	}
+
+	/// Re-encrypts all data of the `user` that is not in the
+	/// [current envelope](common::crypto::Envelope::CURRENT), like raw
+	/// ciphertexts written before envelopes were added, in one transaction.
+	/// Only such rows are loaded, and ones that can't be decrypted are
+	/// skipped.
+	pub(crate) async fn reencrypt_user_data(
+		&self,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		use diesel_async::{
+			scoped_futures::ScopedFutureExt as _, AsyncConnection as _,
+		};
+
+		let mut connection = self.0.get().await?;
+		connection
+			.transaction::<_, anyhow::Error, _>(|c| {
+				async move { Self::reencrypt(c, user).await }.scope_boxed()
+			})
+			.await
+	}
+
+	/// Changes the password of the `user` to the `new_password`, rewrapping
+	/// its data key and rotating the login verifier in one update, so the data
+	/// itself doesn't have to be re-encrypted. Returns the new session
+	/// generation, so other sessions of the user are logged out.
+	pub(crate) async fn change_user_password(
+		&self,
+		user: &crate::raw_models::User,
+		new_password: &str,
+	) -> Result<i32> {
+		use {
+			crate::schema::users::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		// Derive new password keys
+		let salt = self.get_user_salt(user).await?;
+		let params = common::crypto::Argon2Params::default();
+		let password_keys =
+			Self::derive_password_keys(new_password, &salt, params).await?;
+		let (memory_cost, time_cost, parallelism) =
+			crate::models::argon2_columns(params)?;
+		let wrapped_data_key = crate::models::wrap_data_key(
+			"users.wrapped_data_key",
+			&common::crypto::hash(user.username()),
+			&password_keys,
+			user.data_key(),
+		)?;
+
+		// Rewrap the data key, rotate the verifier and the session generation
+		let mut connection = self.0.get().await?;
+		let session_generation = diesel::update(table.find(user.id()))
+			.set((
+				dsl::password_hash.eq(password_keys.verifier().to_vec()),
+				dsl::argon2_memory_cost.eq(memory_cost),
+				dsl::argon2_time_cost.eq(time_cost),
+				dsl::argon2_parallelism.eq(parallelism),
+				dsl::wrapped_data_key.eq(wrapped_data_key),
+				dsl::session_generation.eq(dsl::session_generation + 1),
+			))
+			.returning(dsl::session_generation)
+			.get_result(&mut connection)
+			.await?;
+		Ok(session_generation)
+	}
+
+	async fn reencrypt(
+		connection: &mut diesel_async::AsyncPgConnection,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		Self::reencrypt_user(connection, user).await?;
+		Self::reencrypt_friends(connection, user).await?;
+		Self::reencrypt_emails(connection, user).await?;
+		Self::reencrypt_nodes(connection, user).await?;
+		Self::reencrypt_prekeys(connection, user).await
+	}
+
+	async fn reencrypt_user(
+		connection: &mut diesel_async::AsyncPgConnection,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		use {
+			crate::schema::users::{dsl, table},
+			diesel::{
+				ExpressionMethods as _, OptionalExtension as _, QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let encrypted_private_key_pem: Option<Vec<u8>> = table
+			.find(user.id())
+			.filter(outdated!(dsl::encrypted_private_key_pem))
+			.select(dsl::encrypted_private_key_pem)
+			.first(connection)
+			.await
+			.optional()?;
+		let Some(encrypted_private_key_pem) = encrypted_private_key_pem else {
+			return Ok(());
+		};
+		if let Some(e) = Self::reencrypt_column(
+			user,
+			"users.encrypted_private_key_pem",
+			&common::crypto::hash(user.username()),
+			&encrypted_private_key_pem,
+		)? {
+			diesel::update(table.find(user.id()))
+				.set(dsl::encrypted_private_key_pem.eq(e))
+				.execute(connection)
+				.await?;
+		}
+		Ok(())
+	}
+
+	async fn reencrypt_friends(
+		connection: &mut diesel_async::AsyncPgConnection,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		use {
+			crate::schema::friends::{dsl, table},
+			diesel::{
+				BoolExpressionMethods as _, ExpressionMethods as _,
+				QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let db_friends = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(
+				outdated!(dsl::encrypted_username)
+					.or(outdated!(dsl::encrypted_public_key_pem_base64)),
+			)
+			.load::<crate::models::Friend>(connection)
+			.await?;
+		for f in db_friends {
+			let username = Self::reencrypt_column(
+				user,
+				"friends.encrypted_username",
+				&f.username_hash,
+				&f.encrypted_username,
+			)?;
+			let public_key = Self::reencrypt_column(
+				user,
+				"friends.encrypted_public_key_pem_base64",
+				&f.username_hash,
+				&f.encrypted_public_key_pem_base64,
+			)?;
+			if username.is_none() && public_key.is_none() {
+				continue;
+			}
+			diesel::update(table.find(f.id))
+				.set((
+					dsl::encrypted_username
+						.eq(username.unwrap_or(f.encrypted_username)),
+					dsl::encrypted_public_key_pem_base64.eq(public_key
+						.unwrap_or(f.encrypted_public_key_pem_base64)),
+				))
+				.execute(connection)
+				.await?;
+		}
+		Ok(())
+	}
+
+	async fn reencrypt_emails(
+		connection: &mut diesel_async::AsyncPgConnection,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		use {
+			crate::schema::emails::{dsl, table},
+			diesel::{
+				BoolExpressionMethods as _, ExpressionMethods as _,
+				QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let db_emails = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(
+				outdated!(dsl::encrypted_sender_public_key_pem)
+					.or(outdated!(dsl::encrypted_data_bytes)),
+			)
+			.load::<crate::models::Email>(connection)
+			.await?;
+		for e in db_emails {
+			let sender_public_key = Self::reencrypt_column(
+				user,
+				"emails.encrypted_sender_public_key_pem",
+				e.proof_of_work.as_bytes(),
+				&e.encrypted_sender_public_key_pem,
+			)?;
+			let data_bytes = Self::reencrypt_column(
+				user,
+				"emails.encrypted_data_bytes",
+				e.proof_of_work.as_bytes(),
+				&e.encrypted_data_bytes,
+			)?;
+			if sender_public_key.is_none() && data_bytes.is_none() {
+				continue;
+			}
+			diesel::update(table.find(e.id))
+				.set((
+					dsl::encrypted_sender_public_key_pem.eq(sender_public_key
+						.unwrap_or(e.encrypted_sender_public_key_pem)),
+					dsl::encrypted_data_bytes
+						.eq(data_bytes.unwrap_or(e.encrypted_data_bytes)),
+				))
+				.execute(connection)
+				.await?;
+		}
+		Ok(())
+	}
+
+	async fn reencrypt_nodes(
+		connection: &mut diesel_async::AsyncPgConnection,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		use {
+			crate::schema::nodes::{dsl, table},
+			diesel::{
+				BoolExpressionMethods as _, ExpressionMethods as _,
+				QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let db_nodes = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(
+				outdated!(dsl::encrypted_address)
+					.or(outdated!(dsl::encrypted_password))
+					.or(outdated!(dsl::encrypted_identity_key)),
+			)
+			.load::<crate::models::Node>(connection)
+			.await?;
+		for n in db_nodes {
+			let reencrypt_optional = |column, data: &Option<Vec<u8>>| {
+				data.as_deref()
+					.map(|d| {
+						Self::reencrypt_column(
+							user,
+							column,
+							&n.address_hash,
+							d,
+						)
+					})
+					.transpose()
+					.map(Option::flatten)
+			};
+			let address = Self::reencrypt_column(
+				user,
+				"nodes.encrypted_address",
+				&n.address_hash,
+				&n.encrypted_address,
+			)?;
+			let password = reencrypt_optional(
+				"nodes.encrypted_password",
+				&n.encrypted_password,
+			)?;
+			let identity_key = reencrypt_optional(
+				"nodes.encrypted_identity_key",
+				&n.encrypted_identity_key,
+			)?;
+			if address.is_none()
+				&& password.is_none()
+				&& identity_key.is_none()
+			{
+				continue;
+			}
+			diesel::update(table.find(n.id))
+				.set((
+					dsl::encrypted_address
+						.eq(address.unwrap_or(n.encrypted_address)),
+					dsl::encrypted_password
+						.eq(password.or(n.encrypted_password)),
+					dsl::encrypted_identity_key
+						.eq(identity_key.or(n.encrypted_identity_key)),
+				))
+				.execute(connection)
+				.await?;
+		}
+		Ok(())
+	}
+
+	async fn reencrypt_prekeys(
+		connection: &mut diesel_async::AsyncPgConnection,
+		user: &crate::raw_models::User,
+	) -> Result<()> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let db_prekeys = table
+			.filter(dsl::user_id.eq(user.id()))
+			.filter(outdated!(dsl::encrypted_private_key))
+			.load::<crate::models::Prekey>(connection)
+			.await?;
+		for p in db_prekeys {
+			if let Some(e) = Self::reencrypt_column(
+				user,
+				"prekeys.encrypted_private_key",
+				&p.public_key_hash,
+				&p.encrypted_private_key,
+			)? {
+				diesel::update(table.find(p.id))
+					.set(dsl::encrypted_private_key.eq(e))
+					.execute(connection)
+					.await?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Re-encrypts the `data` of the `column` of the row with the `row_key`
+	/// into the current envelope, returning [`None`] if it is already in it
+	/// or can't be decrypted, so a corrupted row doesn't fail the login.
+	fn reencrypt_column(
+		user: &crate::raw_models::User,
+		column: &str,
+		row_key: &[u8],
+		data: &[u8],
+	) -> Result<Option<Vec<u8>>> {
+		if common::crypto::Envelope::is_current(data) {
+			return Ok(None);
+		}
+		let decrypted = match user.decrypt_column(column, row_key, data) {
+			Ok(d) => d,
+			Err(e) => {
+				common::debug!(
+					"Failed to decrypt {}, skipping: {}",
+					column,
+					e
+				);
+				return Ok(None);
+			}
+		};
+		let encrypted = user
+			.encrypt_column(column, row_key, decrypted)
+			.with_context(|| format!("Failed to encrypt {column}."))?;
+		Ok(Some(encrypted))
+	}
 }
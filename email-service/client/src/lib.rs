@@ -40,12 +40,15 @@ pub async fn launch() -> Result<()> {
 	);
 	actix_web::HttpServer::new(move || {
 		let state = state.clone();
-		let session_middleware =
-			app::middleware::make_session_middleware(state.config());
+		let session_middleware = app::middleware::make_session_middleware(
+			state.config(),
+			state.session_store().clone(),
+		);
 		let identity_middleware = app::middleware::make_identity_middleware();
 
 		actix_web::App::new()
 			.app_data(state)
+			.wrap(app::middleware::CheckCurrentUser)
 			.wrap(session_middleware)
 			.wrap(identity_middleware)
 			.wrap_fn(|r, service| {
@@ -58,13 +61,20 @@ pub async fn launch() -> Result<()> {
 			.service(app::service::login_post)
 			.service(app::service::register_get)
 			.service(app::service::register_post)
+			.service(app::service::recover_get)
+			.service(app::service::recover_post)
 			.service(app::service::logout)
 			.service(app::service::profile)
 			.service(app::service::switch_f2f)
+			.service(app::service::change_password_get)
+			.service(app::service::change_password_post)
 			.service(app::service::delete_account_get)
 			.service(app::service::delete_account_post)
+			.service(app::service::recovery_codes_get)
+			.service(app::service::recovery_codes_post)
 			.service(app::service::emails)
 			.service(app::service::load_emails)
+			.service(app::service::progress)
 			.service(app::service::send_email_get)
 			.service(app::service::send_email_post)
 			.service(app::service::email)
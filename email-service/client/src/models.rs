@@ -1,12 +1,34 @@
 use anyhow::{Context as _, Result};
 
+/// Makes the associated data that binds a ciphertext to the `column` (like
+/// `users.encrypted_private_key_pem`) of the row with the unique `row_key`,
+/// so it can't be moved to another row or column.
+#[must_use]
+pub(crate) fn column_aad(column: &str, row_key: &[u8]) -> Vec<u8> {
+	[column.as_bytes(), &[0], row_key].concat()
+}
+
 /// # Explanation of some fields
 ///
-/// aes key = sha256(user password, user username)
+/// password keys = argon2id[`self.argon2_*`](user password, user salt), see
+/// `common::crypto::derive_password_keys`
+/// data key = random key, see `common::crypto::KeyDerivation::Wrapped`
+/// aes key = data key, or the password keys data key or sha256(user password,
+/// user username) for data encrypted before the data key was added
 ///
 /// `self.username_hash` = sha256(user username)
-/// `self.password_hash` = sha256(password, user salt)
-/// `self.encrypted_private_key_pem` = aes[aes key](private key pem)
+/// `self.password_hash` = password keys verifier, or sha256(password, user
+/// salt) if `self.argon2_*` are `None`
+/// `self.encrypted_private_key_pem` =
+/// aes[aes key, `self.username_hash`](private key pem)
+/// `self.wrapped_data_key` =
+/// aes[password keys data key, `self.username_hash`](data key), or `None` if
+/// the user hasn't logged in since the data key was added
+/// `self.session_generation` - incremented when the password is changed or
+/// recovered, so other sessions of the user are logged out
+///
+/// aes is sealed in the `common::crypto::Envelope`, its second parameter is
+/// the row key of the associated data, see `column_aad`.
 #[allow(dead_code)]
 #[derive(diesel::prelude::Queryable)]
 pub(crate) struct User {
@@ -17,6 +39,76 @@ pub(crate) struct User {
 	pub salt: Vec<u8>,
 	pub f2f_enabled: bool,
 	pub created_at: chrono::NaiveDateTime,
+	pub argon2_memory_cost: Option<i32>,
+	pub argon2_time_cost: Option<i32>,
+	pub argon2_parallelism: Option<i32>,
+	pub wrapped_data_key: Option<Vec<u8>>,
+	pub session_generation: i32,
+}
+
+impl User {
+	/// Returns the Argon2id parameters of the password keys, or [`None`] if
+	/// the password hash is from before Argon2id was added.
+	pub fn argon2_params(
+		&self,
+	) -> Result<Option<common::crypto::Argon2Params>> {
+		match (
+			self.argon2_memory_cost,
+			self.argon2_time_cost,
+			self.argon2_parallelism,
+		) {
+			(Some(m), Some(t), Some(p)) => argon2_params(m, t, p).map(Some),
+			_ => Ok(None),
+		}
+	}
+
+	/// Unwraps the data key with the `password_keys`, or returns [`None`] if
+	/// there is no one.
+	pub fn unwrap_data_key(
+		&self,
+		password_keys: &common::crypto::PasswordKeys,
+	) -> Result<Option<Vec<u8>>> {
+		self.wrapped_data_key
+			.as_deref()
+			.map(|w| {
+				unwrap_data_key(
+					"users.wrapped_data_key",
+					&self.username_hash,
+					password_keys,
+					w,
+				)
+			})
+			.transpose()
+	}
+}
+
+/// Wraps the `data_key` of a user by the `keys` (password or recovery code
+/// keys), the value of the `column` of the row with the `row_key`.
+pub(crate) fn wrap_data_key(
+	column: &str,
+	row_key: &[u8],
+	keys: &common::crypto::PasswordKeys,
+	data_key: &[u8],
+) -> Result<Vec<u8>> {
+	use common::crypto::{Envelope, EnvelopeCipher, KeyDerivation};
+	Envelope::new(EnvelopeCipher::Aes256Gcm, KeyDerivation::Argon2id)
+		.seal(keys.data_key(), &column_aad(column, row_key), data_key)
+		.context("Failed to wrap the data key.")
+}
+
+/// Reverse of `wrap_data_key`.
+fn unwrap_data_key(
+	column: &str,
+	row_key: &[u8],
+	keys: &common::crypto::PasswordKeys,
+	wrapped_data_key: &[u8],
+) -> Result<Vec<u8>> {
+	common::crypto::Envelope::open(
+		keys.data_key(),
+		&column_aad(column, row_key),
+		wrapped_data_key,
+	)
+	.context("Failed to unwrap the data key.")
 }
 
 /// Used to create a new user. For more information see `User`.
@@ -30,50 +122,95 @@ pub(crate) struct NewUser {
 	encrypted_private_key_pem: Vec<u8>,
 	salt: Vec<u8>,
 	f2f_enabled: bool,
+	argon2_memory_cost: i32,
+	argon2_time_cost: i32,
+	argon2_parallelism: i32,
+	wrapped_data_key: Vec<u8>,
 }
 
 impl NewUser {
+	/// The `password_keys` must be derived with the `salt` and the
+	/// `argon2_params`, the `data_key` must be random.
 	pub fn new(
 		username: &str,
-		password: &str,
-		private_key: &openssl::rsa::Rsa<openssl::pkey::Private>,
+		salt: Vec<u8>,
+		argon2_params: common::crypto::Argon2Params,
+		password_keys: &common::crypto::PasswordKeys,
+		data_key: &[u8],
+		private_key: &common::crypto::PrivateKey,
 	) -> Result<Self> {
-		// Hash username and password
-		let salt = common::crypto::generate_random_bytes(None)
-			.context("Failed to generate a salt.")?;
 		let username_hash = common::crypto::hash(username);
-		let password_hash = common::crypto::hash_with_salt(password, &salt);
+		let (argon2_memory_cost, argon2_time_cost, argon2_parallelism) =
+			argon2_columns(argon2_params)?;
 
-		// Make cipher and encrypt private key
-		let key = common::crypto::hash_with_salt(password, username);
-		let cipher = common::crypto::AesCipher::new(&key[..]);
+		// Encrypt private key
 		let private_key_pem = private_key
 			.private_key_to_pem()
 			.context("Failed to get private key pem.")?;
-		let encrypted_private_key_pem = cipher
-			.encrypt(private_key_pem)
+		let encrypted_private_key_pem = common::crypto::Envelope::CURRENT
+			.seal(
+				data_key,
+				&column_aad("users.encrypted_private_key_pem", &username_hash),
+				private_key_pem,
+			)
 			.context("Failed to encrypt private key pem.")?;
 
 		Ok(Self {
 			username_hash: username_hash.to_vec(),
-			password_hash: password_hash.to_vec(),
+			password_hash: password_keys.verifier().to_vec(),
 			encrypted_private_key_pem,
 			salt,
 			f2f_enabled: false,
+			argon2_memory_cost,
+			argon2_time_cost,
+			argon2_parallelism,
+			wrapped_data_key: wrap_data_key(
+				"users.wrapped_data_key",
+				&username_hash,
+				password_keys,
+				data_key,
+			)?,
 		})
 	}
 }
 
+/// Converts values of the `argon2_*` columns into the params.
+fn argon2_params(
+	memory_cost: i32,
+	time_cost: i32,
+	parallelism: i32,
+) -> Result<common::crypto::Argon2Params> {
+	use std::convert::TryInto as _;
+	Ok(common::crypto::Argon2Params::new(
+		memory_cost.try_into().context("Invalid memory cost.")?,
+		time_cost.try_into().context("Invalid time cost.")?,
+		parallelism.try_into().context("Invalid parallelism.")?,
+	))
+}
+
+/// Converts the `params` into values of the `argon2_*` columns.
+pub(crate) fn argon2_columns(
+	params: common::crypto::Argon2Params,
+) -> Result<(i32, i32, i32)> {
+	use std::convert::TryInto as _;
+	Ok((
+		params.memory_cost().try_into().context("Too large memory cost.")?,
+		params.time_cost().try_into().context("Too large time cost.")?,
+		params.parallelism().try_into().context("Too large parallelism.")?,
+	))
+}
+
 /// # Explanation of some fields
 ///
-/// aes key = sha256(current user password, current user username)
+/// aes key = current user aes key, see `User`
 ///
 /// `self.username_hash` = sha256(friend username, current user salt)
 /// `self.public_key_pem_base64_hash` = sha256(base64(friend public key pem),
 /// current user salt)
-/// `self.encrypted_username` = aes[aes key](friend_ username)
+/// `self.encrypted_username` =
+/// aes[aes key, `self.username_hash`](friend username)
 /// `self.encrypted_public_key_pem_base64` =
-/// aes[aes key](base64(friend public key pem))
+/// aes[aes key, `self.username_hash`](base64(friend public key pem))
 #[allow(dead_code)]
 #[derive(diesel::prelude::Queryable)]
 pub(crate) struct Friend {
@@ -116,11 +253,19 @@ impl NewFriend {
 			common::crypto::hash_with_salt(public_key_pem_base64, &salt);
 
 		// Encrypt username and public key pem
-		let cipher = user.make_aes_cipher();
-		let encrypted_username =
-			cipher.encrypt(username).context("Failed to encrypt username.")?;
-		let encrypted_public_key_pem_base64 = cipher
-			.encrypt(public_key_pem_base64)
+		let encrypted_username = user
+			.encrypt_column(
+				"friends.encrypted_username",
+				&username_hash,
+				username,
+			)
+			.context("Failed to encrypt username.")?;
+		let encrypted_public_key_pem_base64 = user
+			.encrypt_column(
+				"friends.encrypted_public_key_pem_base64",
+				&username_hash,
+				public_key_pem_base64,
+			)
 			.context("Failed to encrypt public key pem base64.")?;
 
 		Ok(Self {
@@ -135,11 +280,12 @@ impl NewFriend {
 
 /// # Explanation of some fields
 ///
-/// aes key = sha256(current user password, current user username)
+/// aes key = current user aes key, see `User`
 ///
 /// `self.encrypted_sender_public_key_pem` =
-/// aes[aes key](sender public key pem)
-/// `self.encrypted_data_bytes` = aes[aes key](`common::email::Data` bytes)
+/// aes[aes key, `self.proof_of_work`](sender public key pem)
+/// `self.encrypted_data_bytes` =
+/// aes[aes key, `self.proof_of_work`](`common::email::Data` bytes)
 /// `self.proof_of_work` = proof of work from `Email`. Needed to avoid
 /// duplicates.
 #[allow(dead_code)]
@@ -181,30 +327,41 @@ impl NewEmail {
 		let data_bytes = bincode::serialize(email.data().unwrap())
 			.context("Failed to serialize email data.")?;
 
-		let cipher = user.make_aes_cipher();
-		let encrypted_sender_public_key_pem = cipher
-			.encrypt(sender_public_key_pem)
+		let proof_of_work = email.compute_hash();
+		let encrypted_sender_public_key_pem = user
+			.encrypt_column(
+				"emails.encrypted_sender_public_key_pem",
+				proof_of_work.as_bytes(),
+				sender_public_key_pem,
+			)
 			.context("Failed to encrypt sender public key pem.")?;
-		let encrypted_data_bytes = cipher
-			.encrypt(data_bytes)
+		let encrypted_data_bytes = user
+			.encrypt_column(
+				"emails.encrypted_data_bytes",
+				proof_of_work.as_bytes(),
+				data_bytes,
+			)
 			.context("Failed to encrypt data bytes.")?;
 
 		Ok(Self {
 			user_id: user.id(),
 			encrypted_sender_public_key_pem,
 			encrypted_data_bytes,
-			proof_of_work: email.compute_hash(),
+			proof_of_work,
 		})
 	}
 }
 
 /// # Explanation of some fields
 ///
-/// aes key = sha256(current user password, current user username)
+/// aes key = current user aes key, see `User`
 ///
 /// `self.address_hash` = sha256(address, current user salt)
-/// `self.encrypted_address` = aes[aes key](address)
-/// `self.encrypted_password` = aes[aes key](password)
+/// `self.encrypted_address` = aes[aes key, `self.address_hash`](address)
+/// `self.encrypted_password` = aes[aes key, `self.address_hash`](password)
+/// `self.sync_cursor` = identifier of the last email processed from the node
+/// `self.encrypted_identity_key` =
+/// aes[aes key, `self.address_hash`](pinned node identity key)
 #[allow(dead_code)]
 #[derive(diesel::prelude::Queryable)]
 pub(crate) struct Node {
@@ -214,6 +371,8 @@ pub(crate) struct Node {
 	pub encrypted_address: Vec<u8>,
 	pub encrypted_password: Option<Vec<u8>>,
 	pub created_at: chrono::NaiveDateTime,
+	pub sync_cursor: i32,
+	pub encrypted_identity_key: Option<Vec<u8>>,
 }
 
 /// Used to add a new node. For more information see `Node`.
@@ -226,6 +385,7 @@ pub(crate) struct NewNode {
 	address_hash: Vec<u8>,
 	encrypted_address: Vec<u8>,
 	encrypted_password: Option<Vec<u8>>,
+	encrypted_identity_key: Option<Vec<u8>>,
 }
 
 impl NewNode {
@@ -234,6 +394,7 @@ impl NewNode {
 		user: &crate::raw_models::User,
 		address: &str,
 		password: Option<&str>,
+		identity_key: Option<&str>,
 	) -> Result<Self> {
 		let salt = db
 			.get_user_salt(user)
@@ -242,12 +403,28 @@ impl NewNode {
 		let address_hash = common::crypto::hash_with_salt(address, salt);
 
 		// Encrypt username and public key pem
-		let cipher = user.make_aes_cipher();
-		let encrypted_address =
-			cipher.encrypt(address).context("Failed to encrypt address.")?;
+		let encrypted_address = user
+			.encrypt_column("nodes.encrypted_address", &address_hash, address)
+			.context("Failed to encrypt address.")?;
 		let encrypted_password = match password {
 			Some(p) => Some(
-				cipher.encrypt(p).context("Failed to encrypt password.")?,
+				user.encrypt_column(
+					"nodes.encrypted_password",
+					&address_hash,
+					p,
+				)
+				.context("Failed to encrypt password.")?,
+			),
+			None => None,
+		};
+		let encrypted_identity_key = match identity_key {
+			Some(ik) => Some(
+				user.encrypt_column(
+					"nodes.encrypted_identity_key",
+					&address_hash,
+					ik,
+				)
+				.context("Failed to encrypt identity key.")?,
 			),
 			None => None,
 		};
@@ -256,6 +433,154 @@ impl NewNode {
 			address_hash: address_hash.into(),
 			encrypted_address,
 			encrypted_password,
+			encrypted_identity_key,
+		})
+	}
+}
+
+/// # Explanation of some fields
+///
+/// aes key = current user aes key, see `User`
+///
+/// `self.public_key_hash` = sha256(prekey raw public key)
+/// `self.encrypted_private_key` =
+/// aes[aes key, `self.public_key_hash`](prekey raw private key)
+/// `self.last_resort` - whether it's the last-resort prekey of the user,
+/// which is never deleted
+#[allow(dead_code)]
+#[derive(diesel::prelude::Queryable)]
+pub(crate) struct Prekey {
+	pub id: i32,
+	pub user_id: i32,
+	pub public_key_hash: Vec<u8>,
+	pub encrypted_private_key: Vec<u8>,
+	pub created_at: chrono::NaiveDateTime,
+	pub last_resort: bool,
+}
+
+/// Used to add a new prekey. For more information see `Prekey`.
+///
+/// See also `Prekey`.
+#[derive(diesel::prelude::Insertable)]
+#[diesel(table_name = crate::schema::prekeys)]
+pub(crate) struct NewPrekey {
+	user_id: i32,
+	public_key_hash: Vec<u8>,
+	encrypted_private_key: Vec<u8>,
+	last_resort: bool,
+}
+
+impl NewPrekey {
+	pub fn new(
+		user: &crate::raw_models::User,
+		prekey: &common::crypto::Prekey,
+		last_resort: bool,
+	) -> Result<Self> {
+		let public_key_hash = common::crypto::hash(
+			prekey.raw_public_key().context("Failed to get public key.")?,
+		);
+		let private_key =
+			prekey.raw_private_key().context("Failed to get private key.")?;
+		let encrypted_private_key = user
+			.encrypt_column(
+				"prekeys.encrypted_private_key",
+				&public_key_hash,
+				private_key,
+			)
+			.context("Failed to encrypt private key.")?;
+		Ok(Self {
+			user_id: user.id(),
+			public_key_hash: public_key_hash.into(),
+			encrypted_private_key,
+			last_resort,
+		})
+	}
+}
+
+/// # Explanation of some fields
+///
+/// code keys = argon2id[`self.argon2_*`](recovery code, user salt), see
+/// `common::crypto::derive_password_keys`
+///
+/// `self.code_hash` = code keys verifier
+/// `self.wrapped_data_key` =
+/// aes[code keys data key, `self.code_hash`](data key), see `User`
+#[allow(dead_code)]
+#[derive(diesel::prelude::Queryable)]
+pub(crate) struct RecoveryCode {
+	pub id: i32,
+	pub user_id: i32,
+	pub code_hash: Vec<u8>,
+	pub wrapped_data_key: Vec<u8>,
+	pub argon2_memory_cost: i32,
+	pub argon2_time_cost: i32,
+	pub argon2_parallelism: i32,
+	pub created_at: chrono::NaiveDateTime,
+}
+
+impl RecoveryCode {
+	/// Returns the Argon2id parameters of the code keys.
+	pub fn argon2_params(&self) -> Result<common::crypto::Argon2Params> {
+		argon2_params(
+			self.argon2_memory_cost,
+			self.argon2_time_cost,
+			self.argon2_parallelism,
+		)
+	}
+
+	/// Unwraps the data key with the `code_keys`.
+	pub fn unwrap_data_key(
+		&self,
+		code_keys: &common::crypto::PasswordKeys,
+	) -> Result<Vec<u8>> {
+		unwrap_data_key(
+			"recovery_codes.wrapped_data_key",
+			&self.code_hash,
+			code_keys,
+			&self.wrapped_data_key,
+		)
+	}
+}
+
+/// Used to add a new recovery code. For more information see
+/// `RecoveryCode`.
+///
+/// See also `RecoveryCode`.
+#[derive(diesel::prelude::Insertable)]
+#[diesel(table_name = crate::schema::recovery_codes)]
+pub(crate) struct NewRecoveryCode {
+	user_id: i32,
+	code_hash: Vec<u8>,
+	wrapped_data_key: Vec<u8>,
+	argon2_memory_cost: i32,
+	argon2_time_cost: i32,
+	argon2_parallelism: i32,
+}
+
+impl NewRecoveryCode {
+	/// The `code_keys` must be derived with the user salt and the
+	/// `argon2_params`.
+	pub fn new(
+		user_id: i32,
+		argon2_params: common::crypto::Argon2Params,
+		code_keys: &common::crypto::PasswordKeys,
+		data_key: &[u8],
+	) -> Result<Self> {
+		let (argon2_memory_cost, argon2_time_cost, argon2_parallelism) =
+			argon2_columns(argon2_params)?;
+		let code_hash = code_keys.verifier().to_vec();
+		Ok(Self {
+			user_id,
+			wrapped_data_key: wrap_data_key(
+				"recovery_codes.wrapped_data_key",
+				&code_hash,
+				code_keys,
+				data_key,
+			)?,
+			code_hash,
+			argon2_memory_cost,
+			argon2_time_cost,
+			argon2_parallelism,
 		})
 	}
 }
@@ -1,11 +1,22 @@
 /// This structure is different from other raw model structures and contains
-/// minimal information because it is deserialized in cookies. If you need any
+/// minimal information because it is deserialized in sessions. If you need any
 /// additional information from the database, use `client::Client`.
+///
+/// It never contains the password, only keys derived from it.
 #[derive(serde::Deserialize, serde::Serialize)]
 pub(crate) struct User {
 	id: i32,
 	username: String,
-	password: String,
+	data_key: Vec<u8>,
+	/// The session generation of the user at login, see `models::User`.
+	#[serde(default)]
+	session_generation: i32,
+	/// sha256(password, username), see `models::User`.
+	#[serde(skip)]
+	legacy_key: Vec<u8>,
+	/// The data key of the password keys, see `models::User`.
+	#[serde(skip)]
+	password_key: Vec<u8>,
 }
 
 impl User {
@@ -13,20 +24,118 @@ impl User {
 
 	common::accessor!(& username -> &str);
 
-	common::accessor!(& password -> &str);
+	common::accessor!(& data_key -> &[u8]);
+
+	common::accessor!(copy session_generation -> i32);
 
 	#[inline]
 	#[must_use]
-	pub fn new(id: i32, username: String, password: String) -> Self {
-		Self { id, username, password }
+	pub fn new(
+		id: i32,
+		username: String,
+		data_key: Vec<u8>,
+		session_generation: i32,
+	) -> Self {
+		Self {
+			id,
+			username,
+			data_key,
+			session_generation,
+			legacy_key: Vec::new(),
+			password_key: Vec::new(),
+		}
+	}
+
+	/// Sets the `session_generation` after it was changed, so the session can
+	/// be renewed.
+	#[must_use]
+	pub fn with_session_generation(mut self, session_generation: i32) -> Self {
+		self.session_generation = session_generation;
+		self
 	}
 
-	/// Returns the [cipher](AesCipher) we use to encrypt most data.
+	/// Adds the keys of data encrypted before the data key was added, derived
+	/// from the `password` and the `password_key` (the data key of the
+	/// password keys). They are needed only to re-encrypt such data at login,
+	/// so they are not serialized in sessions.
 	#[must_use]
-	pub fn make_aes_cipher(&self) -> common::crypto::AesCipher {
-		let key =
-			common::crypto::hash_with_salt(&self.password, &self.username);
-		common::crypto::AesCipher::new(key.to_vec())
+	pub fn with_legacy_keys(
+		mut self,
+		password: &str,
+		password_key: Vec<u8>,
+	) -> Self {
+		self.legacy_key =
+			common::crypto::hash_with_salt(password, &self.username).to_vec();
+		self.password_key = password_key;
+		self
+	}
+
+	/// Returns the key we use to encrypt most data, derived by the
+	/// `key_derivation`.
+	#[must_use]
+	fn key(&self, key_derivation: common::crypto::KeyDerivation) -> &[u8] {
+		match key_derivation {
+			common::crypto::KeyDerivation::Sha256 => &self.legacy_key,
+			common::crypto::KeyDerivation::Argon2id => &self.password_key,
+			_ => &self.data_key,
+		}
+	}
+
+	/// Encrypts the `data` of the `column` of the row with the `row_key` in
+	/// the [current envelope](common::crypto::Envelope::CURRENT), binding it
+	/// to them, see `models::column_aad`.
+	pub fn encrypt_column<D: AsRef<[u8]>>(
+		&self,
+		column: &str,
+		row_key: &[u8],
+		data: D,
+	) -> Result<Vec<u8>, common::error::AesEncryptError> {
+		let envelope = common::crypto::Envelope::CURRENT;
+		envelope.seal(
+			self.key(envelope.key_derivation()),
+			&crate::models::column_aad(column, row_key),
+			data,
+		)
+	}
+
+	/// Decrypts the `data` of the `column` of the row with the `row_key`.
+	///
+	/// Raw ciphertexts without an envelope, encrypted before ciphertexts were
+	/// bound to their columns, are decrypted without associated data.
+	pub fn decrypt_column(
+		&self,
+		column: &str,
+		row_key: &[u8],
+		data: &[u8],
+	) -> Result<Vec<u8>, common::error::AesDecryptError> {
+		use common::crypto::{AesCipher, Envelope, KeyDerivation};
+
+		let aad = crate::models::column_aad(column, row_key);
+		if let Some(e) = Envelope::parse(data) {
+			let key = self.key(e.key_derivation());
+			if let Ok(rv) = Envelope::open(key, &aad, data) {
+				return Ok(rv);
+			}
+		}
+
+		// A raw ciphertext, that may start with an envelope by chance
+		let key = self.key(KeyDerivation::Sha256);
+		AesCipher::new(key)
+			.with_aad(aad)
+			.decrypt(data)
+			.or_else(|_| AesCipher::new(key).decrypt(data))
+	}
+
+	/// Same as [`decrypt_column`](User::decrypt_column), but returns
+	/// [`String`].
+	pub fn decrypt_column_string(
+		&self,
+		column: &str,
+		row_key: &[u8],
+		data: &[u8],
+	) -> Result<String, common::error::AesDecryptStringError> {
+		let data = self.decrypt_column(column, row_key, data)?;
+		Ok(String::from_utf8(data)?)
 	}
 }
 
@@ -78,6 +187,8 @@ pub(crate) struct Node {
 	id: i32,
 	address: std::net::SocketAddr,
 	password: Option<String>,
+	identity_key: Option<String>,
+	sync_cursor: i32,
 }
 
 impl Node {
@@ -87,20 +198,28 @@ impl Node {
 
 	common::accessor!(as_deref password -> Option<&str>);
 
+	common::accessor!(as_deref identity_key -> Option<&str>);
+
+	common::accessor!(copy sync_cursor -> i32);
+
 	#[inline]
 	#[must_use]
 	pub fn new(
 		id: i32,
 		address: std::net::SocketAddr,
 		password: Option<String>,
+		identity_key: Option<String>,
+		sync_cursor: i32,
 	) -> Self {
-		Self { id, address, password }
+		Self { id, address, password, identity_key, sync_cursor }
 	}
 }
 
-impl From<Node> for (std::net::SocketAddr, Option<String>) {
+impl From<Node> for (std::net::SocketAddr, Option<String>, Option<String>) {
 	#[inline]
-	fn from(n: Node) -> (std::net::SocketAddr, Option<String>) {
-		(n.address, n.password)
+	fn from(
+		n: Node,
+	) -> (std::net::SocketAddr, Option<String>, Option<String>) {
+		(n.address, n.password, n.identity_key)
 	}
 }
@@ -31,6 +31,32 @@ diesel::table! {
 		encrypted_address -> Bytea,
 		encrypted_password -> Nullable<Bytea>,
 		created_at -> Timestamp,
+		sync_cursor -> Int4,
+		encrypted_identity_key -> Nullable<Bytea>,
+	}
+}
+
+diesel::table! {
+	prekeys (id) {
+		id -> Int4,
+		user_id -> Int4,
+		public_key_hash -> Bytea,
+		encrypted_private_key -> Bytea,
+		created_at -> Timestamp,
+		last_resort -> Bool,
+	}
+}
+
+diesel::table! {
+	recovery_codes (id) {
+		id -> Int4,
+		user_id -> Int4,
+		code_hash -> Bytea,
+		wrapped_data_key -> Bytea,
+		argon2_memory_cost -> Int4,
+		argon2_time_cost -> Int4,
+		argon2_parallelism -> Int4,
+		created_at -> Timestamp,
 	}
 }
 
@@ -43,11 +69,25 @@ diesel::table! {
 		salt -> Bytea,
 		f2f_enabled -> Bool,
 		created_at -> Timestamp,
+		argon2_memory_cost -> Nullable<Int4>,
+		argon2_time_cost -> Nullable<Int4>,
+		argon2_parallelism -> Nullable<Int4>,
+		wrapped_data_key -> Nullable<Bytea>,
+		session_generation -> Int4,
 	}
 }
 
 diesel::joinable!(emails -> users (user_id));
 diesel::joinable!(friends -> users (user_id));
 diesel::joinable!(nodes -> users (user_id));
+diesel::joinable!(prekeys -> users (user_id));
+diesel::joinable!(recovery_codes -> users (user_id));
 
-diesel::allow_tables_to_appear_in_same_query!(emails, friends, nodes, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+	emails,
+	friends,
+	nodes,
+	prekeys,
+	recovery_codes,
+	users,
+);
@@ -3,6 +3,8 @@ use anyhow::{Context as _, Result};
 pub(crate) struct State {
 	config: crate::config::Config,
 	db: crate::db::Db,
+	progresses: crate::app::progress::Progresses,
+	session_store: crate::app::session::Store,
 	tera: tera::Tera,
 }
 
@@ -11,16 +13,27 @@ impl State {
 
 	common::accessor!(& db -> &crate::db::Db);
 
+	common::accessor!(& progresses -> &crate::app::progress::Progresses);
+
+	common::accessor!(& session_store -> &crate::app::session::Store);
+
 	common::accessor!(& tera -> &tera::Tera);
 
 	pub(crate) async fn try_default() -> Result<Self> {
-		Ok(Self {
-			config: crate::config::Config::load()
+		let config = crate::config::Config::load()
+			.await
+			.context("Failed to load the config.")?;
+		let session_store =
+			crate::app::session::Store::new(config.session_backend())
 				.await
-				.context("Failed to load the config.")?,
+				.context("Failed to make a session store.")?;
+		Ok(Self {
+			config,
 			db: crate::db::Db::connect()
 				.await
 				.context("Failed to connect to a db.")?,
+			progresses: crate::app::progress::Progresses::default(),
+			session_store,
 			tera: crate::app::tera::make_tera(),
 		})
 	}
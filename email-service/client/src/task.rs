@@ -1,20 +1,28 @@
 use anyhow::{Context as _, Result};
 
-/// Every `common::consts::CHECK_OLD_EMAILS_INTERVAL` removes emails that are
-/// older than `common:consts::EMAILS_MAX_AGE`.
+/// Every `check_old_emails_interval` from the config limits removes emails
+/// that are older than `emails_max_age` and unused prekeys that are older
+/// than twice of it: a prekey can be taken from a node right before the node
+/// deletes it, and then the email lives on the node for `emails_max_age`.
 pub(crate) async fn delete_old_emails_task(
 	state: actix_web::web::Data<crate::state::State>,
 ) -> Result<()> {
+	let limits = *state.config().limits().common();
 	loop {
-		tokio::time::sleep(common::consts::CHECK_OLD_EMAILS_INTERVAL).await;
+		tokio::time::sleep(limits.check_old_emails_interval()).await;
 		state
 			.db()
-			.delete_old_emails(common::consts::EMAILS_MAX_AGE)
+			.delete_old_emails(limits.emails_max_age())
 			.await
 			.context("Failed to delete old emails.")?;
+		state
+			.db()
+			.delete_old_prekeys(limits.emails_max_age() * 2)
+			.await
+			.context("Failed to delete old prekeys.")?;
 		common::debug!(
 			"Emails older than {} seconds were deleted.",
-			common::consts::EMAILS_MAX_AGE.as_secs()
+			limits.emails_max_age().as_secs()
 		);
 	}
 }
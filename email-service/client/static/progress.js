@@ -0,0 +1,29 @@
+"use strict";
+
+// Shows the progress of the request made by a form with the `data-progress`
+// attribute, while the page waits for the response.
+function watchProgress() {
+	$("form[data-progress]").on("submit", () => {
+		let block = $("#progress");
+		let bar = $("#progress-bar");
+		let message = $("#progress-message");
+
+		block.removeClass("d-none");
+		setInterval(() => {
+			fetch("/progress/", {credentials: "same-origin"})
+				.then((response) => response.json())
+				.then((progress) => {
+					if (progress === null) {
+						return;
+					}
+					message.text(progress.message);
+					if (progress.percent !== null) {
+						bar.css("width", `${progress.percent}%`);
+					}
+				})
+				.catch(() => {});
+		}, 1000);
+	});
+}
+
+$(document).ready(watchProgress);
@@ -0,0 +1,85 @@
+use crate::error::ValidateLimitsError;
+
+/// Limits shared by the node and the client configs. Each missing field
+/// defaults to the constant from [`consts`](crate::consts).
+///
+/// # Example
+///
+/// ```
+/// let limits: common::config::Limits =
+///     serde_json::from_str(r#"{"emails_max_age_secs": 3600}"#).unwrap();
+/// limits.validate().unwrap();
+/// assert_eq!(limits.emails_max_age().as_secs(), 3600);
+/// assert_eq!(
+///     limits.package_max_size(),
+///     common::consts::PACKAGE_MAX_SIZE,
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, serde::Deserialize)]
+#[serde(default)]
+pub struct Limits {
+	package_max_size: usize,
+	package_receive_timeout_secs: u64,
+	proof_of_work_difficulty: crate::email::ProofOfWorkDifficulty,
+	emails_max_age_secs: u64,
+	check_old_emails_interval_secs: u64,
+}
+
+impl Limits {
+	/// Packages must fit at least one chunk.
+	const PACKAGE_MIN_SIZE: usize = crate::consts::PACKAGE_CHUNK_SIZE;
+
+	crate::accessor!(copy package_max_size -> usize);
+
+	crate::accessor!(
+		copy proof_of_work_difficulty -> crate::email::ProofOfWorkDifficulty
+	);
+
+	#[inline]
+	#[must_use]
+	pub fn package_receive_timeout(&self) -> std::time::Duration {
+		std::time::Duration::from_secs(self.package_receive_timeout_secs)
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn emails_max_age(&self) -> std::time::Duration {
+		std::time::Duration::from_secs(self.emails_max_age_secs)
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn check_old_emails_interval(&self) -> std::time::Duration {
+		std::time::Duration::from_secs(self.check_old_emails_interval_secs)
+	}
+
+	pub fn validate(&self) -> Result<(), ValidateLimitsError> {
+		if self.package_max_size < Self::PACKAGE_MIN_SIZE {
+			return Err(ValidateLimitsError::PackageMaxSize(
+				Self::PACKAGE_MIN_SIZE,
+			));
+		} else if self.package_receive_timeout_secs == 0 {
+			return Err(ValidateLimitsError::PackageReceiveTimeout);
+		} else if self.emails_max_age_secs == 0 {
+			return Err(ValidateLimitsError::EmailsMaxAge);
+		} else if self.check_old_emails_interval_secs == 0 {
+			return Err(ValidateLimitsError::CheckOldEmailsInterval);
+		}
+		Ok(())
+	}
+}
+
+impl Default for Limits {
+	fn default() -> Self {
+		Self {
+			package_max_size: crate::consts::PACKAGE_MAX_SIZE,
+			package_receive_timeout_secs:
+				crate::consts::PACKAGE_CHUNK_RECEIVE_TIMEOUT.as_secs(),
+			proof_of_work_difficulty:
+				crate::email::ProofOfWorkDifficulty::default(),
+			emails_max_age_secs: crate::consts::EMAILS_MAX_AGE.as_secs(),
+			check_old_emails_interval_secs:
+				crate::consts::CHECK_OLD_EMAILS_INTERVAL.as_secs(),
+		}
+	}
+}
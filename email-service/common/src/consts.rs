@@ -1,19 +1,36 @@
 lazy_static::lazy_static! {
 	pub static ref DEBUG: bool = std::env::var("DEBUG").is_ok();
 	pub static ref DB_URL: String = std::env::var("DATABASE_URL").unwrap();
-
-	pub(crate) static ref PROOF_OF_WORK_DIFFICULTY_STRING: String =
-		"0".repeat(PROOF_OF_WORK_DIFFICULTY as usize);
 }
 
-pub(crate) const PACKAGE_MAX_SIZE: usize = 10 * 1024 * 1024; // 10 MiB
-pub(crate) const PACKAGE_RECEIVE_TIMEOUT: std::time::Duration =
+pub const PROTOCOL_VERSION: u16 = 2;
+pub const PACKAGE_MAX_SIZE: usize = 32 * 1024 * 1024; // 32 MiB
+pub(crate) const PACKAGE_CHUNK_SIZE: usize = 64 * 1024; // 64 KiB
+pub(crate) const PACKAGE_CHUNK_RECEIVE_TIMEOUT: std::time::Duration =
 	std::time::Duration::from_secs(5);
-pub(crate) const PROOF_OF_WORK_DIFFICULTY: u8 = 5;
+pub(crate) const SHA256_PROOF_OF_WORK_DIFFICULTY: u8 = 20;
+pub(crate) const SCRYPT_PROOF_OF_WORK_DIFFICULTY: u8 = 6;
+pub(crate) const SCRYPT_N: u64 = 1 << 14;
+pub(crate) const SCRYPT_R: u64 = 8;
+pub(crate) const SCRYPT_P: u64 = 1;
+pub(crate) const SCRYPT_MAX_MEMORY: u64 = 32 * 1024 * 1024; // 32 MiB
+pub(crate) const PROOF_OF_WORK_POLL_INTERVAL: std::time::Duration =
+	std::time::Duration::from_millis(10);
+pub(crate) const PROOF_OF_WORK_PROGRESS_INTERVAL: std::time::Duration =
+	std::time::Duration::from_secs(1);
+pub(crate) const EMAIL_FORMAT_MAGIC: &[u8] = b"email";
+pub(crate) const EMAIL_FORMAT_VERSION: u8 = 2;
 pub(crate) const DEFAULT_RANDOM_BYTES_LENGTH: usize = 32;
+pub(crate) const RSA_KEY_SIZE: u32 = 2048;
+pub(crate) const ARGON2_MEMORY_COST: u32 = 19 * 1024; // 19 MiB
+pub(crate) const ARGON2_TIME_COST: u32 = 2;
+pub(crate) const ARGON2_PARALLELISM: u32 = 1;
+pub(crate) const PASSWORD_VERIFIER_INFO: &[u8] = b"password-verifier";
+pub(crate) const PASSWORD_DATA_KEY_INFO: &[u8] = b"password-data-key";
+pub(crate) const CHALLENGE_SIGNATURE_PREFIX: &[u8] = b"mailbox-challenge";
+pub(crate) const PREKEY_SIGNATURE_PREFIX: &[u8] = b"prekey";
 
 pub const EMAILS_MAX_AGE: std::time::Duration =
 	std::time::Duration::from_secs(86400 * 2); // 2 days
 pub const CHECK_OLD_EMAILS_INTERVAL: std::time::Duration =
 	std::time::Duration::from_secs(86400); // 1 day
-pub const PASSWORD_SALT: &[u8; 13] = b"password-salt";
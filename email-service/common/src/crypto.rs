@@ -1,49 +1,108 @@
 use crate::error::{
 	AesDecryptBase64Error, AesDecryptError, AesDecryptStringError,
-	AesEncryptError, GenerateRandomBytesError,
+	AesEncryptError, DecapsulateError, DerivePasswordKeysError,
+	GeneratePrivateKeyError, GenerateRandomBytesError,
+	IdentityKeyFromPemError, KeyFromPemError, SignChallengeError,
+	VerifyChallengeError,
 };
 
+/// AES-256-GCM cipher.
+///
+/// Ciphertexts have the format `version || iv || data || tag`, where
+/// `version` is [`AES_CIPHERTEXT_VERSION`], `iv` is 12 random bytes and `tag`
+/// is 16 bytes. Ciphertexts without a version (`iv || data || tag` with a 16
+/// bytes `iv`), encrypted before it was added, can still be decrypted.
 pub struct AesCipher<'a> {
 	inner: openssl::symm::Cipher,
 	key: std::borrow::Cow<'a, [u8]>,
+	aad: std::borrow::Cow<'a, [u8]>,
 }
 
 impl<'a> AesCipher<'a> {
 	#[must_use]
 	pub fn new<K: Into<std::borrow::Cow<'a, [u8]>>>(key: K) -> Self {
-		Self { inner: openssl::symm::Cipher::aes_256_gcm(), key: key.into() }
+		Self {
+			inner: openssl::symm::Cipher::aes_256_gcm(),
+			key: key.into(),
+			aad: std::borrow::Cow::Borrowed(&[]),
+		}
+	}
+
+	/// Binds ciphertexts to the associated data `aad`.
+	///
+	/// The associated data is authenticated, but not encrypted, so a
+	/// ciphertext is decrypted only with the same associated data it was
+	/// encrypted with.
+	///
+	/// ```
+	/// use common::crypto::AesCipher;
+	///
+	/// let key = [0u8; 32];
+	/// let e = AesCipher::new(&key[..]).with_aad(&b"a"[..]).encrypt("x")?;
+	/// assert!(AesCipher::new(&key[..]).with_aad(&b"b"[..]).decrypt(&e).is_err());
+	/// assert_eq!(AesCipher::new(&key[..]).with_aad(&b"a"[..]).decrypt(&e)?, b"x");
+	/// # Ok::<_, Box<dyn std::error::Error>>(())
+	/// ```
+	#[must_use]
+	pub fn with_aad<A: Into<std::borrow::Cow<'a, [u8]>>>(
+		mut self,
+		aad: A,
+	) -> Self {
+		self.aad = aad.into();
+		self
 	}
 
 	pub fn encrypt<D>(&self, data: D) -> Result<Vec<u8>, AesEncryptError>
 	where
 		D: AsRef<[u8]>,
 	{
-		let iv = generate_random_bytes(Some(16))?;
-		let mut tag = [0u8; 16];
+		let iv = generate_random_bytes(Some(AES_IV_SIZE))?;
+		let mut tag = [0u8; AES_TAG_SIZE];
 		let rv = openssl::symm::encrypt_aead(
 			self.inner,
 			&self.key,
 			Some(&iv),
-			&[],
+			&self.aad,
 			data.as_ref(),
 			&mut tag,
 		)?;
-		Ok([iv, rv, tag.into()].concat())
+		Ok([&[AES_CIPHERTEXT_VERSION][..], &iv, &rv, &tag].concat())
 	}
 
+	/// Decrypts the `slice`, returning an error instead of panicking if it is
+	/// malformed.
+	///
+	/// ```
+	/// use common::{crypto::AesCipher, error::AesDecryptError};
+	///
+	/// let cipher = AesCipher::new(&[0u8; 32][..]);
+	/// let e = cipher.encrypt("data")?;
+	/// assert_eq!(cipher.decrypt(&e)?, b"data");
+	/// let rv = cipher.decrypt(&e[..8]);
+	/// assert!(matches!(rv, Err(AesDecryptError::InvalidCiphertext(8))));
+	/// # Ok::<_, Box<dyn std::error::Error>>(())
+	/// ```
 	pub fn decrypt(&self, slice: &[u8]) -> Result<Vec<u8>, AesDecryptError> {
-		let iv = &slice[..16];
-		let data = &slice[16..slice.len() - 16];
-		let tag = &slice[slice.len() - 16..];
-		let rv = openssl::symm::decrypt_aead(
-			self.inner,
-			&self.key,
-			Some(iv),
-			&[],
-			data,
-			tag,
-		)?;
-		Ok(rv)
+		let versioned = AesCiphertext::parse(slice);
+		let legacy = AesCiphertext::parse_legacy(slice);
+
+		// A ciphertext without a version may start with the version byte by
+		// chance, so both are tried
+		let mut error = AesDecryptError::InvalidCiphertext(slice.len());
+		for c in versioned.into_iter().chain(legacy) {
+			match openssl::symm::decrypt_aead(
+				self.inner,
+				&self.key,
+				Some(c.iv),
+				&self.aad,
+				c.data,
+				c.tag,
+			) {
+				Ok(rv) => return Ok(rv),
+				Err(e) => error = AesDecryptError::Decrypt(e),
+			}
+		}
+		Err(error)
 	}
 
 	pub fn decrypt_string(
@@ -64,6 +123,905 @@ impl<'a> AesCipher<'a> {
 	}
 }
 
+/// Version of the [`AesCipher`] ciphertext format, its first byte.
+pub const AES_CIPHERTEXT_VERSION: u8 = 1;
+
+/// Size of the [`AesCipher`] iv.
+const AES_IV_SIZE: usize = 12;
+
+/// Size of the [`AesCipher`] iv of ciphertexts without a version.
+const AES_LEGACY_IV_SIZE: usize = 16;
+
+/// Size of the [`AesCipher`] tag.
+const AES_TAG_SIZE: usize = 16;
+
+/// Parts of an [`AesCipher`] ciphertext.
+struct AesCiphertext<'a> {
+	iv: &'a [u8],
+	data: &'a [u8],
+	tag: &'a [u8],
+}
+
+impl<'a> AesCiphertext<'a> {
+	/// Parses a ciphertext of the [`AES_CIPHERTEXT_VERSION`], returning
+	/// [`None`] if it has another version or is too short.
+	fn parse(slice: &'a [u8]) -> Option<Self> {
+		match slice.split_first() {
+			Some((&AES_CIPHERTEXT_VERSION, rest)) => {
+				Self::split(rest, AES_IV_SIZE)
+			}
+			_ => None,
+		}
+	}
+
+	/// Parses a ciphertext without a version, returning [`None`] if it is too
+	/// short.
+	fn parse_legacy(slice: &'a [u8]) -> Option<Self> {
+		Self::split(slice, AES_LEGACY_IV_SIZE)
+	}
+
+	fn split(slice: &'a [u8], iv_size: usize) -> Option<Self> {
+		if slice.len() < iv_size + AES_TAG_SIZE {
+			return None;
+		}
+		let (iv, rest) = slice.split_at(iv_size);
+		let (data, tag) = rest.split_at(rest.len() - AES_TAG_SIZE);
+		Some(Self { iv, data, tag })
+	}
+}
+
+/// Cipher of an [`Envelope`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum EnvelopeCipher {
+	/// [`AesCipher`].
+	Aes256Gcm = 1,
+}
+
+impl EnvelopeCipher {
+	fn from_u8(byte: u8) -> Option<Self> {
+		match byte {
+			1 => Some(Self::Aes256Gcm),
+			_ => None,
+		}
+	}
+}
+
+/// How the key of an [`Envelope`] is derived from a password.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum KeyDerivation {
+	/// [`hash_with_salt`], fast to brute-force.
+	Sha256 = 1,
+	/// The data key of [`derive_password_keys`].
+	Argon2id = 2,
+	/// A random key, stored wrapped (encrypted) by a key of another
+	/// derivation, so that key can be changed without re-encrypting the data.
+	Wrapped = 3,
+}
+
+impl KeyDerivation {
+	fn from_u8(byte: u8) -> Option<Self> {
+		match byte {
+			1 => Some(Self::Sha256),
+			2 => Some(Self::Argon2id),
+			3 => Some(Self::Wrapped),
+			_ => None,
+		}
+	}
+}
+
+/// Versioned envelope of data encrypted at rest, with the format
+/// `magic || version || cipher || key derivation || ciphertext`.
+///
+/// The header is authenticated as associated data. It lets us change ciphers
+/// and key derivations: data in an outdated envelope or without one (a raw
+/// [`AesCipher`] ciphertext, written before envelopes were added) is still
+/// [`open`](Envelope::open)ed, and should be re-encrypted, if it is not
+/// [`is_current`](Envelope::is_current).
+///
+/// ```
+/// use common::crypto::{AesCipher, Envelope};
+///
+/// let key = [0u8; 32];
+/// let sealed = Envelope::CURRENT.seal(&key, b"aad", "data")?;
+/// assert!(Envelope::is_current(&sealed));
+/// assert_eq!(Envelope::open(&key, b"aad", &sealed)?, b"data");
+///
+/// let raw = AesCipher::new(&key[..]).with_aad(&b"aad"[..]).encrypt("data")?;
+/// assert!(!Envelope::is_current(&raw));
+/// assert_eq!(Envelope::open(&key, b"aad", &raw)?, b"data");
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Envelope {
+	cipher: EnvelopeCipher,
+	key_derivation: KeyDerivation,
+}
+
+impl Envelope {
+	/// The envelope new data is sealed in.
+	pub const CURRENT: Self =
+		Self::new(EnvelopeCipher::Aes256Gcm, KeyDerivation::Wrapped);
+
+	crate::accessor!(copy cipher -> EnvelopeCipher);
+
+	crate::accessor!(copy key_derivation -> KeyDerivation);
+
+	#[inline]
+	#[must_use]
+	pub const fn new(
+		cipher: EnvelopeCipher,
+		key_derivation: KeyDerivation,
+	) -> Self {
+		Self { cipher, key_derivation }
+	}
+
+	/// Parses the envelope of the `slice`, returning [`None`] if there is no
+	/// supported one.
+	#[must_use]
+	pub fn parse(slice: &[u8]) -> Option<Self> {
+		match *slice.get(..ENVELOPE_HEADER_SIZE)? {
+			[m0, m1, ENVELOPE_VERSION, cipher, key_derivation]
+				if [m0, m1] == ENVELOPE_MAGIC =>
+			{
+				Some(Self::new(
+					EnvelopeCipher::from_u8(cipher)?,
+					KeyDerivation::from_u8(key_derivation)?,
+				))
+			}
+			_ => None,
+		}
+	}
+
+	/// Checks that the `slice` is sealed in the [`CURRENT`](Envelope::CURRENT)
+	/// envelope.
+	#[must_use]
+	pub fn is_current(slice: &[u8]) -> bool {
+		Self::parse(slice) == Some(Self::CURRENT)
+	}
+
+	/// Encrypts the `data` with the `key` derived by the
+	/// [`key_derivation`](Envelope::key_derivation), binding it to the
+	/// associated data `aad`.
+	pub fn seal<D: AsRef<[u8]>>(
+		self,
+		key: &[u8],
+		aad: &[u8],
+		data: D,
+	) -> Result<Vec<u8>, AesEncryptError> {
+		let header = self.header();
+		let ciphertext = self.make_cipher(key, &header, aad).encrypt(data)?;
+		Ok([&header[..], &ciphertext].concat())
+	}
+
+	/// Decrypts the `slice` sealed in any supported envelope, or without one,
+	/// with the `key` and the associated data `aad`.
+	pub fn open(
+		key: &[u8],
+		aad: &[u8],
+		slice: &[u8],
+	) -> Result<Vec<u8>, AesDecryptError> {
+		let raw = || AesCipher::new(key).with_aad(aad).decrypt(slice);
+		match Self::parse(slice) {
+			// A raw ciphertext may start with the header by chance
+			Some(e) => e
+				.make_cipher(key, &e.header(), aad)
+				.decrypt(&slice[ENVELOPE_HEADER_SIZE..])
+				.or_else(|e| raw().map_err(|_| e)),
+			None => raw(),
+		}
+	}
+
+	/// The header that starts data sealed in the envelope, so the outdated
+	/// data can be found by it.
+	#[must_use]
+	pub const fn header(self) -> [u8; ENVELOPE_HEADER_SIZE] {
+		let [m0, m1] = ENVELOPE_MAGIC;
+		[
+			m0,
+			m1,
+			ENVELOPE_VERSION,
+			self.cipher as u8,
+			self.key_derivation as u8,
+		]
+	}
+
+	/// Makes the cipher, that also authenticates the `header`.
+	fn make_cipher(
+		self,
+		key: &[u8],
+		header: &[u8],
+		aad: &[u8],
+	) -> AesCipher<'static> {
+		match self.cipher {
+			EnvelopeCipher::Aes256Gcm => {
+				AesCipher::new(key.to_vec()).with_aad([header, aad].concat())
+			}
+		}
+	}
+}
+
+/// First bytes of an [`Envelope`].
+const ENVELOPE_MAGIC: [u8; 2] = [0xe5, 0x76];
+
+/// Version of the [`Envelope`] format.
+const ENVELOPE_VERSION: u8 = 1;
+
+/// Size of the [`Envelope`] header.
+const ENVELOPE_HEADER_SIZE: usize = 5;
+
+/// A static X25519 key of a node, used to authenticate the node in the
+/// [`Session::handshake`](crate::package::Session::handshake).
+///
+/// Clients pin [`public_key_base64`](IdentityKey::public_key_base64) of the
+/// key.
+pub struct IdentityKey(openssl::pkey::PKey<openssl::pkey::Private>);
+
+impl IdentityKey {
+	/// Reads an X25519 private key PEM, like generated by
+	/// `openssl genpkey -algorithm X25519`.
+	pub fn from_pem(pem: &[u8]) -> Result<Self, IdentityKeyFromPemError> {
+		let pkey = openssl::pkey::PKey::private_key_from_pem(pem)?;
+		if pkey.id() != openssl::pkey::Id::X25519 {
+			return Err(IdentityKeyFromPemError::NotX25519);
+		}
+		Ok(Self(pkey))
+	}
+
+	/// Raw public key encoded into [`base64`].
+	pub fn public_key_base64(
+		&self,
+	) -> Result<String, openssl::error::ErrorStack> {
+		Ok(base64::encode(self.0.raw_public_key()?))
+	}
+
+	#[inline]
+	#[must_use]
+	pub(crate) fn pkey(
+		&self,
+	) -> &openssl::pkey::PKeyRef<openssl::pkey::Private> {
+		&self.0
+	}
+}
+
+/// A type of the user [`PrivateKey`].
+#[derive(
+	Clone,
+	Copy,
+	Debug,
+	Default,
+	Eq,
+	PartialEq,
+	serde::Deserialize,
+	serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyType {
+	/// RSA with `consts::RSA_KEY_SIZE` bits.
+	Rsa,
+	/// X25519 and Ed25519, much smaller and faster than RSA.
+	#[default]
+	Curve25519,
+	/// [`Curve25519`](KeyType::Curve25519) with an ML-KEM-768 key, so email
+	/// sessions are [hybrid](Kem::X25519MlKem768). Requires OpenSSL 3.5+.
+	Curve25519MlKem768,
+}
+
+impl KeyType {
+	/// Whether keys of this type can be generated and read, ML-KEM-768 ones
+	/// need the `ml-kem` feature and OpenSSL 3.5+ at run time.
+	#[must_use]
+	pub fn is_supported(self) -> bool {
+		self != Self::Curve25519MlKem768 || ml_kem::is_supported()
+	}
+}
+
+/// A key encapsulation mechanism of an email session, made by
+/// [`PublicKey::encapsulate`].
+#[derive(
+	Clone,
+	Copy,
+	Debug,
+	Default,
+	Eq,
+	PartialEq,
+	serde::Deserialize,
+	serde::Serialize,
+)]
+pub enum Kem {
+	/// RSA-OAEP or X25519, depending on the recipient key.
+	#[default]
+	Classic,
+	/// X25519 and ML-KEM-768, so the session stays secret as long as one of
+	/// them is not broken, even if the email is recorded now and decrypted
+	/// later with a quantum computer.
+	X25519MlKem768,
+}
+
+/// A user private key, used to decrypt received emails, to sign sent emails
+/// and to authenticate the mailbox.
+///
+/// RSA keys use OAEP padding for email sessions and PSS padding for
+/// signatures. Curve25519 keys are pairs of an X25519 key for email sessions
+/// and an Ed25519 key for signatures, so their PEM consists of two blocks:
+/// Ed25519 first, X25519 second. An optional third block is an ML-KEM-768
+/// key, which advertises [`Kem::X25519MlKem768`] to senders.
+///
+/// # Example
+///
+/// ```
+/// use common::crypto::{KeyType, PrivateKey, PublicKey};
+///
+/// let private_key = PrivateKey::generate(KeyType::Curve25519)?;
+/// let pem = private_key.private_key_to_pem()?;
+/// assert_eq!(PrivateKey::from_pem(&pem)?.key_type(), KeyType::Curve25519);
+///
+/// let public_key = PublicKey::from_pem(&private_key.public_key_to_pem()?)?;
+/// let signature = private_key.sign(b"data")?;
+/// assert!(public_key.verify(b"data", &signature)?);
+///
+/// let (session, e_session, kem) = public_key.encapsulate()?;
+/// assert_eq!(private_key.decapsulate(kem, &e_session)?, session);
+/// # Ok::<_, anyhow::Error>(())
+/// ```
+pub enum PrivateKey {
+	Rsa(openssl::rsa::Rsa<openssl::pkey::Private>),
+	Curve25519 {
+		signing: openssl::pkey::PKey<openssl::pkey::Private>,
+		agreement: openssl::pkey::PKey<openssl::pkey::Private>,
+		ml_kem: Option<openssl::pkey::PKey<openssl::pkey::Private>>,
+	},
+}
+
+impl PrivateKey {
+	pub fn generate(
+		key_type: KeyType,
+	) -> Result<Self, GeneratePrivateKeyError> {
+		if !key_type.is_supported() {
+			return Err(GeneratePrivateKeyError::UnsupportedKeyType(key_type));
+		}
+		Ok(match key_type {
+			KeyType::Rsa => Self::Rsa(openssl::rsa::Rsa::generate(
+				crate::consts::RSA_KEY_SIZE,
+			)?),
+			KeyType::Curve25519 | KeyType::Curve25519MlKem768 => {
+				Self::Curve25519 {
+					signing: openssl::pkey::PKey::generate_ed25519()?,
+					agreement: openssl::pkey::PKey::generate_x25519()?,
+					ml_kem: (key_type == KeyType::Curve25519MlKem768)
+						.then(ml_kem::generate)
+						.transpose()?,
+				}
+			}
+		})
+	}
+
+	/// Reads the PEM of an RSA key or of a Curve25519 key pair, optionally
+	/// followed by an ML-KEM-768 key.
+	pub fn from_pem(pem: &[u8]) -> Result<Self, KeyFromPemError> {
+		match split_pem_blocks(pem)[..] {
+			[rsa] => {
+				Ok(Self::Rsa(openssl::rsa::Rsa::private_key_from_pem(rsa)?))
+			}
+			[signing, agreement, ref ml_kem @ ..] if ml_kem.len() <= 1 => {
+				let signing =
+					openssl::pkey::PKey::private_key_from_pem(signing)?;
+				let agreement =
+					openssl::pkey::PKey::private_key_from_pem(agreement)?;
+				let ml_kem = ml_kem
+					.first()
+					.map(|k| openssl::pkey::PKey::private_key_from_pem(k))
+					.transpose()?;
+				check_curve25519_keys(
+					&signing,
+					&agreement,
+					ml_kem.as_deref(),
+				)?;
+				Ok(Self::Curve25519 { signing, agreement, ml_kem })
+			}
+			_ => Err(KeyFromPemError::InvalidKeys),
+		}
+	}
+
+	#[must_use]
+	pub fn key_type(&self) -> KeyType {
+		match self {
+			Self::Rsa(_) => KeyType::Rsa,
+			Self::Curve25519 { ml_kem: None, .. } => KeyType::Curve25519,
+			Self::Curve25519 { ml_kem: Some(_), .. } => {
+				KeyType::Curve25519MlKem768
+			}
+		}
+	}
+
+	pub fn private_key_to_pem(
+		&self,
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		match self {
+			Self::Rsa(k) => k.private_key_to_pem(),
+			Self::Curve25519 { signing, agreement, ml_kem } => {
+				let mut pem = [
+					signing.private_key_to_pem_pkcs8()?,
+					agreement.private_key_to_pem_pkcs8()?,
+				]
+				.concat();
+				if let Some(ml_kem) = ml_kem {
+					pem.extend(ml_kem.private_key_to_pem_pkcs8()?);
+				}
+				Ok(pem)
+			}
+		}
+	}
+
+	/// See [`PublicKey::from_pem`].
+	pub fn public_key_to_pem(
+		&self,
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		match self {
+			Self::Rsa(k) => k.public_key_to_pem(),
+			Self::Curve25519 { signing, agreement, ml_kem } => {
+				curve25519_public_key_to_pem(
+					signing,
+					agreement,
+					ml_kem.as_deref(),
+				)
+			}
+		}
+	}
+
+	/// Signs the `data` with PKCS1-PSS padding and SHA-256 or with Ed25519.
+	pub fn sign(
+		&self,
+		data: &[u8],
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		match self {
+			Self::Rsa(k) => {
+				let pkey = openssl::pkey::PKey::from_rsa(k.clone())?;
+				let mut signer = openssl::sign::Signer::new(
+					openssl::hash::MessageDigest::sha256(),
+					&pkey,
+				)?;
+				signer.set_rsa_padding(openssl::rsa::Padding::PKCS1_PSS)?;
+				signer.update(data)?;
+				signer.sign_to_vec()
+			}
+			Self::Curve25519 { signing, .. } => {
+				openssl::sign::Signer::new_without_digest(signing)?
+					.sign_oneshot_to_vec(data)
+			}
+		}
+	}
+
+	/// Reverses [`PublicKey::encapsulate`], returning the session.
+	pub fn decapsulate(
+		&self,
+		kem: Kem,
+		e_session: &[u8],
+	) -> Result<Vec<u8>, DecapsulateError> {
+		match (self, kem) {
+			(Self::Rsa(k), Kem::Classic) => {
+				let mut session = vec![0; k.size() as usize];
+				let length = k.private_decrypt(
+					e_session,
+					&mut session,
+					openssl::rsa::Padding::PKCS1_OAEP,
+				)?;
+				session.truncate(length);
+				Ok(session)
+			}
+			(Self::Curve25519 { agreement, .. }, Kem::Classic) => {
+				let shared = x25519(agreement, e_session)?;
+				Ok(derive_x25519_session(
+					&[&shared],
+					e_session,
+					&agreement.raw_public_key()?,
+				)
+				.to_vec())
+			}
+			(
+				Self::Curve25519 { agreement, ml_kem: Some(ml_kem), .. },
+				Kem::X25519MlKem768,
+			) => {
+				if e_session.len() <= X25519_PUBLIC_KEY_SIZE {
+					return Err(DecapsulateError::InvalidSession);
+				}
+				let (ephemeral_public_key, ciphertext) =
+					e_session.split_at(X25519_PUBLIC_KEY_SIZE);
+				let shared = x25519(agreement, ephemeral_public_key)?;
+				let ml_kem_shared = ml_kem::decapsulate(ml_kem, ciphertext)?;
+				Ok(derive_x25519_session(
+					&[&ml_kem_shared, &shared],
+					ephemeral_public_key,
+					&agreement.raw_public_key()?,
+				)
+				.to_vec())
+			}
+			_ => Err(DecapsulateError::UnsupportedKem(kem)),
+		}
+	}
+}
+
+/// A user public key. See [`PrivateKey`].
+pub enum PublicKey {
+	Rsa(openssl::rsa::Rsa<openssl::pkey::Public>),
+	Curve25519 {
+		signing: openssl::pkey::PKey<openssl::pkey::Public>,
+		agreement: openssl::pkey::PKey<openssl::pkey::Public>,
+		ml_kem: Option<openssl::pkey::PKey<openssl::pkey::Public>>,
+	},
+}
+
+impl PublicKey {
+	/// Reads the PEM made by [`PrivateKey::public_key_to_pem`].
+	pub fn from_pem(pem: &[u8]) -> Result<Self, KeyFromPemError> {
+		match split_pem_blocks(pem)[..] {
+			[rsa] => {
+				Ok(Self::Rsa(openssl::rsa::Rsa::public_key_from_pem(rsa)?))
+			}
+			[signing, agreement, ref ml_kem @ ..] if ml_kem.len() <= 1 => {
+				let signing =
+					openssl::pkey::PKey::public_key_from_pem(signing)?;
+				let agreement =
+					openssl::pkey::PKey::public_key_from_pem(agreement)?;
+				let ml_kem = ml_kem
+					.first()
+					.map(|k| openssl::pkey::PKey::public_key_from_pem(k))
+					.transpose()?;
+				check_curve25519_keys(
+					&signing,
+					&agreement,
+					ml_kem.as_deref(),
+				)?;
+				Ok(Self::Curve25519 { signing, agreement, ml_kem })
+			}
+			_ => Err(KeyFromPemError::InvalidKeys),
+		}
+	}
+
+	#[must_use]
+	pub fn key_type(&self) -> KeyType {
+		match self {
+			Self::Rsa(_) => KeyType::Rsa,
+			Self::Curve25519 { ml_kem: None, .. } => KeyType::Curve25519,
+			Self::Curve25519 { ml_kem: Some(_), .. } => {
+				KeyType::Curve25519MlKem768
+			}
+		}
+	}
+
+	pub fn to_pem(&self) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		match self {
+			Self::Rsa(k) => k.public_key_to_pem(),
+			Self::Curve25519 { signing, agreement, ml_kem } => {
+				curve25519_public_key_to_pem(
+					signing,
+					agreement,
+					ml_kem.as_deref(),
+				)
+			}
+		}
+	}
+
+	/// Checks the `signature` of the `data` made by [`PrivateKey::sign`].
+	pub fn verify(
+		&self,
+		data: &[u8],
+		signature: &[u8],
+	) -> Result<bool, openssl::error::ErrorStack> {
+		match self {
+			Self::Rsa(k) => {
+				let pkey = openssl::pkey::PKey::from_rsa(k.clone())?;
+				let mut verifier = openssl::sign::Verifier::new(
+					openssl::hash::MessageDigest::sha256(),
+					&pkey,
+				)?;
+				verifier.set_rsa_padding(openssl::rsa::Padding::PKCS1_PSS)?;
+				verifier.update(data)?;
+				verifier.verify(signature)
+			}
+			Self::Curve25519 { signing, .. } => {
+				openssl::sign::Verifier::new_without_digest(signing)?
+					.verify_oneshot(signature, data)
+			}
+		}
+	}
+
+	/// Makes a random session and encrypts it for the owner of the key.
+	/// Returns the session, the encrypted session and the [`Kem`] of it.
+	///
+	/// With RSA the session is encrypted with OAEP padding. With Curve25519
+	/// the encrypted session is a raw ephemeral X25519 public key, and the
+	/// session is derived from the shared secret with the recipient key. If
+	/// the key has ML-KEM-768, the ML-KEM ciphertext is appended to the
+	/// encrypted session, and the session is derived from both shared
+	/// secrets.
+	pub fn encapsulate(
+		&self,
+	) -> Result<(Vec<u8>, Vec<u8>, Kem), openssl::error::ErrorStack> {
+		match self {
+			Self::Rsa(k) => {
+				let mut session =
+					vec![0; crate::consts::DEFAULT_RANDOM_BYTES_LENGTH];
+				openssl::rand::rand_bytes(&mut session)?;
+				let mut e_session = vec![0; k.size() as usize];
+				k.public_encrypt(
+					&session,
+					&mut e_session,
+					openssl::rsa::Padding::PKCS1_OAEP,
+				)?;
+				Ok((session, e_session, Kem::Classic))
+			}
+			Self::Curve25519 { agreement, ml_kem, .. } => {
+				let ephemeral_key = generate_x25519_key()?;
+				let mut e_session = ephemeral_key.raw_public_key()?;
+				let recipient_public_key = agreement.raw_public_key()?;
+				let shared = x25519(&ephemeral_key, &recipient_public_key)?;
+				let (shared_secrets, kem) = match ml_kem {
+					Some(ml_kem) => {
+						let (ml_kem_shared, ciphertext) =
+							ml_kem::encapsulate(ml_kem)?;
+						e_session.extend(ciphertext);
+						(vec![ml_kem_shared, shared], Kem::X25519MlKem768)
+					}
+					None => (vec![shared], Kem::Classic),
+				};
+				let session = derive_x25519_session(
+					&shared_secrets,
+					&e_session[..X25519_PUBLIC_KEY_SIZE],
+					&recipient_public_key,
+				);
+				Ok((session.to_vec(), e_session, kem))
+			}
+		}
+	}
+}
+
+/// A one-time X25519 prekey of a user, giving emails forward secrecy.
+///
+/// The owner publishes [signed](Prekey::sign) prekeys to nodes and keeps the
+/// private ones until they are used or expire. A sender takes one of them from
+/// a node (which deletes it) and passes it to
+/// [`Email::new`](crate::email::Email::new), which combines the session
+/// encapsulated for the long-term key with an X25519 shared secret of the
+/// prekey. After the prekey is deleted, a leaked long-term key is not enough
+/// to decrypt the email.
+///
+/// # Example
+///
+/// ```
+/// use common::crypto::{KeyType, Prekey, PrivateKey, PublicKey};
+///
+/// let private_key = PrivateKey::generate(KeyType::Curve25519)?;
+/// let public_key = PublicKey::from_pem(&private_key.public_key_to_pem()?)?;
+/// let prekey = Prekey::generate()?;
+/// let signed_prekey = prekey.sign(&private_key)?;
+/// assert!(signed_prekey.verify(&public_key)?);
+///
+/// let (session, ephemeral_public_key) =
+///     signed_prekey.encapsulate(b"session")?;
+/// assert_eq!(prekey.decapsulate(b"session", &ephemeral_public_key)?, session);
+/// # Ok::<_, anyhow::Error>(())
+/// ```
+pub struct Prekey(openssl::pkey::PKey<openssl::pkey::Private>);
+
+impl Prekey {
+	pub fn generate() -> Result<Self, openssl::error::ErrorStack> {
+		Ok(Self(generate_x25519_key()?))
+	}
+
+	/// Reads the raw private key made by
+	/// [`raw_private_key`](Prekey::raw_private_key).
+	pub fn from_raw_private_key(
+		bytes: &[u8],
+	) -> Result<Self, openssl::error::ErrorStack> {
+		Ok(Self(openssl::pkey::PKey::private_key_from_raw_bytes(
+			bytes,
+			openssl::pkey::Id::X25519,
+		)?))
+	}
+
+	pub fn raw_private_key(
+		&self,
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		self.0.raw_private_key()
+	}
+
+	pub fn raw_public_key(
+		&self,
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		self.0.raw_public_key()
+	}
+
+	/// Signs the public key with the long-term `private_key` (prefixed with
+	/// `consts::PREKEY_SIGNATURE_PREFIX`), so nodes can't substitute it.
+	pub fn sign(
+		&self,
+		private_key: &PrivateKey,
+	) -> Result<SignedPrekey, openssl::error::ErrorStack> {
+		let public_key = self.raw_public_key()?;
+		let signature = private_key.sign(
+			&[crate::consts::PREKEY_SIGNATURE_PREFIX, &public_key].concat(),
+		)?;
+		Ok(SignedPrekey { public_key, signature })
+	}
+
+	/// Reverses [`SignedPrekey::encapsulate`], returning the new session.
+	pub fn decapsulate(
+		&self,
+		session: &[u8],
+		ephemeral_public_key: &[u8],
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		let shared = x25519(&self.0, ephemeral_public_key)?;
+		Ok(derive_prekey_session(
+			session,
+			&shared,
+			ephemeral_public_key,
+			&self.raw_public_key()?,
+		)
+		.to_vec())
+	}
+}
+
+/// A public [`Prekey`] with the signature of its owner.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct SignedPrekey {
+	public_key: Vec<u8>,
+	signature: Vec<u8>,
+}
+
+impl SignedPrekey {
+	crate::accessor!(& public_key -> &[u8]);
+
+	/// Checks that the prekey is signed by the owner of `public_key`.
+	pub fn verify(
+		&self,
+		public_key: &PublicKey,
+	) -> Result<bool, openssl::error::ErrorStack> {
+		if self.public_key.len() != X25519_PUBLIC_KEY_SIZE {
+			return Ok(false);
+		}
+		public_key.verify(
+			&[crate::consts::PREKEY_SIGNATURE_PREFIX, &self.public_key]
+				.concat(),
+			&self.signature,
+		)
+	}
+
+	/// Mixes the X25519 shared secret of the prekey and an ephemeral key into
+	/// the `session`. Returns the new session and the raw ephemeral public
+	/// key.
+	///
+	/// Check the signature with [`verify`](SignedPrekey::verify) before.
+	pub fn encapsulate(
+		&self,
+		session: &[u8],
+	) -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+		let ephemeral_key = generate_x25519_key()?;
+		let ephemeral_public_key = ephemeral_key.raw_public_key()?;
+		let shared = x25519(&ephemeral_key, &self.public_key)?;
+		let session = derive_prekey_session(
+			session,
+			&shared,
+			&ephemeral_public_key,
+			&self.public_key,
+		);
+		Ok((session.to_vec(), ephemeral_public_key))
+	}
+}
+
+/// The size of a raw X25519 public key.
+const X25519_PUBLIC_KEY_SIZE: usize = 32;
+
+/// Derives an email session from the `shared_secrets` (an X25519 one last),
+/// binding it to both X25519 public keys.
+fn derive_x25519_session<T: AsRef<[u8]>>(
+	shared_secrets: &[T],
+	ephemeral_public_key: &[u8],
+	recipient_public_key: &[u8],
+) -> [u8; 32] {
+	let mut parts: Vec<&[u8]> =
+		shared_secrets.iter().map(AsRef::as_ref).collect();
+	parts.extend([ephemeral_public_key, recipient_public_key]);
+	hash(parts.concat())
+}
+
+/// Derives a new email session from the `session` encapsulated for the
+/// long-term key and the X25519 `shared` secret of a [`Prekey`].
+fn derive_prekey_session(
+	session: &[u8],
+	shared: &[u8],
+	ephemeral_public_key: &[u8],
+	prekey_public_key: &[u8],
+) -> [u8; 32] {
+	hash([session, shared, ephemeral_public_key, prekey_public_key].concat())
+}
+
+/// Makes the PEM of the Curve25519 public keys, see [`PrivateKey`].
+fn curve25519_public_key_to_pem<T: openssl::pkey::HasPublic>(
+	signing: &openssl::pkey::PKeyRef<T>,
+	agreement: &openssl::pkey::PKeyRef<T>,
+	ml_kem: Option<&openssl::pkey::PKeyRef<T>>,
+) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+	let mut pem =
+		[signing.public_key_to_pem()?, agreement.public_key_to_pem()?]
+			.concat();
+	if let Some(ml_kem) = ml_kem {
+		pem.extend(ml_kem.public_key_to_pem()?);
+	}
+	Ok(pem)
+}
+
+/// Checks that the keys of a Curve25519 pair are Ed25519 and X25519, and
+/// the optional one is ML-KEM-768.
+fn check_curve25519_keys<T>(
+	signing: &openssl::pkey::PKeyRef<T>,
+	agreement: &openssl::pkey::PKeyRef<T>,
+	ml_kem: Option<&openssl::pkey::PKeyRef<T>>,
+) -> Result<(), KeyFromPemError> {
+	if signing.id() != openssl::pkey::Id::ED25519
+		|| agreement.id() != openssl::pkey::Id::X25519
+		|| !ml_kem.is_none_or(ml_kem::is_ml_kem)
+	{
+		return Err(KeyFromPemError::InvalidKeys);
+	}
+	Ok(())
+}
+
+/// Splits the `pem` into blocks, each one ending with an `-----END ...`
+/// line.
+fn split_pem_blocks(pem: &[u8]) -> Vec<&[u8]> {
+	const END: &[u8] = b"-----END ";
+
+	let mut blocks = vec![];
+	let mut rest = pem;
+	while let Some(i) = rest.windows(END.len()).position(|w| w == END) {
+		let end = rest[i..]
+			.iter()
+			.position(|&b| b == b'\n')
+			.map_or(rest.len(), |j| i + j + 1);
+		blocks.push(&rest[..end]);
+		rest = &rest[end..];
+	}
+	blocks
+}
+
+/// Generates an ephemeral X25519 key.
+pub(crate) fn generate_x25519_key() -> Result<
+	openssl::pkey::PKey<openssl::pkey::Private>,
+	openssl::error::ErrorStack,
+> {
+	openssl::pkey::PKey::generate_x25519()
+}
+
+/// X25519 Diffie-Hellman of the `private_key` and the raw `public_key`.
+pub(crate) fn x25519(
+	private_key: &openssl::pkey::PKeyRef<openssl::pkey::Private>,
+	public_key: &[u8],
+) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+	let public_key = openssl::pkey::PKey::public_key_from_raw_bytes(
+		public_key,
+		openssl::pkey::Id::X25519,
+	)?;
+	let mut deriver = openssl::derive::Deriver::new(private_key)?;
+	deriver.set_peer(&public_key)?;
+	deriver.derive_to_vec()
+}
+
+/// HMAC-SHA-256 of the `data` with the `key`.
+pub fn hmac(
+	key: &[u8],
+	data: &[u8],
+) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+	let pkey = openssl::pkey::PKey::hmac(key)?;
+	let mut signer = openssl::sign::Signer::new(
+		openssl::hash::MessageDigest::sha256(),
+		&pkey,
+	)?;
+	signer.update(data)?;
+	signer.sign_to_vec()
+}
+
 /// Generates a random bytes with `getrandom::getrandom`.
 ///
 /// # Params
@@ -86,6 +1044,99 @@ pub fn hash<T: AsRef<[u8]>>(data: T) -> [u8; 32] {
 	openssl::sha::sha256(data.as_ref())
 }
 
+/// Tunable parameters of Argon2id. They must be stored with the keys derived
+/// by [`derive_password_keys`], since other parameters derive other keys.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Argon2Params {
+	memory_cost: u32,
+	time_cost: u32,
+	parallelism: u32,
+}
+
+impl Argon2Params {
+	crate::accessor!(copy memory_cost -> u32);
+
+	crate::accessor!(copy time_cost -> u32);
+
+	crate::accessor!(copy parallelism -> u32);
+
+	/// The `memory_cost` is in KiB, the `time_cost` is the number of passes
+	/// over the memory and the `parallelism` is the number of lanes.
+	#[inline]
+	#[must_use]
+	pub const fn new(
+		memory_cost: u32,
+		time_cost: u32,
+		parallelism: u32,
+	) -> Self {
+		Self { memory_cost, time_cost, parallelism }
+	}
+}
+
+impl Default for Argon2Params {
+	#[inline]
+	fn default() -> Self {
+		Self::new(
+			crate::consts::ARGON2_MEMORY_COST,
+			crate::consts::ARGON2_TIME_COST,
+			crate::consts::ARGON2_PARALLELISM,
+		)
+	}
+}
+
+/// Keys derived from a password by [`derive_password_keys`].
+pub struct PasswordKeys {
+	verifier: Vec<u8>,
+	data_key: Vec<u8>,
+}
+
+impl PasswordKeys {
+	// Stored to check the password
+	crate::accessor!(& verifier -> &[u8]);
+
+	// Encrypts data with `KeyDerivation::Argon2id`, must not be stored
+	crate::accessor!(& data_key -> &[u8]);
+}
+
+/// Hashes the `password` and the random `salt` by Argon2id with the `params`
+/// and derives independent keys from the hash.
+///
+/// Slow by design, so it is better to use this in conjunction with
+/// [`tokio::task::spawn_blocking`].
+///
+/// ```
+/// use common::crypto::{derive_password_keys, Argon2Params};
+///
+/// let params = Argon2Params::new(64, 1, 1);
+/// let keys = derive_password_keys("password", b"some salt", params)?;
+/// let other_keys = derive_password_keys("password", b"some salt", params)?;
+/// assert_eq!(keys.verifier(), other_keys.verifier());
+/// assert_ne!(keys.verifier(), keys.data_key());
+/// # Ok::<_, Box<dyn std::error::Error>>(())
+/// ```
+pub fn derive_password_keys<T: AsRef<[u8]>>(
+	password: T,
+	salt: &[u8],
+	params: Argon2Params,
+) -> Result<PasswordKeys, DerivePasswordKeysError> {
+	let argon2 = argon2::Argon2::new(
+		argon2::Algorithm::Argon2id,
+		argon2::Version::V0x13,
+		argon2::Params::new(
+			params.memory_cost,
+			params.time_cost,
+			params.parallelism,
+			None,
+		)?,
+	);
+	let mut master_key = [0; 32];
+	argon2.hash_password_into(password.as_ref(), salt, &mut master_key)?;
+	Ok(PasswordKeys {
+		verifier: hmac(&master_key, crate::consts::PASSWORD_VERIFIER_INFO)?,
+		data_key: hmac(&master_key, crate::consts::PASSWORD_DATA_KEY_INFO)?,
+	})
+}
+
 /// SHA-256 hashing with salt.
 #[must_use]
 pub fn hash_with_salt<T, U>(data: T, salt: U) -> [u8; 32]
@@ -95,3 +1146,253 @@ where
 {
 	hash([data.as_ref(), salt.as_ref()].concat())
 }
+
+/// Signs the `nonce` of a challenge (prefixed with
+/// `consts::CHALLENGE_SIGNATURE_PREFIX`, so the signature can't be reused for
+/// anything else) with the `private_key`.
+pub fn sign_challenge(
+	private_key: &PrivateKey,
+	nonce: &[u8],
+) -> Result<Vec<u8>, SignChallengeError> {
+	Ok(private_key
+		.sign(&[crate::consts::CHALLENGE_SIGNATURE_PREFIX, nonce].concat())?)
+}
+
+/// Checks the `signature` of the challenge `nonce` made by
+/// [`sign_challenge`] with the private key of `public_key_pem`.
+pub fn verify_challenge(
+	public_key_pem: &[u8],
+	nonce: &[u8],
+	signature: &[u8],
+) -> Result<bool, VerifyChallengeError> {
+	PublicKey::from_pem(public_key_pem)?
+		.verify(
+			&[crate::consts::CHALLENGE_SIGNATURE_PREFIX, nonce].concat(),
+			signature,
+		)
+		.map_err(VerifyChallengeError::Verify)
+}
+
+/// ML-KEM-768 of OpenSSL 3.5+, which is not wrapped by [`openssl`] yet.
+///
+/// The bindings are declared here, so they are behind the `ml-kem` feature
+/// and OpenSSL is checked at run time, see [`KeyType::is_supported`].
+#[cfg(feature = "ml-kem")]
+mod ml_kem {
+	use {
+		foreign_types::{ForeignType as _, ForeignTypeRef as _},
+		std::os::raw::{c_char, c_int, c_void},
+	};
+
+	const ALGORITHM: &[u8] = b"ML-KEM-768\0";
+
+	extern "C" {
+		fn EVP_PKEY_CTX_new_from_name(
+			libctx: *mut c_void,
+			name: *const c_char,
+			propquery: *const c_char,
+		) -> *mut openssl_sys::EVP_PKEY_CTX;
+		fn EVP_PKEY_is_a(
+			pkey: *const openssl_sys::EVP_PKEY,
+			name: *const c_char,
+		) -> c_int;
+		fn EVP_PKEY_encapsulate_init(
+			ctx: *mut openssl_sys::EVP_PKEY_CTX,
+			params: *const c_void,
+		) -> c_int;
+		fn EVP_PKEY_encapsulate(
+			ctx: *mut openssl_sys::EVP_PKEY_CTX,
+			wrapped_key: *mut u8,
+			wrapped_key_length: *mut usize,
+			generated_key: *mut u8,
+			generated_key_length: *mut usize,
+		) -> c_int;
+		fn EVP_PKEY_decapsulate_init(
+			ctx: *mut openssl_sys::EVP_PKEY_CTX,
+			params: *const c_void,
+		) -> c_int;
+		fn EVP_PKEY_decapsulate(
+			ctx: *mut openssl_sys::EVP_PKEY_CTX,
+			unwrapped_key: *mut u8,
+			unwrapped_key_length: *mut usize,
+			wrapped_key: *const u8,
+			wrapped_key_length: usize,
+		) -> c_int;
+	}
+
+	/// Frees the context when dropped.
+	struct Context(*mut openssl_sys::EVP_PKEY_CTX);
+
+	impl Context {
+		fn new<T>(
+			key: &openssl::pkey::PKeyRef<T>,
+		) -> Result<Self, openssl::error::ErrorStack> {
+			// SAFETY: the key is valid and the context holds its reference
+			let ctx = unsafe {
+				openssl_sys::EVP_PKEY_CTX_new(
+					key.as_ptr(),
+					std::ptr::null_mut(),
+				)
+			};
+			Self::check(ctx)
+		}
+
+		fn check(
+			ctx: *mut openssl_sys::EVP_PKEY_CTX,
+		) -> Result<Self, openssl::error::ErrorStack> {
+			if ctx.is_null() {
+				return Err(openssl::error::ErrorStack::get());
+			}
+			Ok(Self(ctx))
+		}
+	}
+
+	impl Drop for Context {
+		fn drop(&mut self) {
+			// SAFETY: the context is valid and not used after
+			unsafe { openssl_sys::EVP_PKEY_CTX_free(self.0) }
+		}
+	}
+
+	/// Whether the linked OpenSSL has ML-KEM-768, older versions of OpenSSL 3
+	/// have the bindings, but not the algorithm.
+	#[must_use]
+	pub(super) fn is_supported() -> bool {
+		openssl::version::number() >= 0x3050_0000
+	}
+
+	fn check(rv: c_int) -> Result<(), openssl::error::ErrorStack> {
+		if rv <= 0 {
+			return Err(openssl::error::ErrorStack::get());
+		}
+		Ok(())
+	}
+
+	pub(super) fn generate() -> Result<
+		openssl::pkey::PKey<openssl::pkey::Private>,
+		openssl::error::ErrorStack,
+	> {
+		// SAFETY: the name is null-terminated, the generated key is owned by
+		// the returned `PKey`
+		unsafe {
+			let ctx = Context::check(EVP_PKEY_CTX_new_from_name(
+				std::ptr::null_mut(),
+				ALGORITHM.as_ptr().cast(),
+				std::ptr::null(),
+			))?;
+			check(openssl_sys::EVP_PKEY_keygen_init(ctx.0))?;
+			let mut key = std::ptr::null_mut();
+			check(openssl_sys::EVP_PKEY_keygen(ctx.0, &raw mut key))?;
+			Ok(openssl::pkey::PKey::from_ptr(key))
+		}
+	}
+
+	#[must_use]
+	pub(super) fn is_ml_kem<T>(key: &openssl::pkey::PKeyRef<T>) -> bool {
+		is_supported()
+			// SAFETY: the key is valid and the name is null-terminated
+			&& unsafe {
+				EVP_PKEY_is_a(key.as_ptr(), ALGORITHM.as_ptr().cast()) == 1
+			}
+	}
+
+	/// Returns the shared secret and the ciphertext.
+	pub(super) fn encapsulate(
+		public_key: &openssl::pkey::PKeyRef<openssl::pkey::Public>,
+	) -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+		let ctx = Context::new(public_key)?;
+		// SAFETY: lengths are queried first, so buffers are big enough
+		unsafe {
+			check(EVP_PKEY_encapsulate_init(ctx.0, std::ptr::null()))?;
+			let (mut ciphertext_length, mut secret_length) = (0, 0);
+			check(EVP_PKEY_encapsulate(
+				ctx.0,
+				std::ptr::null_mut(),
+				&raw mut ciphertext_length,
+				std::ptr::null_mut(),
+				&raw mut secret_length,
+			))?;
+			let mut ciphertext = vec![0; ciphertext_length];
+			let mut secret = vec![0; secret_length];
+			check(EVP_PKEY_encapsulate(
+				ctx.0,
+				ciphertext.as_mut_ptr(),
+				&raw mut ciphertext_length,
+				secret.as_mut_ptr(),
+				&raw mut secret_length,
+			))?;
+			ciphertext.truncate(ciphertext_length);
+			secret.truncate(secret_length);
+			Ok((secret, ciphertext))
+		}
+	}
+
+	/// Returns the shared secret of the `ciphertext`.
+	pub(super) fn decapsulate(
+		private_key: &openssl::pkey::PKeyRef<openssl::pkey::Private>,
+		ciphertext: &[u8],
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		let ctx = Context::new(private_key)?;
+		// SAFETY: the length is queried first, so the buffer is big enough
+		unsafe {
+			check(EVP_PKEY_decapsulate_init(ctx.0, std::ptr::null()))?;
+			let mut secret_length = 0;
+			check(EVP_PKEY_decapsulate(
+				ctx.0,
+				std::ptr::null_mut(),
+				&raw mut secret_length,
+				ciphertext.as_ptr(),
+				ciphertext.len(),
+			))?;
+			let mut secret = vec![0; secret_length];
+			check(EVP_PKEY_decapsulate(
+				ctx.0,
+				secret.as_mut_ptr(),
+				&raw mut secret_length,
+				ciphertext.as_ptr(),
+				ciphertext.len(),
+			))?;
+			secret.truncate(secret_length);
+			Ok(secret)
+		}
+	}
+}
+
+/// Without the `ml-kem` feature ML-KEM-768 keys are neither generated nor
+/// read, so they are never used.
+#[cfg(not(feature = "ml-kem"))]
+mod ml_kem {
+	const UNSUPPORTED: &str = "ML-KEM-768 keys are not supported";
+
+	#[must_use]
+	pub(super) const fn is_supported() -> bool {
+		false
+	}
+
+	pub(super) fn generate() -> Result<
+		openssl::pkey::PKey<openssl::pkey::Private>,
+		openssl::error::ErrorStack,
+	> {
+		unreachable!("{}", UNSUPPORTED)
+	}
+
+	#[must_use]
+	pub(super) const fn is_ml_kem<T>(
+		_key: &openssl::pkey::PKeyRef<T>,
+	) -> bool {
+		false
+	}
+
+	pub(super) fn encapsulate(
+		_public_key: &openssl::pkey::PKeyRef<openssl::pkey::Public>,
+	) -> Result<(Vec<u8>, Vec<u8>), openssl::error::ErrorStack> {
+		unreachable!("{}", UNSUPPORTED)
+	}
+
+	pub(super) fn decapsulate(
+		_private_key: &openssl::pkey::PKeyRef<openssl::pkey::Private>,
+		_ciphertext: &[u8],
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		unreachable!("{}", UNSUPPORTED)
+	}
+}
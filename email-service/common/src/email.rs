@@ -3,7 +3,8 @@ use crate::{
 	self as common,
 	error::{
 		CheckEmailDecryptedIntegrityError, CheckEmailSignatureError,
-		DecryptEmailError, NewEmailError, SignEmailError,
+		DecryptEmailError, EmailFromBytesError, EncryptEmailSessionError,
+		GenerateProofOfWorkError, NewEmailError, SignEmailError,
 	},
 };
 
@@ -58,8 +59,226 @@ impl Data {
 	}
 }
 
+/// A scheme of the [`Email`] proof-of-work. New schemes must only be
+/// appended, since the scheme is transmitted in the email.
+#[derive(
+	Clone,
+	Copy,
+	Debug,
+	Default,
+	Eq,
+	Hash,
+	PartialEq,
+	serde::Deserialize,
+	serde::Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ProofOfWorkScheme {
+	/// SHA-256, cheap on GPUs.
+	#[default]
+	Sha256,
+	/// scrypt with `consts::SCRYPT_*` parameters, memory-hard.
+	Scrypt,
+}
+
+impl ProofOfWorkScheme {
+	/// Computes the digest of the `challenge` with the `nonce`.
+	fn digest(
+		self,
+		challenge: &[u8],
+		nonce: u64,
+	) -> Result<[u8; 32], openssl::error::ErrorStack> {
+		let nonce_be_bytes = nonce.to_be_bytes();
+		match self {
+			Self::Sha256 => {
+				Ok(crate::crypto::hash([challenge, &nonce_be_bytes].concat()))
+			}
+			Self::Scrypt => {
+				let mut digest = [0; 32];
+				openssl::pkcs5::scrypt(
+					&nonce_be_bytes,
+					challenge,
+					crate::consts::SCRYPT_N,
+					crate::consts::SCRYPT_R,
+					crate::consts::SCRYPT_P,
+					crate::consts::SCRYPT_MAX_MEMORY,
+					&mut digest,
+				)?;
+				Ok(digest)
+			}
+		}
+	}
+}
+
+/// Proof-of-work difficulties of each [`ProofOfWorkScheme`] as the number of
+/// leading zero bits of the digest. Each missing field defaults to the
+/// constant from [`consts`](crate::consts).
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ProofOfWorkDifficulty {
+	sha256: u8,
+	scrypt: u8,
+}
+
+impl ProofOfWorkDifficulty {
+	#[inline]
+	#[must_use]
+	pub fn get(&self, scheme: ProofOfWorkScheme) -> u8 {
+		match scheme {
+			ProofOfWorkScheme::Sha256 => self.sha256,
+			ProofOfWorkScheme::Scrypt => self.scrypt,
+		}
+	}
+
+	/// Returns the strictest difficulty of each scheme.
+	#[inline]
+	#[must_use]
+	pub fn max(self, other: Self) -> Self {
+		Self {
+			sha256: self.sha256.max(other.sha256),
+			scrypt: self.scrypt.max(other.scrypt),
+		}
+	}
+}
+
+impl Default for ProofOfWorkDifficulty {
+	fn default() -> Self {
+		Self {
+			sha256: crate::consts::SHA256_PROOF_OF_WORK_DIFFICULTY,
+			scrypt: crate::consts::SCRYPT_PROOF_OF_WORK_DIFFICULTY,
+		}
+	}
+}
+
+/// Cancels [`Email::generate_proof_of_work`] from another thread. Clones
+/// share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct ProofOfWorkCanceller(std::sync::Arc<std::sync::atomic::AtomicBool>);
+
+impl ProofOfWorkCanceller {
+	#[inline]
+	pub fn cancel(&self) {
+		self.0.store(true, std::sync::atomic::Ordering::Relaxed);
+	}
+
+	#[inline]
+	#[must_use]
+	pub fn is_cancelled(&self) -> bool {
+		self.0.load(std::sync::atomic::Ordering::Relaxed)
+	}
+
+	/// Returns a guard that cancels the generation when dropped, e.g. when a
+	/// future waiting for the generation is dropped.
+	#[inline]
+	#[must_use]
+	pub fn cancel_on_drop(&self) -> ProofOfWorkCancelGuard {
+		ProofOfWorkCancelGuard(self.clone())
+	}
+}
+
+/// See [`ProofOfWorkCanceller::cancel_on_drop`].
+pub struct ProofOfWorkCancelGuard(ProofOfWorkCanceller);
+
+impl Drop for ProofOfWorkCancelGuard {
+	fn drop(&mut self) {
+		self.0.cancel();
+	}
+}
+
+/// Progress of [`Email::generate_proof_of_work`].
+#[derive(Clone, Copy, Debug)]
+pub struct ProofOfWorkProgress {
+	attempts: u64,
+	difficulty: u8,
+	elapsed: std::time::Duration,
+}
+
+impl ProofOfWorkProgress {
+	common::accessor!(copy attempts -> u64);
+
+	common::accessor!(copy elapsed -> std::time::Duration);
+
+	/// The expected number of attempts for the difficulty, `2^difficulty`.
+	#[inline]
+	#[must_use]
+	pub fn expected_attempts(&self) -> f64 {
+		2_f64.powi(i32::from(self.difficulty))
+	}
+
+	/// Estimates the time until the expected number of attempts is made with
+	/// the current speed. The search is random, so it can finish much
+	/// earlier or later. `None` if nothing is attempted yet.
+	#[allow(clippy::cast_precision_loss)]
+	#[must_use]
+	pub fn estimated_remaining(&self) -> Option<std::time::Duration> {
+		if self.attempts == 0 {
+			return None;
+		}
+		let speed = self.attempts as f64 / self.elapsed.as_secs_f64();
+		let remaining =
+			(self.expected_attempts() - self.attempts as f64).max(0.0);
+		Some(std::time::Duration::from_secs_f64(remaining / speed))
+	}
+}
+
+/// Public keys of the recipient prekey and the ephemeral key of an [`Email`],
+/// see [`Prekey`](crate::crypto::Prekey).
+#[derive(serde::Deserialize, serde::Serialize)]
+struct PrekeyExchange {
+	prekey_public_key: Box<[u8]>,
+	ephemeral_public_key: Box<[u8]>,
+}
+
+/// Layout of a serialized [`Email`], see [`Email::to_bytes`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum Format {
+	/// The layout of version 1.7.1 and older without a header: the session
+	/// is encrypted with RSA-OAEP, the encrypted fields have no associated
+	/// data and the proof-of-work is a SHA-256 of the email. It is only
+	/// decoded for emails that are already stored, see
+	/// [`Email::from_stored_bytes`].
+	Legacy,
+	/// The layout of `consts::EMAIL_FORMAT_VERSION`.
+	#[default]
+	Current,
+}
+
+/// An [`Email`] in the [`Format::Legacy`] layout.
+#[derive(serde::Deserialize)]
+struct LegacyEmail {
+	recipient_public_key_pem_hash: [u8; 32],
+	nonce: u64,
+	e_session: Box<[u8]>,
+	e_data_bytes: Box<[u8]>,
+	e_sender_public_key_pem: Option<Box<[u8]>>,
+	e_signature: Option<Box<[u8]>>,
+}
+
+impl From<LegacyEmail> for Email {
+	fn from(e: LegacyEmail) -> Self {
+		// The RSA-OAEP encrypted session is decapsulated directly
+		Self {
+			format: Format::Legacy,
+			recipient_public_key_pem_hash: e.recipient_public_key_pem_hash,
+			nonce: e.nonce,
+			encapsulated_key: e.e_session,
+			e_data_bytes: e.e_data_bytes,
+			e_sender_public_key_pem: e.e_sender_public_key_pem,
+			e_signature: e.e_signature,
+			..Self::default()
+		}
+	}
+}
+
 /// An email that is transmitted between client and node.
 ///
+/// Emails are transmitted as [`to_bytes`](Email::to_bytes), which starts
+/// with a header of the format version, and received with
+/// [`from_bytes`](Email::from_bytes). Emails loaded from a node are received
+/// with [`from_stored_bytes`](Email::from_stored_bytes), which also accepts
+/// emails of version 1.7.1 and older without a header, so emails stored on
+/// nodes before an update can still be loaded.
+///
 /// # Examples
 ///
 /// Creating:
@@ -67,28 +286,38 @@ impl Data {
 /// ```no_run
 /// # #[tokio::main]
 /// # async fn main() -> anyhow::Result<()> {
-/// # let recipient_private_key = openssl::rsa::Rsa::generate(2048)?;
-/// # let recipient_public_key_pem
-/// #     = recipient_private_key.public_key_to_pem()?;
+/// # use common::crypto::{KeyType, PrivateKey, PublicKey};
+/// # let recipient_private_key = PrivateKey::generate(KeyType::Curve25519)?;
 /// # let recipient_public_key
-/// #     = openssl::rsa::Rsa::public_key_from_pem(&recipient_public_key_pem)?;
-/// # let sender_private_key = openssl::rsa::Rsa::generate(2048)?;
+/// #     = PublicKey::from_pem(&recipient_private_key.public_key_to_pem()?)?;
+/// # let sender_private_key = PrivateKey::generate(KeyType::Rsa)?;
+/// use common::email::{ProofOfWorkCanceller, ProofOfWorkScheme};
+///
 /// let data = common::email::Data::new(
 ///     "sender".to_owned(), "title".to_owned(), "text".to_owned(), None,
 /// );
 /// let mut email = common::email::Email::new(&recipient_public_key, data)?;
 /// email = tokio::task::spawn_blocking(move || {
-///     email.generate_proof_of_work();
-///     email
-/// }).await?;
+///     email.generate_proof_of_work(
+///         ProofOfWorkScheme::Scrypt,
+///         6,
+///         std::thread::available_parallelism()?,
+///         &ProofOfWorkCanceller::default(),
+///         |p| println!("Remaining: {:?}", p.estimated_remaining()),
+///     )?;
+///     Ok::<_, anyhow::Error>(email)
+/// }).await??;
+/// // A signed prekey of the recipient taken from a node, if there is one
+/// let recipient_prekey = None;
+/// email.encrypt_session(&recipient_public_key, recipient_prekey.as_ref())?;
 /// email.sign(&sender_private_key)?;
-/// # let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// # let mut session = common::package::Session::new(stream);
 /// # let package = common::package::Package::new(
-/// #     None,
 /// #     common::package::Action::SendEmail,
-/// #     bincode::serialize(&email)?,
+/// #     email.to_bytes()?,
 /// # );
-/// # package.send(&mut stream).await?;
+/// # session.send(&package).await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -98,14 +327,14 @@ impl Data {
 /// ```no_run
 /// # #[tokio::main]
 /// # async fn main() -> anyhow::Result<()> {
-/// # let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
-/// # let package = common::package::Package::receive(
-/// #     &mut stream,
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// # let mut session = common::package::Session::new(stream);
+/// # let package = session.receive(
 /// #     None,
 /// #     Some(common::set![common::package::Action::SendEmail]),
 /// # ).await?;
-/// let email = bincode::deserialize::<common::email::Email>(package.data())?;
-/// assert!(email.check_encrypted_integrity(), "Invalid email.");
+/// let email = common::email::Email::from_bytes(package.data())?;
+/// assert!(email.check_encrypted_integrity(Default::default()), "Invalid email.");
 /// # Ok(())
 /// # }
 /// ```
@@ -115,17 +344,21 @@ impl Data {
 /// ```no_run
 /// # #[tokio::main]
 /// # async fn main() -> anyhow::Result<()> {
-/// # let private_key = openssl::rsa::Rsa::generate(2048)?;
-/// # let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
-/// # let package = common::package::Package::receive(
-/// #     &mut stream,
+/// # let private_key = common::crypto::PrivateKey::generate(
+/// #     common::crypto::KeyType::Curve25519,
+/// # )?;
+/// # let find_prekey = |_: &[u8]| common::crypto::Prekey::generate().ok();
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// # let mut session = common::package::Session::new(stream);
+/// # let package = session.receive(
 /// #     None,
-/// #    Some(common::set![common::package::Action::SendEmail]),
+/// #     Some(common::set![common::package::Action::SendEmail]),
 /// # ).await?;
-/// let mut email
-///     = bincode::deserialize::<common::email::Email>(package.data())?;
-/// if !email.check_encrypted_integrity()
-///     || email.decrypt(&private_key).is_err()
+/// let mut email = common::email::Email::from_bytes(package.data())?;
+/// // Our private prekey the email is encrypted to, if any
+/// let prekey = email.prekey_public_key().and_then(find_prekey);
+/// if !email.check_encrypted_integrity(Default::default())
+///     || email.decrypt(&private_key, prekey.as_ref()).is_err()
 ///     || !email.check_decrypted_integrity()?
 /// {
 ///     panic!("Invalid email.");
@@ -136,8 +369,14 @@ impl Data {
 /// ```
 #[derive(Default, serde::Deserialize, serde::Serialize)]
 pub struct Email {
+	#[serde(skip)]
+	format: Format,
 	recipient_public_key_pem_hash: [u8; 32],
+	proof_of_work_scheme: ProofOfWorkScheme,
 	nonce: u64,
+	kem: crate::crypto::Kem,
+	encapsulated_key: Box<[u8]>,
+	prekey_exchange: Option<PrekeyExchange>,
 	#[serde(skip)]
 	session: Option<Box<[u8]>>,
 	e_session: Box<[u8]>,
@@ -159,41 +398,86 @@ impl Email {
 
 	crate::accessor!(as_deref sender_public_key_pem -> Option<&[u8]>);
 
+	crate::accessor!(copy proof_of_work_scheme -> ProofOfWorkScheme);
+
+	crate::accessor!(copy kem -> crate::crypto::Kem);
+
+	/// The public key of the recipient [`Prekey`](crate::crypto::Prekey) the
+	/// email is encrypted to, if any.
+	#[inline]
+	#[must_use]
+	pub fn prekey_public_key(&self) -> Option<&[u8]> {
+		self.prekey_exchange.as_ref().map(|p| &*p.prekey_public_key)
+	}
+
+	/// Serializes the email with a header of `consts::EMAIL_FORMAT_VERSION`.
+	pub fn to_bytes(&self) -> Result<Vec<u8>, bincode::Error> {
+		let header = [crate::consts::EMAIL_FORMAT_MAGIC, &[
+			crate::consts::EMAIL_FORMAT_VERSION,
+		]]
+		.concat();
+		Ok([header, bincode::serialize(self)?].concat())
+	}
+
+	/// Deserializes an email made by [`to_bytes`](Email::to_bytes). Emails of
+	/// version 1.7.1 and older without a header are rejected with
+	/// [`EmailFromBytesError::Legacy`], since their proof-of-work and
+	/// encryption are weaker, so new emails can't be sent in that layout.
+	pub fn from_bytes(bytes: &[u8]) -> Result<Self, EmailFromBytesError> {
+		if !bytes.starts_with(crate::consts::EMAIL_FORMAT_MAGIC) {
+			return Err(EmailFromBytesError::Legacy);
+		}
+		Self::from_stored_bytes(bytes)
+	}
+
+	/// Deserializes an email like [`from_bytes`](Email::from_bytes), but also
+	/// an email of version 1.7.1 and older without a header. Use it only for
+	/// emails that are already stored, like ones loaded from a node.
+	pub fn from_stored_bytes(
+		bytes: &[u8],
+	) -> Result<Self, EmailFromBytesError> {
+		let Some(rest) = bytes.strip_prefix(crate::consts::EMAIL_FORMAT_MAGIC)
+		else {
+			let legacy: LegacyEmail = crate::helpers::deserialize(bytes)?;
+			return Ok(legacy.into());
+		};
+		match rest.split_first() {
+			Some((&crate::consts::EMAIL_FORMAT_VERSION, rest)) => {
+				Ok(crate::helpers::deserialize(rest)?)
+			}
+			Some((&v, _)) => Err(EmailFromBytesError::UnsupportedVersion(v)),
+			None => Err(EmailFromBytesError::NoVersion),
+		}
+	}
+
+	/// Encrypts the `data` for the recipient with a random session. The
+	/// session is encrypted for the recipient by
+	/// [`encrypt_session`](Email::encrypt_session) after the
+	/// [proof-of-work](Email::generate_proof_of_work) is generated.
 	pub fn new(
-		recipient_public_key: &openssl::rsa::Rsa<openssl::pkey::Public>,
+		recipient_public_key: &crate::crypto::PublicKey,
 		data: Data,
 	) -> Result<Self, NewEmailError> {
 		crate::debug!("Creating a new email...");
 
 		// Get recipient's public key pem hash
-		let recipient_public_key_pem = recipient_public_key
-			.public_key_to_pem()
-			.map_err(NewEmailError::RecipientPublicKeyToPem)?;
-		let recipient_public_key_pem_hash =
-			crate::crypto::hash(recipient_public_key_pem);
+		let recipient_public_key_pem_hash = crate::crypto::hash(
+			recipient_public_key
+				.to_pem()
+				.map_err(NewEmailError::RecipientPublicKeyToPem)?,
+		);
 
-		// Generate and encrypt session using public key and PKCS1_OAEP padding
+		// Serialize and encrypt a data using a random session
 		let session =
 			crate::crypto::generate_random_bytes(None)?.into_boxed_slice();
-		let mut e_session =
-			vec![0; recipient_public_key.size() as usize].into_boxed_slice();
-		recipient_public_key
-			.public_encrypt(
-				&session,
-				&mut e_session,
-				openssl::rsa::Padding::PKCS1_OAEP,
-			)
-			.map_err(NewEmailError::EncryptSession)?;
-
-		// Serialize and encrypt a data using session
 		let data_bytes = bincode::serialize(&data)?;
 		let e_data_bytes = crate::crypto::AesCipher::new(&*session)
+			.with_aad(Self::data_aad(&recipient_public_key_pem_hash))
 			.encrypt(data_bytes)?
 			.into_boxed_slice();
 
 		Ok(Self {
 			recipient_public_key_pem_hash,
-			e_session,
 			session: Some(session),
 			data: Some(data),
 			e_data_bytes,
@@ -201,62 +485,214 @@ impl Email {
 		})
 	}
 
-	/// Generates a proof-of-work.
+	/// Encrypts the session for the recipient, hybrid if the key of the
+	/// recipient has ML-KEM. If the `recipient_prekey` is given, the session
+	/// is also encrypted to it, so the email can't be decrypted after the
+	/// recipient deletes the prekey.
+	///
+	/// The proof-of-work doesn't cover the encrypted session, so a one-time
+	/// prekey can be taken from a node after the proof-of-work is generated
+	/// and is not wasted if the generation fails or is cancelled.
+	///
+	/// # Panics
+	///
+	/// If you are not sender.
+	pub fn encrypt_session(
+		&mut self,
+		recipient_public_key: &crate::crypto::PublicKey,
+		recipient_prekey: Option<&crate::crypto::SignedPrekey>,
+	) -> Result<(), EncryptEmailSessionError> {
+		let recipient_public_key_pem_hash = crate::crypto::hash(
+			recipient_public_key
+				.to_pem()
+				.map_err(EncryptEmailSessionError::RecipientPublicKeyToPem)?,
+		);
+		if recipient_public_key_pem_hash != self.recipient_public_key_pem_hash
+		{
+			return Err(EncryptEmailSessionError::InvalidRecipient);
+		}
+
+		// Make a key encapsulated for the recipient
+		let (key, encapsulated_key, kem) = recipient_public_key
+			.encapsulate()
+			.map_err(EncryptEmailSessionError::Encapsulate)?;
+
+		// Mix the prekey of the recipient into the key
+		let (key, prekey_exchange) = match recipient_prekey {
+			Some(p) => {
+				if !p
+					.verify(recipient_public_key)
+					.map_err(EncryptEmailSessionError::VerifyPrekey)?
+				{
+					return Err(EncryptEmailSessionError::InvalidPrekey);
+				}
+				let (key, ephemeral_public_key) = p
+					.encapsulate(&key)
+					.map_err(EncryptEmailSessionError::EncapsulatePrekey)?;
+				let prekey_exchange = PrekeyExchange {
+					prekey_public_key: p.public_key().into(),
+					ephemeral_public_key: ephemeral_public_key.into(),
+				};
+				(key, Some(prekey_exchange))
+			}
+			None => (key, None),
+		};
+
+		self.kem = kem;
+		self.encapsulated_key = encapsulated_key.into_boxed_slice();
+		self.prekey_exchange = prekey_exchange;
+		self.e_session = crate::crypto::AesCipher::new(key)
+			.with_aad(self.session_aad())
+			.encrypt(self.session.as_ref().unwrap())?
+			.into_boxed_slice();
+		Ok(())
+	}
+
+	/// Generates a proof-of-work with the `scheme` and the `difficulty` in
+	/// bits.
+	///
+	/// The nonce space is split between `threads`: the thread `i` tries
+	/// nonces `i`, `i + threads`, `i + 2 * threads` and so on. Every
+	/// `consts::PROOF_OF_WORK_PROGRESS_INTERVAL` the calling thread passes
+	/// the [`ProofOfWorkProgress`] to `on_progress`. The generation stops with
+	/// [`GenerateProofOfWorkError::Cancelled`] as soon as the `canceller` is
+	/// cancelled.
 	///
 	/// May take a long time. It is better to use this in conjunction with
 	/// [`tokio::task::spawn_blocking`].
-	pub fn generate_proof_of_work(&mut self) {
+	pub fn generate_proof_of_work<F>(
+		&mut self,
+		scheme: ProofOfWorkScheme,
+		difficulty: u8,
+		threads: std::num::NonZeroUsize,
+		canceller: &ProofOfWorkCanceller,
+		mut on_progress: F,
+	) -> Result<(), GenerateProofOfWorkError>
+	where
+		F: FnMut(ProofOfWorkProgress),
+	{
+		use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
 		crate::debug!("Generation of proof-of-work in the email...");
-		while !self.check_proof_of_work() {
-			self.nonce += 1;
+		self.proof_of_work_scheme = scheme;
+		let challenge = self.compute_proof_of_work_challenge();
+		let target = u32::from(difficulty);
+		let step = threads.get() as u64;
+
+		let is_done = AtomicBool::new(false);
+		let attempts = AtomicU64::new(0);
+		let result = std::sync::Mutex::new(None);
+		let started_at = std::time::Instant::now();
+		std::thread::scope(|scope| {
+			// Search nonces
+			let workers: Vec<_> = (0..step)
+				.map(|first_nonce| {
+					let (challenge, is_done, attempts, result) =
+						(&challenge, &is_done, &attempts, &result);
+					scope.spawn(move || {
+						let mut nonce = first_nonce;
+						while !is_done.load(Ordering::Relaxed)
+							&& !canceller.is_cancelled()
+						{
+							let found = match scheme.digest(challenge, nonce) {
+								Ok(d) if leading_zero_bits(&d) >= target => {
+									Ok(nonce)
+								}
+								Ok(_) => {
+									attempts.fetch_add(1, Ordering::Relaxed);
+									match nonce.checked_add(step) {
+										Some(n) => nonce = n,
+										None => break,
+									}
+									continue;
+								}
+								Err(e) => Err(e.into()),
+							};
+							result
+								.lock()
+								.unwrap_or_else(
+									std::sync::PoisonError::into_inner,
+								)
+								.get_or_insert(found);
+							is_done.store(true, Ordering::Relaxed);
+						}
+					})
+				})
+				.collect();
+
+			// Report progress until all workers are finished
+			let mut reported_at = started_at;
+			while !workers
+				.iter()
+				.all(std::thread::ScopedJoinHandle::is_finished)
+			{
+				std::thread::sleep(crate::consts::PROOF_OF_WORK_POLL_INTERVAL);
+				if reported_at.elapsed()
+					>= crate::consts::PROOF_OF_WORK_PROGRESS_INTERVAL
+				{
+					reported_at = std::time::Instant::now();
+					on_progress(ProofOfWorkProgress {
+						attempts: attempts.load(Ordering::Relaxed),
+						difficulty,
+						elapsed: started_at.elapsed(),
+					});
+				}
+			}
+		});
+
+		match result
+			.into_inner()
+			.unwrap_or_else(std::sync::PoisonError::into_inner)
+		{
+			Some(Ok(nonce)) => {
+				self.nonce = nonce;
+				Ok(())
+			}
+			Some(Err(e)) => Err(e),
+			None if canceller.is_cancelled() => {
+				Err(GenerateProofOfWorkError::Cancelled)
+			}
+			None => Err(GenerateProofOfWorkError::Exhausted),
 		}
 	}
 
-	/// Signs the proof-of-work with the
-	/// [`private_key`](openssl::rsa::Rsa<Private>).
+	/// Signs the proof-of-work with the `private_key`.
 	///
 	/// # Panics
 	///
 	/// If you are not sender and have not used [`decrypt`](Email::decrypt).
 	pub fn sign(
 		&mut self,
-		private_key: &openssl::rsa::Rsa<openssl::pkey::Private>,
+		private_key: &crate::crypto::PrivateKey,
 	) -> Result<(), SignEmailError> {
 		crate::debug!("Signing a email...");
-
-		// Create signer
-		let pkey = openssl::pkey::PKey::from_rsa(private_key.clone())
-			.map_err(SignEmailError::PkeyFromRsa)?;
-		let mut signer = openssl::sign::Signer::new(
-			openssl::hash::MessageDigest::sha256(),
-			&pkey,
-		)
-		.map_err(SignEmailError::NewSigner)?;
-		signer
-			.set_rsa_padding(openssl::rsa::Padding::PKCS1_PSS)
-			.map_err(SignEmailError::SetPadding)?;
-		signer
-			.update(self.compute_hash().as_bytes())
-			.map_err(SignEmailError::UpdateSigner)?;
+		if self.e_session.is_empty() {
+			return Err(SignEmailError::SessionNotEncrypted);
+		}
 
 		// Sign, encrypt signature and sender public key
-		let signature = signer
-			.sign_to_vec()
+		let signature = private_key
+			.sign(self.compute_hash().as_bytes())
 			.map_err(SignEmailError::Sign)?
 			.into_boxed_slice();
 		let sender_public_key_pem = private_key
 			.public_key_to_pem()
 			.map_err(SignEmailError::PublicKeyToPem)?
 			.into_boxed_slice();
-		let cipher = self.make_aes_cipher();
-		let e_signature = cipher
-			.encrypt(&signature)
-			.map_err(SignEmailError::EncryptSignature)?
-			.into_boxed_slice();
-		let e_sender_public_key_pem = cipher
+		let hash = self.compute_hash();
+		let e_sender_public_key_pem = self
+			.make_aes_cipher(Self::sender_public_key_pem_aad(&hash))
 			.encrypt(&sender_public_key_pem)
 			.map_err(SignEmailError::EncryptPublicKeyPem)?
 			.into_boxed_slice();
+		let e_signature = self
+			.make_aes_cipher(Self::signature_aad(
+				&hash,
+				&e_sender_public_key_pem,
+			))
+			.encrypt(&signature)
+			.map_err(SignEmailError::EncryptSignature)?
+			.into_boxed_slice();
 
 		// Update fields
 		self.e_signature = Some(e_signature);
@@ -266,12 +702,15 @@ impl Email {
 		Ok(())
 	}
 
-	/// Checks the proof-of-work and that email is signed. If you want to check
-	/// signature, use
+	/// Checks the proof-of-work with the `proof_of_work_difficulty` of its
+	/// scheme and that email is signed. If you want to check signature, use
 	/// [`check_decrypted_integrity`](Email::check_decrypted_integrity).
 	#[must_use]
-	pub fn check_encrypted_integrity(&self) -> bool {
-		if !self.check_proof_of_work() {
+	pub fn check_encrypted_integrity(
+		&self,
+		proof_of_work_difficulty: ProofOfWorkDifficulty,
+	) -> bool {
+		if !self.check_proof_of_work(proof_of_work_difficulty) {
 			crate::debug!("Encrypted email has invalid proof-of-work.");
 			return false;
 		} else if !self.check_is_signed() {
@@ -297,42 +736,72 @@ impl Email {
 		Ok(true)
 	}
 
-	/// With the [`private_key`](openssl::rsa::Rsa<openssl::pkey::Private>)
-	/// it decrypts encrypted session, then using decrypted session decrypts
-	/// encrypted data, sender public key and sender signature.
-	///
-	/// # Panics
+	/// With the `private_key` (and the `prekey`, if the email is encrypted to
+	/// [one](Email::prekey_public_key)) it decapsulates encrypted session of
+	/// any [`Kem`](crate::crypto::Kem), then using
+	/// decrypted session decrypts encrypted data, sender public key and
+	/// sender signature.
 	///
-	/// If email is not [signed](Email::sign)ed. Use
-	/// [`check_encrypted_integrity`](Email::decrypt) before.
+	/// Returns an error instead of panicking for any malformed email, like one
+	/// that is not [signed](Email::sign)ed.
 	pub fn decrypt(
 		&mut self,
-		private_key: &openssl::rsa::Rsa<openssl::pkey::Private>,
+		private_key: &crate::crypto::PrivateKey,
+		prekey: Option<&crate::crypto::Prekey>,
 	) -> Result<(), DecryptEmailError> {
 		// Decrypt session
-		let mut session = vec![0; private_key.size() as usize];
-		private_key.private_decrypt(
-			&self.e_session,
-			&mut session,
-			openssl::rsa::Padding::PKCS1_OAEP,
-		)?;
-		session.truncate(crate::consts::DEFAULT_RANDOM_BYTES_LENGTH);
-		self.session = Some(session.into_boxed_slice());
+		let mut key =
+			private_key.decapsulate(self.kem, &self.encapsulated_key)?;
+		if self.format == Format::Legacy {
+			return self.decrypt_fields(key, |_| Vec::new());
+		}
+		if let Some(ref e) = self.prekey_exchange {
+			key = prekey
+				.ok_or(DecryptEmailError::PrekeyRequired)?
+				.decapsulate(&key, &e.ephemeral_public_key)
+				.map_err(DecryptEmailError::Prekey)?;
+		}
+		let session = crate::crypto::AesCipher::new(key)
+			.with_aad(self.session_aad())
+			.decrypt(&self.e_session)
+			.map_err(DecryptEmailError::Session)?;
+		self.decrypt_fields(session, |aad| aad)
+	}
 
-		// Decrypt other fields
-		let cipher = self.make_aes_cipher();
-		let data_bytes = cipher
-			.decrypt(&self.e_data_bytes)
-			.map_err(DecryptEmailError::DataBytes)?;
-		let sender_public_key_pem = cipher
-			.decrypt(self.e_sender_public_key_pem.as_ref().unwrap())
-			.map_err(DecryptEmailError::SenderPublicKeyPem)?;
-		let signature = cipher
-			.decrypt(self.e_signature.as_ref().unwrap())
-			.map_err(DecryptEmailError::Signature)?;
+	/// Decrypts encrypted data, sender public key and sender signature with
+	/// the `session`, passing their associated data through `aad`.
+	fn decrypt_fields<A>(
+		&mut self,
+		session: Vec<u8>,
+		aad: A,
+	) -> Result<(), DecryptEmailError>
+	where
+		A: Fn(Vec<u8>) -> Vec<u8>,
+	{
+		let (Some(e_sender_public_key_pem), Some(e_signature)) =
+			(&self.e_sender_public_key_pem, &self.e_signature)
+		else {
+			return Err(DecryptEmailError::NotSigned);
+		};
+		let cipher =
+			|a| crate::crypto::AesCipher::new(&session).with_aad(aad(a));
+		let hash = self.compute_hash();
+		let data_bytes =
+			cipher(Self::data_aad(&self.recipient_public_key_pem_hash))
+				.decrypt(&self.e_data_bytes)
+				.map_err(DecryptEmailError::DataBytes)?;
+		let sender_public_key_pem =
+			cipher(Self::sender_public_key_pem_aad(&hash))
+				.decrypt(e_sender_public_key_pem)
+				.map_err(DecryptEmailError::SenderPublicKeyPem)?;
+		let signature =
+			cipher(Self::signature_aad(&hash, e_sender_public_key_pem))
+				.decrypt(e_signature)
+				.map_err(DecryptEmailError::Signature)?;
 
 		// Update fields
-		self.data = Some(bincode::deserialize(&data_bytes)?);
+		self.data = Some(crate::helpers::deserialize(&data_bytes)?);
+		self.session = Some(session.into_boxed_slice());
 		self.sender_public_key_pem =
 			Some(sender_public_key_pem.into_boxed_slice());
 		self.signature = Some(signature.into_boxed_slice());
@@ -345,8 +814,21 @@ impl Email {
 	/// work has been generated.
 	#[must_use]
 	pub fn compute_hash(&self) -> String {
+		if self.format == Format::Legacy {
+			let parts = [
+				&self.nonce.to_be_bytes()[..],
+				&self.encapsulated_key,
+				&self.recipient_public_key_pem_hash,
+				&self.e_data_bytes,
+			];
+			return hex::encode(crate::crypto::hash(parts.concat()));
+		}
 		let parts = [
-			&self.nonce.to_be_bytes(),
+			&self.nonce.to_be_bytes()[..],
+			&[self.proof_of_work_scheme as u8],
+			&[self.kem as u8],
+			&*self.encapsulated_key,
+			&self.prekey_exchange_bytes(),
 			&*self.e_session,
 			&self.recipient_public_key_pem_hash,
 			&self.e_data_bytes,
@@ -354,6 +836,42 @@ impl Email {
 		hex::encode(crate::crypto::hash(parts.concat()))
 	}
 
+	/// Makes bytes of the prekey exchange for hashing, that differ for
+	/// [`None`] and any [`Some`].
+	#[must_use]
+	fn prekey_exchange_bytes(&self) -> Vec<u8> {
+		self.prekey_exchange.as_ref().map_or_else(Vec::new, |p| {
+			[&[1][..], &p.prekey_public_key, &p.ephemeral_public_key].concat()
+		})
+	}
+
+	/// Calculates the hash of the proof-of-work with its challenge. Unlike
+	/// [`compute_hash`](Email::compute_hash), it is the same for any
+	/// [encrypted session](Email::encrypt_session), so nodes use it to reject
+	/// the proof-of-work reused with another one.
+	#[must_use]
+	pub fn compute_proof_of_work_hash(&self) -> String {
+		let parts = [
+			&self.compute_proof_of_work_challenge()[..],
+			&self.nonce.to_be_bytes(),
+		];
+		hex::encode(crate::crypto::hash(parts.concat()))
+	}
+
+	/// Calculates the hash of the email fields that the proof-of-work is
+	/// generated for, so large emails are not hashed for each nonce. It
+	/// doesn't take the encrypted session, see
+	/// [`encrypt_session`](Email::encrypt_session).
+	#[must_use]
+	fn compute_proof_of_work_challenge(&self) -> [u8; 32] {
+		let parts = [
+			&[self.proof_of_work_scheme as u8],
+			&self.recipient_public_key_pem_hash[..],
+			&self.e_data_bytes,
+		];
+		crate::crypto::hash(parts.concat())
+	}
+
 	/// Checks that the encrypted signature and encrypted sender public key
 	/// fields are [`Some`].
 	#[must_use]
@@ -361,17 +879,28 @@ impl Email {
 		self.e_signature.is_some() && self.e_sender_public_key_pem.is_some()
 	}
 
-	/// Checks that the [`compute_hash`](Email::compute_hash) starts
-	/// with `consts::PROOF_OF_WORK_DIFFICULTY_STRING`.
+	/// Checks that the proof-of-work digest of the email scheme starts with
+	/// at least `difficulty` zero bits.
 	#[must_use]
-	fn check_proof_of_work(&self) -> bool {
-		self.compute_hash()
-			.starts_with(&*crate::consts::PROOF_OF_WORK_DIFFICULTY_STRING)
+	fn check_proof_of_work(&self, difficulty: ProofOfWorkDifficulty) -> bool {
+		if self.format == Format::Legacy {
+			// The hash is a SHA-256 digest in hex
+			return hex::decode(self.compute_hash()).is_ok_and(|d| {
+				leading_zero_bits(&d)
+					>= u32::from(difficulty.get(ProofOfWorkScheme::Sha256))
+			});
+		}
+		let challenge = self.compute_proof_of_work_challenge();
+		self.proof_of_work_scheme.digest(&challenge, self.nonce).is_ok_and(
+			|d| {
+				leading_zero_bits(&d)
+					>= u32::from(difficulty.get(self.proof_of_work_scheme))
+			},
+		)
 	}
 
 	/// Checks that the sender has [signed](Email::sign)
-	/// [`compute_hash`](Email::compute_hash) with his
-	/// private key with [PKCS1-PSS padding](openssl::rsa::Padding).
+	/// [`compute_hash`](Email::compute_hash) with his private key.
 	///
 	/// # Panics
 	///
@@ -381,39 +910,74 @@ impl Email {
 		if !self.check_is_signed() {
 			return Ok(false);
 		}
-		// Convert sender pem to `PKey`
-		let sender_public_key = openssl::rsa::Rsa::public_key_from_pem(
+		crate::crypto::PublicKey::from_pem(
 			self.sender_public_key_pem.as_ref().unwrap(),
+		)?
+		.verify(
+			self.compute_hash().as_bytes(),
+			self.signature.as_ref().unwrap(),
 		)
-		.map_err(CheckEmailSignatureError::PublicKeyFromPem)?;
-		let pkey = openssl::pkey::PKey::from_rsa(sender_public_key)
-			.map_err(CheckEmailSignatureError::PkeyFromRsa)?;
-
-		// Create, update verifier and verify
-		let mut verifier = openssl::sign::Verifier::new(
-			openssl::hash::MessageDigest::sha256(),
-			&pkey,
-		)
-		.map_err(CheckEmailSignatureError::NewVerifier)?;
-		verifier
-			.set_rsa_padding(openssl::rsa::Padding::PKCS1_PSS)
-			.map_err(CheckEmailSignatureError::SetPadding)?;
-		verifier
-			.update(self.compute_hash().as_bytes())
-			.map_err(CheckEmailSignatureError::UpdateVerifier)?;
-		let rv = verifier
-			.verify(self.signature.as_ref().unwrap())
-			.map_err(CheckEmailSignatureError::Verify)?;
-		Ok(rv)
-	}
-
-	/// Creates `crypto::AesCipher` with key `self.session`.
+		.map_err(CheckEmailSignatureError::Verify)
+	}
+
+	/// Creates `crypto::AesCipher` with key `self.session` and associated
+	/// data `aad`.
 	///
 	/// # Panics
 	///
 	/// If you are not sender and have not used [`decrypt`](Email::decrypt).
 	#[must_use]
-	fn make_aes_cipher(&self) -> crate::crypto::AesCipher {
+	fn make_aes_cipher(&self, aad: Vec<u8>) -> crate::crypto::AesCipher {
 		crate::crypto::AesCipher::new(self.session.as_ref().unwrap().as_ref())
+			.with_aad(aad)
+	}
+
+	/// Associated data of the encrypted session, that binds it to the
+	/// encapsulated key, the prekey exchange and the recipient.
+	#[must_use]
+	fn session_aad(&self) -> Vec<u8> {
+		[
+			&b"session"[..],
+			&[self.kem as u8],
+			&self.encapsulated_key,
+			&self.prekey_exchange_bytes(),
+			&self.recipient_public_key_pem_hash,
+		]
+		.concat()
+	}
+
+	/// Associated data of the encrypted data bytes, that binds them to the
+	/// recipient.
+	#[must_use]
+	fn data_aad(recipient_public_key_pem_hash: &[u8]) -> Vec<u8> {
+		[&b"data"[..], recipient_public_key_pem_hash].concat()
+	}
+
+	/// Associated data of the encrypted sender public key, that binds it to
+	/// the [`compute_hash`](Email::compute_hash), which covers the recipient
+	/// and the encrypted data bytes.
+	#[must_use]
+	fn sender_public_key_pem_aad(hash: &str) -> Vec<u8> {
+		[&b"sender_public_key_pem"[..], hash.as_bytes()].concat()
+	}
+
+	/// Associated data of the encrypted signature, that binds it to the
+	/// [`compute_hash`](Email::compute_hash) and the encrypted sender public
+	/// key.
+	#[must_use]
+	fn signature_aad(hash: &str, e_sender_public_key_pem: &[u8]) -> Vec<u8> {
+		[&b"signature"[..], hash.as_bytes(), e_sender_public_key_pem].concat()
+	}
+}
+
+/// Counts leading zero bits of the `digest`.
+fn leading_zero_bits(digest: &[u8]) -> u32 {
+	let mut bits = 0;
+	for b in digest {
+		bits += b.leading_zeros();
+		if *b != 0 {
+			break;
+		}
 	}
+	bits
 }
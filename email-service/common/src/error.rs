@@ -1,8 +1,29 @@
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum AcceptHandshakeError {
+	#[error("Session is already encrypted.")]
+	AlreadyEncrypted,
+	#[error("Failed to derive transport keys.")]
+	Derive(#[source] openssl::error::ErrorStack),
+	#[error("Failed to encrypt a confirmation.")]
+	EncryptConfirmation(#[source] openssl::error::ErrorStack),
+	#[error("Failed to generate an ephemeral key.")]
+	GenerateKey(#[source] openssl::error::ErrorStack),
+	#[error("Failed to get a raw public key.")]
+	RawPublicKey(#[source] openssl::error::ErrorStack),
+	#[error("Failed to send a response.")]
+	Send(#[from] SendPackageError),
+	#[error("Failed to serialize a response.")]
+	Serialize(#[from] bincode::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum AesDecryptError {
 	#[error("Failed to decrypt.")]
 	Decrypt(#[from] openssl::error::ErrorStack),
+	#[error("Invalid ciphertext of length {0}.")]
+	InvalidCiphertext(usize),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -30,6 +51,17 @@ pub enum AesEncryptError {
 	Encrypt(#[from] openssl::error::ErrorStack),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum AuthenticateSessionError {
+	#[error("Failed to compute HMAC.")]
+	Hmac(#[source] openssl::error::ErrorStack),
+	#[error("Invalid password.")]
+	InvalidPassword,
+	#[error("Failed to make a request.")]
+	Request(#[from] RequestPackageError),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum CheckEmailDecryptedIntegrityError {
@@ -40,18 +72,10 @@ pub enum CheckEmailDecryptedIntegrityError {
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum CheckEmailSignatureError {
-	#[error("Failed to create a new verifier.")]
-	NewVerifier(#[source] openssl::error::ErrorStack),
-	#[error("Failed to get pkey from rsa private key.")]
-	PkeyFromRsa(#[source] openssl::error::ErrorStack),
-	#[error("Failed to convert PEM to public key.")]
-	PublicKeyFromPem(#[source] openssl::error::ErrorStack),
-	#[error("Failed to set the padding.")]
-	SetPadding(#[source] openssl::error::ErrorStack),
+	#[error("Failed to read a sender public key PEM.")]
+	PublicKeyFromPem(#[from] KeyFromPemError),
 	#[error("Failed to verify a signature.")]
 	Verify(#[source] openssl::error::ErrorStack),
-	#[error("Failed to update a verifier.")]
-	UpdateVerifier(#[source] openssl::error::ErrorStack),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -61,6 +85,17 @@ pub enum CreateDbPoolError {
 	Build(#[from] diesel_async::pooled_connection::deadpool::BuildError),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DecapsulateError {
+	#[error("Failed to decapsulate a session.")]
+	Decapsulate(#[from] openssl::error::ErrorStack),
+	#[error("Invalid encapsulated session.")]
+	InvalidSession,
+	#[error("The key does not support the {0:?} key encapsulation.")]
+	UnsupportedKem(crate::crypto::Kem),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum DecryptEmailError {
@@ -68,14 +103,31 @@ pub enum DecryptEmailError {
 	DataFromBytes(#[from] bincode::Error),
 	#[error("Failed to decrypt a data bytes.")]
 	DataBytes(#[source] AesDecryptError),
+	#[error("Failed to decapsulate a key.")]
+	Decapsulate(#[from] DecapsulateError),
 	#[error("Failed to decrypt a sender public key PEM.")]
 	SenderPublicKeyPem(#[source] AesDecryptError),
+	#[error("The email is not signed.")]
+	NotSigned,
+	#[error("Failed to decrypt a session with the prekey.")]
+	Prekey(#[source] openssl::error::ErrorStack),
+	#[error("The email is encrypted to a prekey, but it is not given.")]
+	PrekeyRequired,
 	#[error("Failed to decrypt a session.")]
-	Session(#[from] openssl::error::ErrorStack),
+	Session(#[source] AesDecryptError),
 	#[error("Failed to decrypt a signature.")]
 	Signature(#[source] AesDecryptError),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum DerivePasswordKeysError {
+	#[error("Failed to hash the password with Argon2id.")]
+	Argon2(#[from] argon2::Error),
+	#[error("Failed to derive a key from the hash.")]
+	Hmac(#[from] openssl::error::ErrorStack),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum DeserializeJsonFromFileError {
@@ -85,6 +137,61 @@ pub enum DeserializeJsonFromFileError {
 	Deserialize(#[from] serde_json::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EmailFromBytesError {
+	#[error("Failed to deserialize an email.")]
+	Deserialize(#[from] bincode::Error),
+	#[error("The email is in the legacy format.")]
+	Legacy,
+	#[error("The email has no format version.")]
+	NoVersion,
+	#[error("Unsupported email format version {0}.")]
+	UnsupportedVersion(u8),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum EncryptEmailSessionError {
+	#[error("Failed to encapsulate a key.")]
+	Encapsulate(#[source] openssl::error::ErrorStack),
+	#[error("Failed to encapsulate a key for the prekey.")]
+	EncapsulatePrekey(#[source] openssl::error::ErrorStack),
+	#[error("Failed to encrypt a session.")]
+	EncryptSession(#[from] AesEncryptError),
+	#[error("The prekey is not signed by the recipient.")]
+	InvalidPrekey,
+	#[error("The email is made for another recipient.")]
+	InvalidRecipient,
+	#[error("Failed to convert recipient's public key to PEM.")]
+	RecipientPublicKeyToPem(#[source] openssl::error::ErrorStack),
+	#[error("Failed to verify the prekey signature.")]
+	VerifyPrekey(#[source] openssl::error::ErrorStack),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum GeneratePrivateKeyError {
+	#[error("Failed to generate a key.")]
+	Generate(#[from] openssl::error::ErrorStack),
+	#[error(
+		"{0:?} keys require OpenSSL 3.5+ and the `ml-kem` feature of the \
+		 build."
+	)]
+	UnsupportedKeyType(crate::crypto::KeyType),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum GenerateProofOfWorkError {
+	#[error("Cancelled.")]
+	Cancelled,
+	#[error("Failed to compute a digest.")]
+	Digest(#[from] openssl::error::ErrorStack),
+	#[error("All nonces are exhausted.")]
+	Exhausted,
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum GenerateRandomBytesError {
@@ -92,6 +199,56 @@ pub enum GenerateRandomBytesError {
 	Generate(#[from] getrandom::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum HandshakeError {
+	#[error("Failed to decode the node identity key.")]
+	DecodeIdentityKey(#[from] base64::DecodeError),
+	#[error("Failed to derive transport keys.")]
+	Derive(#[source] openssl::error::ErrorStack),
+	#[error("Failed to generate an ephemeral key.")]
+	GenerateKey(#[source] openssl::error::ErrorStack),
+	#[error("Invalid confirmation.")]
+	InvalidConfirmation,
+	#[error("Invalid response data.")]
+	InvalidResponse,
+	#[error("Failed to get a raw public key.")]
+	RawPublicKey(#[source] openssl::error::ErrorStack),
+	#[error("The node has refused the handshake.")]
+	Refused,
+	#[error("Failed to make a request.")]
+	Request(#[from] RequestPackageError),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum HelloError {
+	#[error("Invalid response data.")]
+	InvalidResponse,
+	#[error("Failed to make a request.")]
+	Request(#[from] RequestPackageError),
+	#[error("Failed to serialize a request.")]
+	Serialize(#[from] bincode::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum IdentityKeyFromPemError {
+	#[error("The key is not X25519.")]
+	NotX25519,
+	#[error("Failed to read a private key PEM.")]
+	PrivateKeyFromPem(#[from] openssl::error::ErrorStack),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum KeyFromPemError {
+	#[error("Failed to read a PEM.")]
+	FromPem(#[from] openssl::error::ErrorStack),
+	#[error("Keys are not RSA or Ed25519 and X25519.")]
+	InvalidKeys,
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum NewEmailError {
@@ -99,8 +256,6 @@ pub enum NewEmailError {
 	DataToBytes(#[from] bincode::Error),
 	#[error("Failed to encrypt data bytes.")]
 	EncryptDataBytes(#[from] AesEncryptError),
-	#[error("Failed to encrypt a session.")]
-	EncryptSession(#[source] openssl::error::ErrorStack),
 	#[error("Failed to generate a session.")]
 	GenerateSession(#[from] GenerateRandomBytesError),
 	#[error("Failed to convert recipient's public key to PEM.")]
@@ -117,8 +272,16 @@ pub enum PackageIsTooBigError {
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum ReceivePackageBytesError {
+	#[error("Connection closed.")]
+	Closed,
+	#[error("Failed to decrypt a chunk.")]
+	Decrypt(#[source] openssl::error::ErrorStack),
 	#[error("Timeout.")]
 	Elapsed(#[from] tokio::time::error::Elapsed),
+	#[error("Invalid chunk size.")]
+	InvalidChunkSize,
+	#[error("Failed to receive a chunk size.")]
+	ReceiveChunkSize(#[source] std::io::Error),
 	#[error("Failed to receive a file.")]
 	ReceiveData(#[source] std::io::Error),
 	#[error("Failed to receive a size.")]
@@ -132,26 +295,49 @@ pub enum ReceivePackageBytesError {
 pub enum ReceivePackageError {
 	#[error("Failed to build package from bytes.")]
 	FromBytes(#[from] bincode::Error),
+	#[error("Failed to generate a password challenge.")]
+	GeneratePasswordChallenge(#[from] GenerateRandomBytesError),
+	#[error("Failed to compute HMAC.")]
+	Hmac(#[source] openssl::error::ErrorStack),
 	#[error("Invalid action.")]
 	InvalidAction,
 	#[error("Invalid password.")]
 	InvalidPassword,
+	#[error("Password is required.")]
+	PasswordRequired,
 	#[error("Failed to receive package's bytes.")]
 	ReceiveBytes(#[from] ReceivePackageBytesError),
 	#[error("Failed to send a package.")]
 	SendPackage(#[from] SendPackageError),
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum RequestPackageError {
+	#[error("Response has an invalid request identifier.")]
+	InvalidRequestId,
+	#[error("Failed to receive a response.")]
+	Receive(#[from] ReceivePackageError),
+	#[error("Failed to send a request.")]
+	Send(#[from] SendPackageError),
+	#[error("The node doesn't support the request action.")]
+	UnsupportedAction,
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum SendEmailToNodesError {
 	#[error("Failed to join a task.")]
 	JoinTask(#[from] tokio::task::JoinError),
+	#[error("Failed to serialize a package.")]
+	SerializePackage(#[source] bincode::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum SendPackageError {
+	#[error("Failed to encrypt a chunk.")]
+	Encrypt(#[source] openssl::error::ErrorStack),
 	#[error("Failed to send a data.")]
 	SendData(#[source] std::io::Error),
 	#[error("Failed to send a size.")]
@@ -162,6 +348,13 @@ pub enum SendPackageError {
 	TooBig,
 }
 
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum SignChallengeError {
+	#[error("Failed to sign a challenge.")]
+	Sign(#[from] openssl::error::ErrorStack),
+}
+
 #[derive(Debug, thiserror::Error)]
 #[non_exhaustive]
 pub enum SignEmailError {
@@ -169,16 +362,32 @@ pub enum SignEmailError {
 	EncryptPublicKeyPem(#[source] AesEncryptError),
 	#[error("Failed to encrypt a signature.")]
 	EncryptSignature(#[source] AesEncryptError),
-	#[error("Failed to create a new signer.")]
-	NewSigner(#[source] openssl::error::ErrorStack),
-	#[error("Failed to get pkey from rsa private key.")]
-	PkeyFromRsa(#[source] openssl::error::ErrorStack),
 	#[error("Failed to convert public key to PEM.")]
 	PublicKeyToPem(#[source] openssl::error::ErrorStack),
-	#[error("Failed to set the padding.")]
-	SetPadding(#[source] openssl::error::ErrorStack),
-	#[error("Failed to sign using signer.")]
+	#[error("The session is not encrypted for the recipient.")]
+	SessionNotEncrypted,
+	#[error("Failed to sign a hash.")]
 	Sign(#[source] openssl::error::ErrorStack),
-	#[error("Failed to update a signer.")]
-	UpdateSigner(#[source] openssl::error::ErrorStack),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum ValidateLimitsError {
+	#[error("`check_old_emails_interval_secs` must be > 0.")]
+	CheckOldEmailsInterval,
+	#[error("`emails_max_age_secs` must be > 0.")]
+	EmailsMaxAge,
+	#[error("`package_max_size` must be >= {0}.")]
+	PackageMaxSize(usize),
+	#[error("`package_receive_timeout_secs` must be > 0.")]
+	PackageReceiveTimeout,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum VerifyChallengeError {
+	#[error("Failed to read a public key PEM.")]
+	PublicKeyFromPem(#[from] KeyFromPemError),
+	#[error("Failed to verify a signature.")]
+	Verify(#[source] openssl::error::ErrorStack),
 }
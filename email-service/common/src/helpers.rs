@@ -21,6 +21,20 @@ pub fn create_db_pool() -> Result<DbPool, CreateDbPoolError> {
 	Ok(pool)
 }
 
+/// Same as [`bincode::deserialize`], but fails if the `bytes` don't end
+/// with the value, so a peer can't append unauthenticated data to it.
+pub fn deserialize<'a, T>(bytes: &'a [u8]) -> bincode::Result<T>
+where
+	T: serde::Deserialize<'a>,
+{
+	use bincode::Options as _;
+
+	bincode::DefaultOptions::new()
+		.with_fixint_encoding()
+		.reject_trailing_bytes()
+		.deserialize(bytes)
+}
+
 /// Reads the file and uses [`serde_json`] to deserialize the content.
 pub async fn deserialize_json_from_file<T>(
 	path: &std::path::Path,
@@ -36,55 +50,99 @@ where
 /// In multi-threaded mode, sends each node a `package` which contains
 /// `email::Email` bytes. After sending, it waits for a response from the node.
 ///
+/// The `package` is serialized once and shared between all nodes. Sessions
+/// are made with the `limits`.
+///
+/// Each node is an address, a password and a pinned identity key. If the
+/// identity key is pinned, the session is encrypted with
+/// [`Session::handshake`](crate::package::Session::handshake). If the password
+/// is set, the session is authenticated with
+/// [`Session::authenticate`](crate::package::Session::authenticate).
+///
+/// While the package is being sent, `on_progress` receives the sum of the
+/// [`Progress`](crate::package::Progress) of all nodes.
+///
 /// # Debug panic
 ///
 /// If `package.action()` is not `package::Action::SendEmail`.
-pub async fn send_email_to_nodes<N, IN>(
+pub async fn send_email_to_nodes<N, IN, F>(
 	package: crate::package::Package,
 	nodes: IN,
 	nodes_len: usize,
 	proxy: Option<std::net::SocketAddr>,
+	limits: crate::config::Limits,
+	on_progress: F,
 ) -> Result<usize, SendEmailToNodesError>
 where
-	N: Into<(std::net::SocketAddr, Option<String>)>,
+	N: Into<(std::net::SocketAddr, Option<String>, Option<String>)>,
 	IN: IntoIterator<Item = N>,
+	F: Fn(crate::package::Progress) + Send + Sync + 'static,
 {
 	debug_assert_eq!(package.action(), crate::package::Action::SendEmail);
 
 	let mut count = 0;
-	let package = std::sync::Arc::new(tokio::sync::Mutex::new(package));
+	let package = std::sync::Arc::new(
+		crate::package::SerializedPackage::new(&package)
+			.map_err(SendEmailToNodesError::SerializePackage)?,
+	);
 	let mut futures = Vec::with_capacity(nodes_len);
+	let on_progress = std::sync::Arc::new(on_progress);
+	let progress_sum = crate::package::ProgressSum::default();
 
-	for node in nodes {
-		let (address, password) = node.into();
+	for (i, node) in nodes.into_iter().enumerate() {
+		let (address, password, identity_key) = node.into();
 		let package = package.clone();
+		let (on_progress, progress_sum) =
+			(on_progress.clone(), progress_sum.clone());
 
 		let future = tokio::spawn(async move {
-			let mut stream =
-				crate::connect_or_else!(address, proxy, return false);
-
-			// Send email package
-			let mut lock = package.lock().await;
-			lock.set_password(password.as_deref());
-			crate::send_package_or_else!(
-				lock,
-				&mut stream,
+			let stream = crate::connect_or_else!(address, proxy, return false);
+			let mut session =
+				crate::package::Session::with_limits(stream, limits);
+			crate::handshake_or_else!(
+				&mut session,
 				address,
+				identity_key.as_deref(),
 				return false
 			);
-			drop(lock);
-
-			// Receive a response
-			let response = crate::receive_package_or_else!(
-				&mut stream,
+			crate::authenticate_or_else!(
+				&mut session,
 				address,
-				None,
-				Some(crate::set![
-					crate::package::Action::SendEmailSuccess,
-					crate::package::Action::SendEmailFail,
-				]),
-				return false,
+				password.as_deref(),
+				return false
+			);
+			crate::package::Progress::forward(
+				session.watch_send_progress(),
+				move |p| on_progress(progress_sum.update(i, p)),
 			);
+
+			// Send email package and receive a response
+			let response = match session
+				.request_serialized(
+					&package,
+					Some(crate::set![
+						crate::package::Action::SendEmailSuccess,
+						crate::package::Action::SendEmailFail,
+					]),
+				)
+				.await
+			{
+				Ok(r) => {
+					crate::debug!(
+						"Received a valid response from {}.",
+						address
+					);
+					r
+				}
+				Err(e) => {
+					crate::debug!(
+						"Failed to make a request to {}: {}",
+						address,
+						e
+					);
+					return false;
+				}
+			};
 			if response.action() == common::package::Action::SendEmailSuccess {
 				crate::debug!("New email successfully added in {}.", address);
 				true
@@ -15,6 +15,7 @@
 
 #[macro_use]
 mod macros;
+pub mod config;
 pub mod consts;
 pub mod crypto;
 pub mod email;
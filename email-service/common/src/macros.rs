@@ -80,52 +80,49 @@ macro_rules! accessor (
 	};
 );
 
-/// Just a shorthand for receiving packages (with debug), else doing
-/// something.
+/// Just a shorthand for sending a package to a
+/// [`session`](common::package::Session) and receiving a response to it (with
+/// debug), else doing something.
 ///
 /// # Example
 ///
 /// ```no_run
 /// # use std::net::SocketAddr;
-/// # use common::{receive_package_or_else, set, package::Action};
+/// # use common::{request_package_or_else, set, package::{Action, Package}};
 /// # #[tokio::main]
 /// # async fn main() -> Result<(), usize> {
 /// #    let address = SocketAddr::from(([127, 0, 0, 1], 8888));
-/// #    let mut stream
+/// #    let stream
 /// #        = common::connect_or_else!(address, None::<&str>, return Err(1));
-/// let _package = receive_package_or_else!(
-///     &mut stream,
+/// #    let mut session = common::package::Session::new(stream);
+/// let package = Package::new(Action::CheckConnection, vec![]);
+/// let _response = request_package_or_else!(
+///     &mut session,
+///     package,
 ///     address,
-///     None,
-///     Some(set![Action::SendEmail]),
+///     Some(set![Action::CheckConnectionSuccess]),
 ///     return Err(2),
 /// );
 /// #    Ok(())
 /// # }
 /// ```
 #[macro_export]
-macro_rules! receive_package_or_else {
+macro_rules! request_package_or_else {
 	(
-		$stream_mut_ref:expr,
+		$session_mut_ref:expr,
+		$package:expr,
 		$address:expr,
-		$password:expr,
 		$accepted_actions:expr,
 		$else:expr $(,)?
 	) => {
-		match common::package::Package::receive(
-			$stream_mut_ref,
-			$password,
-			$accepted_actions,
-		)
-		.await
-		{
+		match $session_mut_ref.request(&$package, $accepted_actions).await {
 			Ok(r) => {
-				common::debug!("Received a valid package from {}.", $address);
+				common::debug!("Received a valid response from {}.", $address);
 				r
 			}
 			Err(e) => {
 				common::debug!(
-					"Failed to receive a package from {}: {}",
+					"Failed to make a request to {}: {}",
 					$address,
 					e
 				);
@@ -135,24 +132,81 @@ macro_rules! receive_package_or_else {
 	};
 }
 
-/// Just a shorthand for sending package, else execute some code.
+/// Just a shorthand for encrypting a [`session`](common::package::Session)
+/// with a [`handshake`](common::package::Session::handshake) (with debug) if
+/// the node identity key is pinned, else doing something.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::net::SocketAddr;
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), usize> {
+/// #    let address = SocketAddr::from(([127, 0, 0, 1], 8888));
+/// #    let stream
+/// #        = common::connect_or_else!(address, None::<&str>, return Err(1));
+/// #    let mut session = common::package::Session::new(stream);
+/// let identity_key = Some("bm9kZSBpZGVudGl0eSBrZXkgYmFzZTY0IGVuY29kZWQ=");
+/// common::handshake_or_else!(&mut session, address, identity_key, return Err(2));
+/// #    Ok(())
+/// # }
+/// ```
 #[macro_export]
-macro_rules! send_package_or_else {
-	($package:expr, $stream:expr, $address:expr, $else:expr $(,)?) => {
-		match $package.send($stream).await {
-			Ok(()) => {
-				common::debug!(
-					"The package was successfully sent to {}.",
-					$address
-				);
+macro_rules! handshake_or_else {
+	(
+		$session_mut_ref:expr,
+		$address:expr,
+		$identity_key_option:expr,
+		$else:expr $(,)?
+	) => {
+		if let Some(k) = $identity_key_option {
+			match $session_mut_ref.handshake(k).await {
+				Ok(()) => {
+					common::debug!(
+						"The session with {} is encrypted.",
+						$address
+					);
+				}
+				Err(e) => {
+					common::debug!(
+						"Failed to make a handshake with {}: {}",
+						$address,
+						e
+					);
+					$else
+				}
 			}
-			Err(e) => {
-				common::debug!(
-					"Failed to send the package to {}: {}",
-					$address,
-					e
-				);
-				$else
+		}
+	};
+}
+
+/// Just a shorthand for
+/// [`authenticate`](common::package::Session::authenticate) with the node
+/// password (with debug) if it is set, else doing something.
+#[macro_export]
+macro_rules! authenticate_or_else {
+	(
+		$session_mut_ref:expr,
+		$address:expr,
+		$password_option:expr,
+		$else:expr $(,)?
+	) => {
+		if let Some(p) = $password_option {
+			match $session_mut_ref.authenticate(p).await {
+				Ok(()) => {
+					common::debug!(
+						"The session with {} is authenticated.",
+						$address
+					);
+				}
+				Err(e) => {
+					common::debug!(
+						"Failed to authenticate the session with {}: {}",
+						$address,
+						e
+					);
+					$else
+				}
 			}
 		}
 	};
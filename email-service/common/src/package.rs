@@ -1,9 +1,45 @@
 use crate::error::{
-	PackageIsTooBigError, ReceivePackageBytesError, ReceivePackageError,
-	SendPackageError,
+	AcceptHandshakeError, AuthenticateSessionError, HandshakeError,
+	HelloError, PackageIsTooBigError, ReceivePackageBytesError,
+	ReceivePackageError, RequestPackageError, SendPackageError,
 };
 
 /// `Package` action.
+///
+/// Actions are transmitted as stable `u16` codes, so new actions can be added
+/// without breaking older nodes: an action with an unknown code is received as
+/// `Action::Unknown` and the node responds to it with
+/// `Action::UnsupportedAction`. Supported actions of the node can be received
+/// with [`Session::hello`].
+///
+/// Codes and the request identifier of [`Package`] broke compatibility with
+/// version 1.7.1 and older, which serialized the variant index as `u32` and
+/// had no request identifier, so such peers can't talk to this version.
+///
+/// Emails of a mailbox can be listed and received only after the mailbox is
+/// authenticated in the session: the client sends its public key PEM with
+/// `Action::GetMailboxChallenge`, the node responds with a random nonce, then
+/// the client sends the nonce signature made by
+/// [`crypto::sign_challenge`](crate::crypto::sign_challenge) with
+/// `Action::AuthenticateMailbox`.
+///
+/// `Action::GetProofOfWorkDifficulty` is responded with the `u8` difficulty
+/// the node requires from emails of `Action::SendEmail`.
+///
+/// Owners of authenticated mailboxes publish their
+/// [signed prekeys](crate::crypto::SignedPrekey) with
+/// `Action::PublishPrekeys` (see [`PublishPrekeysData`]) and check how many of
+/// them are left with `Action::GetPrekeysCount` (the data is the public key
+/// PEM hash, the response data is a serialized [`PrekeysCountData`]). Anyone
+/// can take a prekey of a mailbox with `Action::GetPrekey` (the data is the
+/// public key PEM hash, the response data is a serialized `SignedPrekey`). The
+/// node deletes a taken one-time prekey and gives the last-resort one when
+/// there are none left or they are taken too often, so they can't be drained.
+/// Mailboxes without prekeys are responded with `Action::GetPrekeyFail`,
+/// whether they exist or not.
+///
+/// `Action::Handshake` is described in [`Session::handshake`] and password
+/// actions in [`Session::authenticate`].
 #[derive(
 	Clone,
 	Copy,
@@ -14,23 +50,280 @@ use crate::error::{
 	serde::Deserialize,
 	serde::Serialize,
 )]
+#[serde(from = "u16", into = "u16")]
 pub enum Action {
+	AuthenticateMailbox,
+	AuthenticateMailboxSuccess,
+	AuthenticateMailboxFail,
+	AuthenticatePassword,
+	AuthenticatePasswordSuccess,
 	CheckConnection,
 	CheckConnectionSuccess,
 	GetEmail,
 	GetEmailSuccess,
 	GetEmailFail,
+	GetEmailIds,
+	GetEmailIdsSuccess,
+	GetEmailIdsFail,
+	GetEmails,
+	GetEmailsSuccess,
+	GetEmailsFail,
 	GetEmailsCount,
 	GetEmailsCountSuccess,
 	GetEmailsCountFail,
+	GetMailboxChallenge,
+	GetMailboxChallengeSuccess,
+	GetMailboxChallengeFail,
+	GetPasswordChallenge,
+	GetPasswordChallengeSuccess,
+	GetPrekey,
+	GetPrekeySuccess,
+	GetPrekeyFail,
+	GetPrekeysCount,
+	GetPrekeysCountSuccess,
+	GetPrekeysCountFail,
+	GetProofOfWorkDifficulty,
+	GetProofOfWorkDifficultySuccess,
+	Handshake,
+	HandshakeSuccess,
+	HandshakeFail,
+	Hello,
+	HelloSuccess,
 	InvalidPassword,
+	PasswordRequired,
+	PublishPrekeys,
+	PublishPrekeysSuccess,
+	PublishPrekeysFail,
 	SendEmail,
 	SendEmailSuccess,
 	SendEmailFail,
+	UnsupportedAction,
+	/// An action with a code unknown to this version of the protocol.
+	Unknown(u16),
+}
+
+/// Implements conversions between `Action` and its code. Codes must never be
+/// changed or reused.
+macro_rules! impl_action_codes {
+	($($action:ident = $code:literal),* $(,)?) => {
+		impl From<u16> for Action {
+			fn from(code: u16) -> Self {
+				match code {
+					$($code => Self::$action,)*
+					c => Self::Unknown(c),
+				}
+			}
+		}
+
+		impl From<Action> for u16 {
+			fn from(action: Action) -> Self {
+				match action {
+					$(Action::$action => $code,)*
+					Action::Unknown(c) => c,
+				}
+			}
+		}
+	};
+}
+
+impl_action_codes! {
+	CheckConnection = 1,
+	CheckConnectionSuccess = 2,
+	GetEmail = 3,
+	GetEmailSuccess = 4,
+	GetEmailFail = 5,
+	GetEmailsCount = 6,
+	GetEmailsCountSuccess = 7,
+	GetEmailsCountFail = 8,
+	InvalidPassword = 9,
+	SendEmail = 10,
+	SendEmailSuccess = 11,
+	SendEmailFail = 12,
+	GetEmails = 13,
+	GetEmailsSuccess = 14,
+	GetEmailsFail = 15,
+	GetEmailIds = 16,
+	GetEmailIdsSuccess = 17,
+	GetEmailIdsFail = 18,
+	AuthenticateMailbox = 19,
+	AuthenticateMailboxSuccess = 20,
+	AuthenticateMailboxFail = 21,
+	GetMailboxChallenge = 22,
+	GetMailboxChallengeSuccess = 23,
+	GetMailboxChallengeFail = 24,
+	Handshake = 25,
+	HandshakeSuccess = 26,
+	HandshakeFail = 27,
+	GetPasswordChallenge = 28,
+	GetPasswordChallengeSuccess = 29,
+	AuthenticatePassword = 30,
+	AuthenticatePasswordSuccess = 31,
+	PasswordRequired = 32,
+	Hello = 33,
+	HelloSuccess = 34,
+	UnsupportedAction = 35,
+	GetProofOfWorkDifficulty = 36,
+	GetProofOfWorkDifficultySuccess = 37,
+	PublishPrekeys = 38,
+	PublishPrekeysSuccess = 39,
+	PublishPrekeysFail = 40,
+	GetPrekeysCount = 41,
+	GetPrekeysCountSuccess = 42,
+	GetPrekeysCountFail = 43,
+	GetPrekey = 44,
+	GetPrekeySuccess = 45,
+	GetPrekeyFail = 46,
+}
+
+/// Data of the `Action::Hello` package and its response.
+///
+/// Each side advertises its `consts::PROTOCOL_VERSION` and the request actions
+/// it supports.
+#[derive(Clone, Debug, serde::Deserialize, serde::Serialize)]
+pub struct HelloData {
+	protocol_version: u16,
+	supported_actions: std::collections::HashSet<Action>,
+}
+
+impl HelloData {
+	crate::accessor!(copy protocol_version -> u16);
+
+	crate::accessor!(
+		& supported_actions -> &std::collections::HashSet<Action>
+	);
+
+	#[inline]
+	#[must_use]
+	pub fn new(supported_actions: std::collections::HashSet<Action>) -> Self {
+		Self {
+			protocol_version: crate::consts::PROTOCOL_VERSION,
+			supported_actions,
+		}
+	}
+
+	/// `true` if all `actions` are supported.
+	#[must_use]
+	pub fn supports(&self, actions: &[Action]) -> bool {
+		actions.iter().all(|a| self.supported_actions.contains(a))
+	}
+}
+
+/// Data of the `Action::GetEmailIds` package.
+///
+/// Requests at most `self.limit` identifiers of emails stored on the node
+/// that are greater than `self.after_id` in ascending order. Identifiers are
+/// stable, so the greatest received identifier can be used as a cursor for the
+/// next request. The response data is a serialized `Vec<i32>`.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct GetEmailIdsData {
+	recipient_public_key_pem_hash: [u8; 32],
+	after_id: i32,
+	limit: i64,
+}
+
+impl GetEmailIdsData {
+	crate::accessor!(& recipient_public_key_pem_hash -> &[u8; 32]);
+
+	crate::accessor!(copy after_id -> i32);
+
+	crate::accessor!(copy limit -> i64);
+
+	#[inline]
+	#[must_use]
+	pub fn new(
+		recipient_public_key_pem_hash: [u8; 32],
+		after_id: i32,
+		limit: i64,
+	) -> Self {
+		Self { recipient_public_key_pem_hash, after_id, limit }
+	}
+}
+
+/// Data of the `Action::GetEmails` package.
+///
+/// Requests emails with `self.ids` in ascending order of identifiers. The
+/// node stops adding emails to the response when their total size reaches
+/// `self.max_size`, but the response always contains at least one email if
+/// there is one. The response data is a serialized `Vec<(i32, Vec<u8>)>` with
+/// identifiers and email bytes.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct GetEmailsData {
+	recipient_public_key_pem_hash: [u8; 32],
+	ids: Vec<i32>,
+	max_size: u64,
+}
+
+impl GetEmailsData {
+	crate::accessor!(& recipient_public_key_pem_hash -> &[u8; 32]);
+
+	crate::accessor!(& ids -> &[i32]);
+
+	crate::accessor!(copy max_size -> u64);
+
+	#[inline]
+	#[must_use]
+	pub fn new(
+		recipient_public_key_pem_hash: [u8; 32],
+		ids: Vec<i32>,
+		max_size: u64,
+	) -> Self {
+		Self { recipient_public_key_pem_hash, ids, max_size }
+	}
+}
+
+/// Data of the `Action::GetPrekeysCountSuccess` package.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct PrekeysCountData {
+	count: i64,
+	has_last_resort_prekey: bool,
+}
+
+impl PrekeysCountData {
+	crate::accessor!(copy count -> i64);
+
+	crate::accessor!(copy has_last_resort_prekey -> bool);
+
+	#[inline]
+	#[must_use]
+	pub const fn new(count: i64, has_last_resort_prekey: bool) -> Self {
+		Self { count, has_last_resort_prekey }
+	}
+}
+
+/// Data of the `Action::PublishPrekeys` package.
+///
+/// Adds one-time `self.prekeys` to the authenticated mailbox. The node may
+/// reject them if the mailbox has too many prekeys.
+/// `self.last_resort_prekey` replaces the last-resort prekey of the mailbox,
+/// which is given when there are no one-time prekeys and is never deleted.
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct PublishPrekeysData {
+	recipient_public_key_pem_hash: [u8; 32],
+	prekeys: Vec<crate::crypto::SignedPrekey>,
+	last_resort_prekey: Option<crate::crypto::SignedPrekey>,
+}
+
+impl PublishPrekeysData {
+	crate::accessor!(& recipient_public_key_pem_hash -> &[u8; 32]);
+
+	crate::accessor!(& prekeys -> &[crate::crypto::SignedPrekey]);
+
+	crate::accessor!(
+		as_ref last_resort_prekey -> Option<&crate::crypto::SignedPrekey>
+	);
+
+	#[inline]
+	#[must_use]
+	pub fn new(
+		recipient_public_key_pem_hash: [u8; 32],
+		prekeys: Vec<crate::crypto::SignedPrekey>,
+		last_resort_prekey: Option<crate::crypto::SignedPrekey>,
+	) -> Self {
+		Self { recipient_public_key_pem_hash, prekeys, last_resort_prekey }
+	}
 }
 
-/// A package for exchanging `self.data` using the `self.send` and
-/// `Self::receive` methods.
+/// A package for exchanging `self.data` over a [`Session`].
 ///
 /// # Examples
 ///
@@ -39,13 +332,13 @@ pub enum Action {
 /// ```no_run
 /// # #[tokio::main]
 /// # async fn main() -> anyhow::Result<()> {
-/// # let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// let mut session = common::package::Session::new(stream);
 /// let package = common::package::Package::new(
-///     None,
 ///     common::package::Action::SendEmail,
 ///     vec![0, 1, 2],
 /// );
-/// package.send(&mut stream).await?;
+/// session.send(&package).await?;
 /// # Ok(())
 /// # }
 /// ```
@@ -55,12 +348,11 @@ pub enum Action {
 /// ```no_run
 /// # #[tokio::main]
 /// # async fn main() -> anyhow::Result<()> {
-/// # let mut stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
-/// let mut package = common::package::Package::receive(
-///     &mut stream,
-///     None,
-///     Some(common::set![common::package::Action::SendEmail]),
-/// ).await?;
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// # let mut session = common::package::Session::new(stream);
+/// let package = session
+///     .receive(None, Some(common::set![common::package::Action::SendEmail]))
+///     .await?;
 /// println!("Data: {:?}", package.data());
 /// # Ok(())
 /// # }
@@ -69,7 +361,9 @@ pub enum Action {
 pub struct Package {
 	action: Action,
 	data: Vec<u8>,
-	password_hash: Option<[u8; 32]>,
+	/// Set by [`Session::receive`]. Travels outside of the package.
+	#[serde(skip)]
+	request_id: u64,
 }
 
 impl Package {
@@ -77,35 +371,416 @@ impl Package {
 
 	crate::accessor!(& data -> &[u8]);
 
-	#[must_use = "Send a package with `self.send`."]
-	pub fn new<D>(password: Option<&str>, action: Action, data: D) -> Self
+	crate::accessor!(copy request_id -> u64);
+
+	#[must_use = "Send a package with `Session::send`."]
+	pub fn new<D>(action: Action, data: D) -> Self
 	where
 		D: Into<Vec<u8>>,
 	{
-		let mut rv = Self { password_hash: None, action, data: data.into() };
-		rv.set_password(password);
-		rv
+		Self { action, data: data.into(), request_id: 0 }
+	}
+
+	/// `true` if [serialized size](bincode::serialized_size) of `self` with
+	/// the request identifier greater than `max_size`, else `false`.
+	pub fn is_too_big(
+		&self,
+		max_size: usize,
+	) -> Result<bool, PackageIsTooBigError> {
+		let size = bincode::serialized_size(&(self.request_id, self))?;
+		Ok(size > max_size as u64)
+	}
+}
+
+/// A [`Package`] serialized once, so it can be sent to many [`Session`]s
+/// without serializing (and copying) it for each one.
+pub struct SerializedPackage {
+	action: Action,
+	bytes: Vec<u8>,
+}
+
+impl SerializedPackage {
+	crate::accessor!(copy action -> Action);
+
+	pub fn new(package: &Package) -> Result<Self, bincode::Error> {
+		Ok(Self {
+			action: package.action,
+			bytes: bincode::serialize(package)?,
+		})
+	}
+}
+
+/// A connection through which many [`Package`]s are exchanged, so that there
+/// is no need to open a new connection (and a new circuit, when using a
+/// proxy) for each package.
+///
+/// Each package is sent together with a request identifier. The responding
+/// side answers requests in the order in which they were received and copies
+/// the identifier of the request into the response, so requests can be
+/// pipelined: several packages can be [`send`](Session::send) before their
+/// responses are [`receive`](Session::receive)d.
+///
+/// Optionally, the session can be encrypted with a
+/// [`handshake`](Session::handshake) at the start of the connection.
+///
+/// The stream is a [`tokio::net::TcpStream`] by default, but it can be any
+/// other one, like [`tokio::io::duplex`] in tests.
+///
+/// # Examples
+///
+/// Pipelining:
+///
+/// ```no_run
+/// # use common::package::{Action, Package, Session};
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// let mut session = Session::new(stream);
+/// let first_id =
+///     session.send(&Package::new(Action::CheckConnection, [])).await?;
+/// let second_id =
+///     session.send(&Package::new(Action::CheckConnection, [])).await?;
+/// let accepted_actions = Some(common::set![Action::CheckConnectionSuccess]);
+/// let first = session.receive(None, accepted_actions.clone()).await?;
+/// let second = session.receive(None, accepted_actions).await?;
+/// assert_eq!(first.request_id(), first_id);
+/// assert_eq!(second.request_id(), second_id);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Responding:
+///
+/// ```no_run
+/// # use common::package::{Action, Package, Session};
+/// # #[tokio::main]
+/// # async fn main() -> anyhow::Result<()> {
+/// # let stream = tokio::net::TcpStream::connect("127.0.0.1:8888").await?;
+/// let mut session = Session::new(stream);
+/// let request = session.receive(None, None).await?;
+/// let response = Package::new(Action::CheckConnectionSuccess, []);
+/// session.respond(&request, &response).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Session<S = tokio::net::TcpStream> {
+	stream: S,
+	last_request_id: u64,
+	transport: Option<Transport>,
+	/// Nonce of the pending password challenge.
+	password_challenge: Option<Vec<u8>>,
+	is_password_authenticated: bool,
+	send_progress: tokio::sync::watch::Sender<Progress>,
+	receive_progress: tokio::sync::watch::Sender<Progress>,
+	limits: crate::config::Limits,
+}
+
+impl<S> Session<S>
+where
+	S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+	#[inline]
+	#[must_use]
+	pub fn new(stream: S) -> Self {
+		Self::with_limits(stream, crate::config::Limits::default())
+	}
+
+	/// Same as [`new`](Session::new), but uses the package max size and the
+	/// receive timeout from the `limits` instead of the default ones.
+	#[inline]
+	#[must_use]
+	pub fn with_limits(stream: S, limits: crate::config::Limits) -> Self {
+		Self {
+			stream,
+			last_request_id: 0,
+			transport: None,
+			password_challenge: None,
+			is_password_authenticated: false,
+			send_progress: tokio::sync::watch::channel(Progress::default()).0,
+			receive_progress: tokio::sync::watch::channel(Progress::default())
+				.0,
+			limits,
+		}
+	}
+
+	/// Returns a receiver of the [`Progress`] of the package being sent.
+	#[must_use]
+	pub fn watch_send_progress(
+		&self,
+	) -> tokio::sync::watch::Receiver<Progress> {
+		self.send_progress.subscribe()
+	}
+
+	/// Returns a receiver of the [`Progress`] of the package being received.
+	#[must_use]
+	pub fn watch_receive_progress(
+		&self,
+	) -> tokio::sync::watch::Receiver<Progress> {
+		self.receive_progress.subscribe()
 	}
 
-	/// Receives bytes from a [`stream`](tokio::net::TcpStream) with timeout
-	/// `consts::PACKAGE_RECEIVE_TIMEOUT` and deserializes into the
-	/// structure. Also makes sure that the `self.action` field is in
-	/// `accepted_actions`.
+	#[inline]
+	#[must_use]
+	pub fn is_encrypted(&self) -> bool {
+		self.transport.is_some()
+	}
+
+	/// Encrypts the session with the node whose
+	/// [identity key](crate::crypto::IdentityKey) is `node_identity_key`
+	/// (raw X25519 public key encoded into [`base64`]).
+	///
+	/// Sends an ephemeral X25519 public key with `Action::Handshake` and
+	/// receives an ephemeral public key of the node with a confirmation.
+	/// Transport keys are derived from Diffie-Hellman of our ephemeral key
+	/// with both keys of the node, so only the owner of the identity key can
+	/// derive them and make the confirmation. After that all packages in both
+	/// directions are encrypted with AES-256-GCM and numbered, so they can't
+	/// be read, modified, replayed or reordered.
 	///
-	/// See also: [`send`](Package::send).
+	/// The handshake package doesn't require a password, so the password can
+	/// be [`authenticate`](Session::authenticate)d after the session is
+	/// encrypted.
+	///
+	/// See also: [`accept_handshake`](Session::accept_handshake).
+	pub async fn handshake(
+		&mut self,
+		node_identity_key: &str,
+	) -> Result<(), HandshakeError> {
+		let node_identity_key = base64::decode(node_identity_key)?;
+		let ephemeral_key = crate::crypto::generate_x25519_key()
+			.map_err(HandshakeError::GenerateKey)?;
+		let ephemeral_public_key = ephemeral_key
+			.raw_public_key()
+			.map_err(HandshakeError::RawPublicKey)?;
+
+		// Exchange ephemeral keys
+		let package =
+			Package::new(Action::Handshake, ephemeral_public_key.clone());
+		let response = self
+			.request(
+				&package,
+				Some(crate::set![
+					Action::HandshakeSuccess,
+					Action::HandshakeFail
+				]),
+			)
+			.await?;
+		if response.action == Action::HandshakeFail {
+			return Err(HandshakeError::Refused);
+		}
+		let (node_ephemeral_public_key, confirmation): (Vec<u8>, Vec<u8>) =
+			crate::helpers::deserialize(&response.data)
+				.map_err(|_| HandshakeError::InvalidResponse)?;
+
+		// Derive keys and check the confirmation
+		let es = crate::crypto::x25519(&ephemeral_key, &node_identity_key)
+			.map_err(HandshakeError::Derive)?;
+		let ee =
+			crate::crypto::x25519(&ephemeral_key, &node_ephemeral_public_key)
+				.map_err(HandshakeError::Derive)?;
+		let secret = crate::crypto::hash(
+			[
+				&es[..],
+				&ee,
+				&ephemeral_public_key,
+				&node_ephemeral_public_key,
+				&node_identity_key,
+			]
+			.concat(),
+		);
+		let mut transport = Transport::new(&secret, true);
+		if transport.decrypt(&confirmation, 0, true).is_err() {
+			return Err(HandshakeError::InvalidConfirmation);
+		}
+		self.transport = Some(transport);
+		Ok(())
+	}
+
+	/// Responds to the `request` with `Action::Handshake` made by
+	/// [`handshake`](Session::handshake) and encrypts the session.
+	pub async fn accept_handshake(
+		&mut self,
+		request: &Package,
+		identity_key: &crate::crypto::IdentityKey,
+	) -> Result<(), AcceptHandshakeError> {
+		if self.is_encrypted() {
+			return Err(AcceptHandshakeError::AlreadyEncrypted);
+		}
+		let identity_public_key = identity_key
+			.pkey()
+			.raw_public_key()
+			.map_err(AcceptHandshakeError::RawPublicKey)?;
+		let ephemeral_key = crate::crypto::generate_x25519_key()
+			.map_err(AcceptHandshakeError::GenerateKey)?;
+		let ephemeral_public_key = ephemeral_key
+			.raw_public_key()
+			.map_err(AcceptHandshakeError::RawPublicKey)?;
+
+		// Derive keys
+		let keys = crate::crypto::x25519(identity_key.pkey(), &request.data)
+			.and_then(|es| {
+				let ee = crate::crypto::x25519(&ephemeral_key, &request.data)?;
+				Ok((es, ee))
+			});
+		let Ok((es, ee)) = keys else {
+			let response = Package::new(Action::HandshakeFail, vec![]);
+			self.respond(request, &response).await?;
+			return Err(AcceptHandshakeError::Derive(keys.unwrap_err()));
+		};
+		let secret = crate::crypto::hash(
+			[
+				&es[..],
+				&ee,
+				&request.data,
+				&ephemeral_public_key,
+				&identity_public_key,
+			]
+			.concat(),
+		);
+
+		// Make a confirmation and send response
+		let mut transport = Transport::new(&secret, false);
+		let confirmation = transport
+			.encrypt(&[], 0, true)
+			.map_err(AcceptHandshakeError::EncryptConfirmation)?;
+		let response = Package::new(
+			Action::HandshakeSuccess,
+			bincode::serialize(&(ephemeral_public_key, confirmation))?,
+		);
+		self.respond(request, &response).await?;
+		self.transport = Some(transport);
+		Ok(())
+	}
+
+	/// Exchanges [`HelloData`] with the node and returns the node's one.
+	///
+	/// `Action::Hello` doesn't require a password.
+	pub async fn hello(
+		&mut self,
+		supported_actions: std::collections::HashSet<Action>,
+	) -> Result<HelloData, HelloError> {
+		let package = Package::new(
+			Action::Hello,
+			bincode::serialize(&HelloData::new(supported_actions))?,
+		);
+		let response = self
+			.request(&package, Some(crate::set![Action::HelloSuccess]))
+			.await?;
+		crate::helpers::deserialize(&response.data)
+			.map_err(|_| HelloError::InvalidResponse)
+	}
+
+	/// Proves to the node that we know the `password` without sending it, so
+	/// the proof can't be replayed in another session.
+	///
+	/// Requests a random nonce with `Action::GetPasswordChallenge` and sends
+	/// HMAC-SHA-256 of the nonce with the password with
+	/// `Action::AuthenticatePassword`. The node responds with
+	/// `Action::InvalidPassword` only if the proof is wrong. Before the
+	/// authentication, a password-protected node responds to other packages
+	/// (except `Action::Handshake`) with `Action::PasswordRequired`.
+	pub async fn authenticate(
+		&mut self,
+		password: &str,
+	) -> Result<(), AuthenticateSessionError> {
+		// Get a challenge
+		let package = Package::new(Action::GetPasswordChallenge, vec![]);
+		let response = self
+			.request(
+				&package,
+				Some(crate::set![Action::GetPasswordChallengeSuccess]),
+			)
+			.await?;
+
+		// Send a proof
+		let proof = crate::crypto::hmac(password.as_bytes(), &response.data)
+			.map_err(AuthenticateSessionError::Hmac)?;
+		let package = Package::new(Action::AuthenticatePassword, proof);
+		let response = self
+			.request(
+				&package,
+				Some(crate::set![
+					Action::AuthenticatePasswordSuccess,
+					Action::InvalidPassword,
+				]),
+			)
+			.await?;
+		if response.action == Action::InvalidPassword {
+			return Err(AuthenticateSessionError::InvalidPassword);
+		}
+		Ok(())
+	}
+
+	/// Receives bytes from the stream with timeout
+	/// `limits.package_receive_timeout()` for each chunk and deserializes
+	/// them into the request identifier and the [`Package`]. Also makes sure
+	/// that the package action is in `accepted_actions`.
+	///
+	/// Password challenges made by [`authenticate`](Session::authenticate)
+	/// are answered here and not returned. If the `password` is set and the
+	/// session is not authenticated yet, responds with
+	/// `Action::PasswordRequired`. If the proof is invalid, responds with
+	/// `Action::InvalidPassword`.
+	///
+	/// Unlike [`request`](Session::request), an `Action::UnsupportedAction`
+	/// response is not accepted unless it is in `accepted_actions`.
+	///
+	/// See also: [`send`](Session::send).
 	pub async fn receive(
-		stream: &mut tokio::net::TcpStream,
+		&mut self,
 		password: Option<&str>,
 		accepted_actions: Option<std::collections::HashSet<Action>>,
-	) -> Result<Self, ReceivePackageError> {
-		let bytes = Self::receive_bytes(stream).await?;
-		let package: Self = bincode::deserialize(&bytes)?;
-		if !package.check_password(password) {
-			Package::new(None, Action::InvalidPassword, vec![])
-				.send(stream)
-				.await?;
-			return Err(ReceivePackageError::InvalidPassword);
-		}
+	) -> Result<Package, ReceivePackageError> {
+		let package = loop {
+			let package = self.receive_package().await?;
+			match package.action {
+				Action::GetPasswordChallenge => {
+					let nonce = crate::crypto::generate_random_bytes(None)?;
+					self.password_challenge = Some(nonce.clone());
+					let response = Package::new(
+						Action::GetPasswordChallengeSuccess,
+						nonce,
+					);
+					self.respond(&package, &response).await?;
+				}
+				Action::AuthenticatePassword => {
+					let is_valid =
+						match (password, self.password_challenge.take()) {
+							(Some(p), Some(n)) => {
+								let proof =
+									crate::crypto::hmac(p.as_bytes(), &n)
+										.map_err(ReceivePackageError::Hmac)?;
+								package.data.len() == proof.len()
+									&& openssl::memcmp::eq(
+										&package.data,
+										&proof,
+									)
+							}
+							_ => false,
+						};
+					if !is_valid {
+						let response =
+							Package::new(Action::InvalidPassword, vec![]);
+						self.respond(&package, &response).await?;
+						return Err(ReceivePackageError::InvalidPassword);
+					}
+					self.is_password_authenticated = true;
+					let response = Package::new(
+						Action::AuthenticatePasswordSuccess,
+						vec![],
+					);
+					self.respond(&package, &response).await?;
+				}
+				Action::Handshake | Action::Hello => break package,
+				_ if password.is_some() && !self.is_password_authenticated => {
+					let response =
+						Package::new(Action::PasswordRequired, vec![]);
+					self.respond(&package, &response).await?;
+					return Err(ReceivePackageError::PasswordRequired);
+				}
+				_ => break package,
+			}
+		};
 		if let Some(aa) = accepted_actions {
 			if !aa.contains(&package.action) {
 				return Err(ReceivePackageError::InvalidAction);
@@ -114,81 +789,398 @@ impl Package {
 		Ok(package)
 	}
 
-	/// Receives bytes from a [`stream`](tokio::net::TcpStream) with timeouts
-	/// `consts::PACKAGE_RECEIVE_TIMEOUT`
-	///
-	/// See also: [`send`](Package::send).
+	/// Sends the `package` with a new request identifier and returns this
+	/// identifier. The response will have the same identifier.
+	pub async fn send(
+		&mut self,
+		package: &Package,
+	) -> Result<u64, SendPackageError> {
+		self.send_serialized(&SerializedPackage::new(package)?).await
+	}
+
+	/// Same as [`send`](Session::send), but with the [`SerializedPackage`].
+	pub async fn send_serialized(
+		&mut self,
+		package: &SerializedPackage,
+	) -> Result<u64, SendPackageError> {
+		self.last_request_id += 1;
+		self.send_with_request_id(self.last_request_id, package).await?;
+		Ok(self.last_request_id)
+	}
+
+	/// Sends the `response` with the identifier of the `request`.
+	pub async fn respond(
+		&mut self,
+		request: &Package,
+		response: &Package,
+	) -> Result<(), SendPackageError> {
+		self.send_with_request_id(
+			request.request_id,
+			&SerializedPackage::new(response)?,
+		)
+		.await
+	}
+
+	/// Sends the `package` and receives a response to it.
 	///
-	/// `#[allow(clippy::needless_pass_by_ref_mut)]` because of clippy bug.
-	#[allow(clippy::needless_pass_by_ref_mut)]
-	async fn receive_bytes(
-		stream: &mut tokio::net::TcpStream,
-	) -> Result<Box<[u8]>, ReceivePackageBytesError> {
-		tokio::time::timeout(crate::consts::PACKAGE_RECEIVE_TIMEOUT, async {
-			use tokio::io::AsyncReadExt as _;
-
-			// Receive a size
-			let mut size_be_bytes_buffer = [0; 8];
-			stream
-				.read_exact(&mut size_be_bytes_buffer)
-				.await
-				.map_err(ReceivePackageBytesError::ReceiveSize)?;
-			let size = usize::from_be_bytes(size_be_bytes_buffer);
-			if size > crate::consts::PACKAGE_MAX_SIZE {
-				return Err(ReceivePackageBytesError::TooBig);
-			}
-			// Receive a bytes
-			let mut bytes_buffer = vec![0; size].into_boxed_slice();
-			stream
-				.read_exact(&mut bytes_buffer)
-				.await
-				.map_err(ReceivePackageBytesError::ReceiveData)?;
-			Ok(bytes_buffer)
-		})
-		.await?
+	/// If the node doesn't support the action of the `package`, returns
+	/// `RequestPackageError::UnsupportedAction`.
+	pub async fn request(
+		&mut self,
+		package: &Package,
+		accepted_actions: Option<std::collections::HashSet<Action>>,
+	) -> Result<Package, RequestPackageError> {
+		let package =
+			SerializedPackage::new(package).map_err(SendPackageError::from)?;
+		self.request_serialized(&package, accepted_actions).await
 	}
 
-	/// `true` if [serialized size](bincode::serialized_size) of `self` greater
-	/// than `consts::PACKAGE_MAX_SIZE`, else `false`.
-	pub fn is_too_big(&self) -> Result<bool, PackageIsTooBigError> {
-		let size = bincode::serialized_size(self)?;
-		Ok(size > crate::consts::PACKAGE_MAX_SIZE as u64)
+	/// Same as [`request`](Session::request), but with the
+	/// [`SerializedPackage`].
+	pub async fn request_serialized(
+		&mut self,
+		package: &SerializedPackage,
+		accepted_actions: Option<std::collections::HashSet<Action>>,
+	) -> Result<Package, RequestPackageError> {
+		let request_id = self.send_serialized(package).await?;
+		let response = self.receive(None, None).await?;
+		if response.request_id != request_id {
+			return Err(RequestPackageError::InvalidRequestId);
+		}
+		if response.action == Action::UnsupportedAction {
+			return Err(RequestPackageError::UnsupportedAction);
+		}
+		if let Some(aa) = accepted_actions {
+			if !aa.contains(&response.action) {
+				return Err(ReceivePackageError::InvalidAction.into());
+			}
+		}
+		Ok(response)
 	}
 
-	pub fn set_password(&mut self, password: Option<&str>) {
-		self.password_hash = password.map(|p| {
-			crate::crypto::hash_with_salt(p, crate::consts::PASSWORD_SALT)
-		});
+	/// Receives bytes with [`receive_bytes`](Session::receive_bytes) and
+	/// deserializes them into the request identifier and the [`Package`].
+	async fn receive_package(
+		&mut self,
+	) -> Result<Package, ReceivePackageError> {
+		let bytes = self.receive_bytes().await?;
+		let (request_id, mut package): (u64, Package) =
+			crate::helpers::deserialize(&bytes)?;
+		package.request_id = request_id;
+		Ok(package)
 	}
 
-	#[must_use]
-	pub fn check_password(&self, password: Option<&str>) -> bool {
-		self.password_hash
-			== password.map(|p| {
-				crate::crypto::hash_with_salt(p, crate::consts::PASSWORD_SALT)
-			})
+	/// Receives bytes from the stream with timeout
+	/// `limits.package_receive_timeout()` for each chunk, decrypting
+	/// chunks if the session is encrypted.
+	///
+	/// The buffer grows with received chunks, so a peer can't make us
+	/// allocate `limits.package_max_size()` just by sending a size. Every
+	/// chunk except the last one must be of `consts::PACKAGE_CHUNK_SIZE`, so
+	/// a peer can't stretch receiving a package by sending tiny chunks.
+	///
+	/// See also: [`send_with_request_id`](Session::send_with_request_id).
+	async fn receive_bytes(
+		&mut self,
+	) -> Result<Vec<u8>, ReceivePackageBytesError> {
+		use tokio::io::AsyncReadExt as _;
+
+		let timeout = self.limits.package_receive_timeout();
+
+		// Receive a size
+		let mut size_be_bytes_buffer = [0; 8];
+		match tokio::time::timeout(
+			timeout,
+			self.stream.read_exact(&mut size_be_bytes_buffer),
+		)
+		.await?
+		{
+			Ok(_) => {}
+			Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+				return Err(ReceivePackageBytesError::Closed);
+			}
+			Err(e) => return Err(ReceivePackageBytesError::ReceiveSize(e)),
+		}
+		let size = <usize as std::convert::TryFrom<u64>>::try_from(
+			u64::from_be_bytes(size_be_bytes_buffer),
+		)
+		.map_err(|_| ReceivePackageBytesError::TooBig)?;
+		if size > self.limits.package_max_size() {
+			return Err(ReceivePackageBytesError::TooBig);
+		}
+
+		// Receive chunks
+		let tag_size =
+			self.transport.as_ref().map_or(0, |_| Transport::TAG_SIZE);
+		let mut bytes =
+			Vec::with_capacity(size.min(crate::consts::PACKAGE_CHUNK_SIZE));
+		self.receive_progress.send_replace(Progress::new(0, size));
+		let mut index = 0;
+		while bytes.len() < size {
+			let mut chunk_size_be_bytes_buffer = [0; 4];
+			tokio::time::timeout(
+				timeout,
+				self.stream.read_exact(&mut chunk_size_be_bytes_buffer),
+			)
+			.await?
+			.map_err(ReceivePackageBytesError::ReceiveChunkSize)?;
+			let chunk_size =
+				u32::from_be_bytes(chunk_size_be_bytes_buffer) as usize;
+			let data_size =
+				(size - bytes.len()).min(crate::consts::PACKAGE_CHUNK_SIZE);
+			if chunk_size != data_size + tag_size {
+				return Err(ReceivePackageBytesError::InvalidChunkSize);
+			}
+			let mut chunk = vec![0; chunk_size];
+			tokio::time::timeout(timeout, self.stream.read_exact(&mut chunk))
+				.await?
+				.map_err(ReceivePackageBytesError::ReceiveData)?;
+			if let Some(ref mut t) = self.transport {
+				let last = bytes.len() + data_size == size;
+				chunk = t
+					.decrypt(&chunk, index, last)
+					.map_err(ReceivePackageBytesError::Decrypt)?;
+			}
+			index += 1;
+			bytes.extend_from_slice(&chunk);
+			self.receive_progress
+				.send_replace(Progress::new(bytes.len(), size));
+		}
+		Ok(bytes)
 	}
 
-	/// Sends `self` to [`stream`](tokio::net::TcpStream).
+	/// Sends the `package` with the `request_id` to the stream.
 	///
 	/// First it sends a data with a size of 8 bytes, which contains the size
-	/// of the `self`. Then it sends the `self` bytes.
-	pub async fn send(
-		&self,
-		stream: &mut tokio::net::TcpStream,
+	/// of the serialized `request_id` and `package`. Then it sends these
+	/// bytes in chunks of `consts::PACKAGE_CHUNK_SIZE`, each one prefixed with
+	/// its size of 4 bytes and encrypted if the session is encrypted.
+	async fn send_with_request_id(
+		&mut self,
+		request_id: u64,
+		package: &SerializedPackage,
 	) -> Result<(), SendPackageError> {
 		use tokio::io::AsyncWriteExt as _;
 
-		let data = bincode::serialize(self)?;
-		if data.len() > crate::consts::PACKAGE_MAX_SIZE {
+		// `bincode` serializes `(request_id, package)` as little-endian
+		// `request_id` followed by `package`, so we don't need to serialize
+		// the package again
+		let request_id_bytes = request_id.to_le_bytes();
+		let size = request_id_bytes.len() + package.bytes.len();
+		if size > self.limits.package_max_size() {
 			return Err(SendPackageError::TooBig);
 		}
-		let size_u64_be_bytes = (data.len() as u64).to_be_bytes();
-		stream
+		let size_u64_be_bytes = (size as u64).to_be_bytes();
+		self.stream
 			.write_all(&size_u64_be_bytes)
 			.await
 			.map_err(SendPackageError::SendSize)?;
-		stream.write_all(&data).await.map_err(SendPackageError::SendData)?;
+
+		// Send chunks
+		self.send_progress.send_replace(Progress::new(0, size));
+		let first_chunk_end = package
+			.bytes
+			.len()
+			.min(crate::consts::PACKAGE_CHUNK_SIZE - request_id_bytes.len());
+		let (first_chunk_rest, rest) = package.bytes.split_at(first_chunk_end);
+		self.send_chunk(
+			&[&request_id_bytes, first_chunk_rest].concat(),
+			0,
+			rest.is_empty(),
+		)
+		.await?;
+		let mut sent = request_id_bytes.len() + first_chunk_rest.len();
+		self.send_progress.send_replace(Progress::new(sent, size));
+		let chunks = rest.chunks(crate::consts::PACKAGE_CHUNK_SIZE);
+		let last_index = chunks.len() as u64;
+		for (index, chunk) in (1..).zip(chunks) {
+			self.send_chunk(chunk, index, index == last_index).await?;
+			sent += chunk.len();
+			self.send_progress.send_replace(Progress::new(sent, size));
+		}
+		Ok(())
+	}
+
+	/// Sends the `chunk` (encrypted if the session is encrypted) prefixed
+	/// with its size. `index` is the position of the chunk in the package and
+	/// `last` is `true` for the final chunk of the package.
+	async fn send_chunk(
+		&mut self,
+		chunk: &[u8],
+		index: u64,
+		last: bool,
+	) -> Result<(), SendPackageError> {
+		use tokio::io::AsyncWriteExt as _;
+
+		let encrypted_chunk;
+		let chunk = match self.transport {
+			Some(ref mut t) => {
+				encrypted_chunk = t
+					.encrypt(chunk, index, last)
+					.map_err(SendPackageError::Encrypt)?;
+				&encrypted_chunk
+			}
+			None => chunk,
+		};
+		// A chunk is at most `consts::PACKAGE_CHUNK_SIZE` plus a tag
+		#[allow(clippy::cast_possible_truncation)]
+		let size_u32_be_bytes = (chunk.len() as u32).to_be_bytes();
+		self.stream
+			.write_all(&size_u32_be_bytes)
+			.await
+			.map_err(SendPackageError::SendSize)?;
+		self.stream
+			.write_all(chunk)
+			.await
+			.map_err(SendPackageError::SendData)?;
 		Ok(())
 	}
 }
+
+/// Progress of a package transfer in a [`Session`] in bytes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Progress {
+	transferred: usize,
+	total: usize,
+}
+
+impl Progress {
+	crate::accessor!(copy transferred -> usize);
+
+	crate::accessor!(copy total -> usize);
+
+	#[inline]
+	#[must_use]
+	fn new(transferred: usize, total: usize) -> Self {
+		Self { transferred, total }
+	}
+
+	/// Passes each new progress of the `receiver` to `on_progress` in a new
+	/// task, until the [`Session`] of the `receiver` is dropped.
+	pub fn forward<F>(
+		mut receiver: tokio::sync::watch::Receiver<Self>,
+		mut on_progress: F,
+	) where
+		F: FnMut(Self) + Send + 'static,
+	{
+		tokio::spawn(async move {
+			while receiver.changed().await.is_ok() {
+				let progress = *receiver.borrow_and_update();
+				on_progress(progress);
+			}
+		});
+	}
+}
+
+/// Sum of the [`Progress`] of several transfers, like of one package sent to
+/// several nodes.
+///
+/// Clones share the same transfers.
+#[derive(Clone, Default)]
+pub struct ProgressSum(
+	std::sync::Arc<
+		std::sync::Mutex<std::collections::HashMap<usize, Progress>>,
+	>,
+);
+
+impl ProgressSum {
+	/// Sets the `progress` of the transfer `i` and returns the sum of all
+	/// transfers.
+	#[must_use]
+	pub fn update(&self, i: usize, progress: Progress) -> Progress {
+		let mut transfers =
+			self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+		transfers.insert(i, progress);
+		transfers.values().fold(Progress::default(), |sum, p| {
+			Progress::new(sum.transferred + p.transferred, sum.total + p.total)
+		})
+	}
+}
+
+/// Keys and counters of an encrypted [`Session`].
+struct Transport {
+	send_key: [u8; 32],
+	receive_key: [u8; 32],
+	send_counter: u64,
+	receive_counter: u64,
+}
+
+impl Transport {
+	const TAG_SIZE: usize = 16;
+
+	/// Derives a key for each direction from the handshake `secret`.
+	#[must_use]
+	fn new(secret: &[u8; 32], initiator: bool) -> Self {
+		let initiator_key =
+			crate::crypto::hash_with_salt(secret, b"initiator");
+		let responder_key =
+			crate::crypto::hash_with_salt(secret, b"responder");
+		let (send_key, receive_key) = if initiator {
+			(initiator_key, responder_key)
+		} else {
+			(responder_key, initiator_key)
+		};
+		Self { send_key, receive_key, send_counter: 0, receive_counter: 0 }
+	}
+
+	/// Encrypts the `data` with the send counter as IV, so it's never reused
+	/// with the same key. The chunk `index`, size and `last` flag are
+	/// authenticated, so chunks can't be truncated or moved between packages.
+	fn encrypt(
+		&mut self,
+		data: &[u8],
+		index: u64,
+		last: bool,
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		let mut tag = [0; Self::TAG_SIZE];
+		let rv = openssl::symm::encrypt_aead(
+			openssl::symm::Cipher::aes_256_gcm(),
+			&self.send_key,
+			Some(&Self::make_iv(self.send_counter)),
+			&Self::make_aad(index, data.len(), last),
+			data,
+			&mut tag,
+		)?;
+		self.send_counter += 1;
+		Ok([rv, tag.into()].concat())
+	}
+
+	/// Decrypts the `data` encrypted by the other side with the expected
+	/// counter, chunk `index` and `last` flag.
+	fn decrypt(
+		&mut self,
+		data: &[u8],
+		index: u64,
+		last: bool,
+	) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+		let Some(tag_start) = data.len().checked_sub(Self::TAG_SIZE) else {
+			return Err(openssl::error::ErrorStack::get());
+		};
+		let rv = openssl::symm::decrypt_aead(
+			openssl::symm::Cipher::aes_256_gcm(),
+			&self.receive_key,
+			Some(&Self::make_iv(self.receive_counter)),
+			&Self::make_aad(index, tag_start, last),
+			&data[..tag_start],
+			&data[tag_start..],
+		)?;
+		self.receive_counter += 1;
+		Ok(rv)
+	}
+
+	#[must_use]
+	fn make_iv(counter: u64) -> [u8; 12] {
+		let mut iv = [0; 12];
+		iv[4..].copy_from_slice(&counter.to_be_bytes());
+		iv
+	}
+
+	#[must_use]
+	fn make_aad(index: u64, size: usize, last: bool) -> [u8; 17] {
+		let mut aad = [0; 17];
+		aad[..8].copy_from_slice(&index.to_be_bytes());
+		aad[8..16].copy_from_slice(&(size as u64).to_be_bytes());
+		aad[16] = last.into();
+		aad
+	}
+}
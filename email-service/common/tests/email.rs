@@ -0,0 +1,115 @@
+//! Proof-of-work difficulties and email layouts that nodes accept.
+
+use common::{
+	crypto::{KeyType, PrivateKey, PublicKey},
+	email::{
+		Data, Email, ProofOfWorkCanceller, ProofOfWorkDifficulty,
+		ProofOfWorkScheme,
+	},
+	error::EmailFromBytesError,
+};
+
+/// Layout of emails of version 1.7.1 and older, without a header.
+#[derive(serde::Serialize)]
+struct LegacyEmail {
+	recipient_public_key_pem_hash: [u8; 32],
+	nonce: u64,
+	e_session: Box<[u8]>,
+	e_data_bytes: Box<[u8]>,
+	e_sender_public_key_pem: Option<Box<[u8]>>,
+	e_signature: Option<Box<[u8]>>,
+}
+
+fn make_difficulty(
+	scheme: ProofOfWorkScheme,
+	difficulty: u8,
+) -> anyhow::Result<ProofOfWorkDifficulty> {
+	let scheme = serde_json::to_string(&scheme)?;
+	Ok(serde_json::from_str(&format!("{{{}: {}}}", scheme, difficulty))?)
+}
+
+/// Generates the proof-of-work of the `email` at the `difficulty` and signs
+/// it, so only the proof-of-work decides its integrity.
+fn solve(
+	email: &mut Email,
+	recipient_public_key: &PublicKey,
+	scheme: ProofOfWorkScheme,
+	difficulty: u8,
+) -> anyhow::Result<()> {
+	email.generate_proof_of_work(
+		scheme,
+		difficulty,
+		std::num::NonZeroUsize::MIN,
+		&ProofOfWorkCanceller::default(),
+		|_| {},
+	)?;
+	email.encrypt_session(recipient_public_key, None)?;
+	email.sign(&PrivateKey::generate(KeyType::Curve25519)?)?;
+	Ok(())
+}
+
+/// Checks that an email solved at `difficulty` bits passes at it and, unless
+/// its digest happens to have more zero bits, fails at one bit more.
+fn check_difficulty(
+	scheme: ProofOfWorkScheme,
+	difficulty: u8,
+) -> anyhow::Result<()> {
+	let private_key = PrivateKey::generate(KeyType::Curve25519)?;
+	let public_key = PublicKey::from_pem(&private_key.public_key_to_pem()?)?;
+	let at = make_difficulty(scheme, difficulty)?;
+	let above = make_difficulty(scheme, difficulty + 1)?;
+	for _ in 0..64 {
+		let data = Data::new(
+			"sender".to_owned(),
+			"title".to_owned(),
+			"text".to_owned(),
+			None,
+		);
+		let mut email = Email::new(&public_key, data)?;
+		solve(&mut email, &public_key, scheme, difficulty)?;
+		let solved = Email::from_bytes(&email.to_bytes()?)?;
+		assert!(solved.check_encrypted_integrity(at));
+
+		// With one thread nonces are searched in order, so if the first
+		// nonce at one bit more is another one, the solved digest has
+		// exactly `difficulty` zero bits
+		let solved_hash = email.compute_proof_of_work_hash();
+		solve(&mut email, &public_key, scheme, difficulty + 1)?;
+		assert!(email.check_encrypted_integrity(above));
+		if email.compute_proof_of_work_hash() != solved_hash {
+			assert!(!solved.check_encrypted_integrity(above));
+			return Ok(());
+		}
+	}
+	panic!("Every digest has more zero bits than required.");
+}
+
+#[test]
+fn sha256_proof_of_work_is_checked_by_bits() -> anyhow::Result<()> {
+	check_difficulty(ProofOfWorkScheme::Sha256, 8)
+}
+
+#[test]
+fn scrypt_proof_of_work_is_checked_by_bits() -> anyhow::Result<()> {
+	check_difficulty(ProofOfWorkScheme::Scrypt, 2)
+}
+
+#[test]
+fn legacy_emails_are_accepted_only_when_stored() -> anyhow::Result<()> {
+	let legacy = LegacyEmail {
+		recipient_public_key_pem_hash: [0; 32],
+		nonce: 0,
+		e_session: vec![1; 256].into_boxed_slice(),
+		e_data_bytes: vec![2; 64].into_boxed_slice(),
+		e_sender_public_key_pem: Some(vec![3; 64].into_boxed_slice()),
+		e_signature: Some(vec![4; 64].into_boxed_slice()),
+	};
+	let bytes = bincode::serialize(&legacy)?;
+	assert!(matches!(
+		Email::from_bytes(&bytes),
+		Err(EmailFromBytesError::Legacy),
+	));
+	let email = Email::from_stored_bytes(&bytes)?;
+	assert_eq!(email.recipient_public_key_pem_hash(), &[0; 32]);
+	Ok(())
+}
@@ -0,0 +1,163 @@
+//! Fuzz-style tests: malformed ciphertexts and emails, like ones received from
+//! a malicious node or peer, must be rejected with errors instead of panics.
+
+use common::{
+	crypto::{AesCipher, KeyType, Prekey, PrivateKey, PublicKey},
+	email::{Data, Email, ProofOfWorkCanceller, ProofOfWorkScheme},
+	error::AesDecryptError,
+};
+
+/// Deterministic xorshift generator, so failures are reproducible.
+struct Rng(u64);
+
+impl Rng {
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	fn below(&mut self, n: usize) -> usize {
+		(self.next() % n as u64) as usize
+	}
+
+	fn bytes(&mut self, length: usize) -> Vec<u8> {
+		(0..length).map(|_| self.next() as u8).collect()
+	}
+}
+
+/// Truncations, single bit flips and random byte replacements of `bytes`.
+fn mutations(bytes: &[u8], rng: &mut Rng) -> Vec<Vec<u8>> {
+	let mut rv = Vec::new();
+	for length in 0..bytes.len() {
+		rv.push(bytes[..length].to_vec());
+	}
+	for i in 0..bytes.len() {
+		let mut m = bytes.to_vec();
+		m[i] ^= 1 << rng.below(8);
+		rv.push(m);
+	}
+	for _ in 0..bytes.len() {
+		let mut m = bytes.to_vec();
+		let i = rng.below(m.len());
+		m[i] = m[i].wrapping_add(1 + rng.below(255) as u8);
+		rv.push(m);
+	}
+	rv
+}
+
+/// Makes a signed email from a new sender to the `recipient_private_key`.
+fn make_email(
+	recipient_private_key: &PrivateKey,
+	recipient_prekey: Option<&Prekey>,
+) -> anyhow::Result<Email> {
+	let recipient_public_key =
+		PublicKey::from_pem(&recipient_private_key.public_key_to_pem()?)?;
+	let signed_prekey =
+		recipient_prekey.map(|p| p.sign(recipient_private_key)).transpose()?;
+	let data = Data::new(
+		"sender".to_owned(),
+		"title".to_owned(),
+		"text".to_owned(),
+		None,
+	);
+	let mut email = Email::new(&recipient_public_key, data)?;
+	email.generate_proof_of_work(
+		ProofOfWorkScheme::Sha256,
+		0,
+		std::num::NonZeroUsize::MIN,
+		&ProofOfWorkCanceller::default(),
+		|_| {},
+	)?;
+	email.encrypt_session(&recipient_public_key, signed_prekey.as_ref())?;
+	email.sign(&PrivateKey::generate(KeyType::Curve25519)?)?;
+	Ok(email)
+}
+
+/// Deserializes and decrypts the `bytes`, returning whether the email is
+/// valid.
+fn accept_email(
+	bytes: &[u8],
+	private_key: &PrivateKey,
+	prekey: Option<&Prekey>,
+) -> bool {
+	let Ok(mut email) = Email::from_bytes(bytes) else {
+		return false;
+	};
+	let _ = email.check_encrypted_integrity(Default::default());
+	email.decrypt(private_key, prekey).is_ok()
+		&& matches!(email.check_decrypted_integrity(), Ok(true))
+}
+
+#[test]
+fn aes_decrypt_rejects_malformed_ciphertexts() -> anyhow::Result<()> {
+	let mut rng = Rng(0x5eed);
+	let cipher = AesCipher::new(&[7u8; 32][..]).with_aad(&b"aad"[..]);
+
+	for length in 0..128 {
+		let bytes = rng.bytes(length);
+		assert!(cipher.decrypt(&bytes).is_err());
+		let mut versioned = bytes;
+		if let Some(b) = versioned.first_mut() {
+			*b = common::crypto::AES_CIPHERTEXT_VERSION;
+		}
+		assert!(cipher.decrypt(&versioned).is_err());
+	}
+	assert!(matches!(
+		cipher.decrypt(&[]),
+		Err(AesDecryptError::InvalidCiphertext(0)),
+	));
+
+	let ciphertext = cipher.encrypt("data")?;
+	assert_eq!(cipher.decrypt(&ciphertext)?, b"data");
+	for m in mutations(&ciphertext, &mut rng) {
+		assert!(cipher.decrypt(&m).is_err());
+	}
+	Ok(())
+}
+
+#[test]
+fn email_deserialization_rejects_random_bytes() -> anyhow::Result<()> {
+	let mut rng = Rng(0xe3a1);
+	let private_key = PrivateKey::generate(KeyType::Curve25519)?;
+
+	for _ in 0..2048 {
+		let length = rng.below(512);
+		let bytes = rng.bytes(length);
+		assert!(!accept_email(&bytes, &private_key, None));
+	}
+	Ok(())
+}
+
+#[test]
+fn email_decryption_rejects_mutations() -> anyhow::Result<()> {
+	let mut rng = Rng(0xdec1);
+	let private_key = PrivateKey::generate(KeyType::Curve25519)?;
+	let prekey = Prekey::generate()?;
+
+	for prekey in [None, Some(&prekey)] {
+		let bytes = make_email(&private_key, prekey)?.to_bytes()?;
+		assert!(accept_email(&bytes, &private_key, prekey));
+		for m in mutations(&bytes, &mut rng) {
+			assert!(!accept_email(&m, &private_key, prekey));
+		}
+	}
+	Ok(())
+}
+
+#[test]
+fn email_decryption_rejects_unsigned_emails() -> anyhow::Result<()> {
+	let private_key = PrivateKey::generate(KeyType::Curve25519)?;
+	let public_key = PublicKey::from_pem(&private_key.public_key_to_pem()?)?;
+	let data = Data::new(
+		"sender".to_owned(),
+		"title".to_owned(),
+		"text".to_owned(),
+		None,
+	);
+	let email = Email::new(&public_key, data)?;
+	let bytes = email.to_bytes()?;
+	assert!(!accept_email(&bytes, &private_key, None));
+	Ok(())
+}
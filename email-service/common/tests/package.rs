@@ -0,0 +1,283 @@
+//! Round trips of packages through sessions over in-memory streams, and
+//! malformed or tampered framing, like from a malicious node or peer, that
+//! must be rejected.
+
+use {
+	common::{
+		crypto::IdentityKey,
+		error::{
+			ReceivePackageBytesError, ReceivePackageError, RequestPackageError,
+		},
+		package::{Action, Package, Session},
+	},
+	tokio::io::{AsyncReadExt as _, AsyncWriteExt as _, DuplexStream},
+};
+
+/// Size of chunks, see `consts::PACKAGE_CHUNK_SIZE`.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Size of the tag of an encrypted chunk.
+const TAG_SIZE: usize = 16;
+
+/// Big enough for any package of the tests, so writes don't wait for reads.
+const DUPLEX_SIZE: usize = 1024 * 1024;
+
+/// Makes data of 3.5 chunks, so a package has several full chunks and a
+/// shorter last one.
+fn make_multi_chunk_data() -> Vec<u8> {
+	(0..CHUNK_SIZE * 7 / 2).map(|i| i as u8).collect()
+}
+
+fn make_identity_key() -> anyhow::Result<IdentityKey> {
+	let pem =
+		openssl::pkey::PKey::generate_x25519()?.private_key_to_pem_pkcs8()?;
+	Ok(IdentityKey::from_pem(&pem)?)
+}
+
+/// Encrypts the `client` session with the `server` one.
+async fn handshake<S>(
+	client: &mut Session<S>,
+	server: &mut Session<S>,
+) -> anyhow::Result<()>
+where
+	S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin,
+{
+	let identity_key = make_identity_key()?;
+	let public_key = identity_key.public_key_base64()?;
+	let accept = async {
+		let request = server.receive(None, None).await?;
+		server.accept_handshake(&request, &identity_key).await?;
+		Ok::<_, anyhow::Error>(())
+	};
+	let (handshake, accept) =
+		tokio::join!(client.handshake(&public_key), accept);
+	handshake?;
+	accept
+}
+
+/// Reads the raw frame of one package from the `stream`: the size and each
+/// chunk with its size. Chunks have a tag of `tag_size`.
+async fn read_frame(
+	stream: &mut DuplexStream,
+	tag_size: usize,
+) -> anyhow::Result<([u8; 8], Vec<Vec<u8>>)> {
+	let mut size_bytes = [0; 8];
+	stream.read_exact(&mut size_bytes).await?;
+	let mut size = u64::from_be_bytes(size_bytes) as usize;
+	let mut chunks = Vec::new();
+	while size > 0 {
+		let mut chunk_size_bytes = [0; 4];
+		stream.read_exact(&mut chunk_size_bytes).await?;
+		let mut chunk = vec![0; u32::from_be_bytes(chunk_size_bytes) as usize];
+		stream.read_exact(&mut chunk).await?;
+		size -= chunk.len() - tag_size;
+		chunks.push(chunk);
+	}
+	Ok((size_bytes, chunks))
+}
+
+/// Writes a frame read by `read_frame` to the `stream`.
+async fn write_frame(
+	stream: &mut DuplexStream,
+	size_bytes: &[u8],
+	chunks: &[Vec<u8>],
+) -> anyhow::Result<()> {
+	stream.write_all(size_bytes).await?;
+	for c in chunks {
+		stream.write_all(&(c.len() as u32).to_be_bytes()).await?;
+		stream.write_all(c).await?;
+	}
+	Ok(())
+}
+
+/// Makes an encrypted client session and a server one connected through a
+/// relay, then sends a multi-chunk package from the client and passes its
+/// frame changed by `tamper` to the server. Returns the result of receiving
+/// it by the server.
+async fn receive_tampered<F>(
+	tamper: F,
+) -> anyhow::Result<Result<Package, ReceivePackageError>>
+where
+	F: FnOnce(&mut Vec<Vec<u8>>),
+{
+	let (client_stream, mut client_relay) = tokio::io::duplex(DUPLEX_SIZE);
+	let (server_stream, mut server_relay) = tokio::io::duplex(DUPLEX_SIZE);
+	let mut client = Session::new(client_stream);
+	let mut server = Session::new(server_stream);
+
+	// Pass the handshake as is
+	let identity_key = make_identity_key()?;
+	let public_key = identity_key.public_key_base64()?;
+	let relay = async {
+		let (size, chunks) = read_frame(&mut client_relay, 0).await?;
+		write_frame(&mut server_relay, &size, &chunks).await?;
+		let (size, chunks) = read_frame(&mut server_relay, 0).await?;
+		write_frame(&mut client_relay, &size, &chunks).await
+	};
+	let accept = async {
+		let request = server.receive(None, None).await?;
+		server.accept_handshake(&request, &identity_key).await?;
+		Ok::<_, anyhow::Error>(())
+	};
+	let (handshake, relay, accept) =
+		tokio::join!(client.handshake(&public_key), relay, accept);
+	handshake?;
+	relay?;
+	accept?;
+
+	// Pass the package tampered
+	client
+		.send(&Package::new(Action::SendEmail, make_multi_chunk_data()))
+		.await?;
+	let (size, mut chunks) = read_frame(&mut client_relay, TAG_SIZE).await?;
+	assert!(chunks.len() > 2);
+	tamper(&mut chunks);
+	write_frame(&mut server_relay, &size, &chunks).await?;
+	Ok(server.receive(None, None).await)
+}
+
+#[tokio::test]
+async fn multi_chunk_packages_round_trip() -> anyhow::Result<()> {
+	for encrypted in [false, true] {
+		let (client_stream, server_stream) = tokio::io::duplex(DUPLEX_SIZE);
+		let mut client = Session::new(client_stream);
+		let mut server = Session::new(server_stream);
+		if encrypted {
+			handshake(&mut client, &mut server).await?;
+		}
+
+		let data = make_multi_chunk_data();
+		let request_id = client
+			.send(&Package::new(Action::SendEmail, data.clone()))
+			.await?;
+		let request = server.receive(None, None).await?;
+		assert_eq!(request.action(), Action::SendEmail);
+		assert_eq!(request.request_id(), request_id);
+		assert_eq!(request.data(), &data[..]);
+
+		let response = Package::new(Action::SendEmailSuccess, data.clone());
+		server.respond(&request, &response).await?;
+		let response = client.receive(None, None).await?;
+		assert_eq!(response.request_id(), request_id);
+		assert_eq!(response.data(), &data[..]);
+	}
+	Ok(())
+}
+
+#[tokio::test]
+async fn short_chunks_are_rejected() -> anyhow::Result<()> {
+	let (mut stream, session_stream) = tokio::io::duplex(DUPLEX_SIZE);
+	let mut session = Session::new(session_stream);
+
+	// A chunk shorter than the rest of the package
+	stream.write_all(&100_u64.to_be_bytes()).await?;
+	stream.write_all(&50_u32.to_be_bytes()).await?;
+	stream.write_all(&[0; 50]).await?;
+	assert!(matches!(
+		session.receive(None, None).await,
+		Err(ReceivePackageError::ReceiveBytes(
+			ReceivePackageBytesError::InvalidChunkSize
+		)),
+	));
+	Ok(())
+}
+
+#[tokio::test]
+async fn truncated_chunks_are_rejected() -> anyhow::Result<()> {
+	let (mut stream, session_stream) = tokio::io::duplex(DUPLEX_SIZE);
+	let mut session = Session::new(session_stream);
+
+	// The connection is closed in the middle of a chunk
+	stream.write_all(&100_u64.to_be_bytes()).await?;
+	stream.write_all(&100_u32.to_be_bytes()).await?;
+	stream.write_all(&[0; 50]).await?;
+	drop(stream);
+	assert!(matches!(
+		session.receive(None, None).await,
+		Err(ReceivePackageError::ReceiveBytes(
+			ReceivePackageBytesError::ReceiveData(_)
+		)),
+	));
+	Ok(())
+}
+
+#[tokio::test]
+async fn oversize_packages_are_rejected_by_size() -> anyhow::Result<()> {
+	let max_size = common::config::Limits::default().package_max_size();
+	for size in [max_size as u64 + 1, u64::MAX] {
+		let (mut stream, session_stream) = tokio::io::duplex(DUPLEX_SIZE);
+		let mut session = Session::new(session_stream);
+
+		// Only the size is sent, so the session must not wait for the data
+		stream.write_all(&size.to_be_bytes()).await?;
+		assert!(matches!(
+			session.receive(None, None).await,
+			Err(ReceivePackageError::ReceiveBytes(
+				ReceivePackageBytesError::TooBig
+			)),
+		));
+	}
+	Ok(())
+}
+
+#[tokio::test]
+async fn responses_out_of_order_are_matched_by_request_id(
+) -> anyhow::Result<()> {
+	let (client_stream, server_stream) = tokio::io::duplex(DUPLEX_SIZE);
+	let mut client = Session::new(client_stream);
+	let mut server = Session::new(server_stream);
+
+	// Responses in reverse order keep the request identifiers
+	let first_id =
+		client.send(&Package::new(Action::CheckConnection, [])).await?;
+	let second_id =
+		client.send(&Package::new(Action::CheckConnection, [])).await?;
+	assert_ne!(first_id, second_id);
+	let first = server.receive(None, None).await?;
+	let second = server.receive(None, None).await?;
+	let response = Package::new(Action::CheckConnectionSuccess, []);
+	server.respond(&second, &response).await?;
+	server.respond(&first, &response).await?;
+	assert_eq!(client.receive(None, None).await?.request_id(), second_id);
+	assert_eq!(client.receive(None, None).await?.request_id(), first_id);
+
+	// A request doesn't accept a response to another one
+	client.send(&Package::new(Action::CheckConnection, [])).await?;
+	let respond = async {
+		let first = server.receive(None, None).await?;
+		let _second = server.receive(None, None).await?;
+		server.respond(&first, &response).await?;
+		Ok::<_, anyhow::Error>(())
+	};
+	let request = Package::new(Action::CheckConnection, []);
+	let (request, respond) =
+		tokio::join!(client.request(&request, None), respond);
+	respond?;
+	assert!(matches!(request, Err(RequestPackageError::InvalidRequestId)));
+	Ok(())
+}
+
+#[tokio::test]
+async fn tampered_encrypted_chunks_fail_to_decrypt() -> anyhow::Result<()> {
+	let received = receive_tampered(|_| {}).await?;
+	assert_eq!(received?.data(), &make_multi_chunk_data()[..]);
+
+	// A flipped bit
+	let received = receive_tampered(|chunks| chunks[1][0] ^= 1).await?;
+	assert!(matches!(
+		received,
+		Err(ReceivePackageError::ReceiveBytes(
+			ReceivePackageBytesError::Decrypt(_)
+		)),
+	));
+
+	// Chunks of the same size swapped
+	let received = receive_tampered(|chunks| chunks.swap(1, 2)).await?;
+	assert!(matches!(
+		received,
+		Err(ReceivePackageError::ReceiveBytes(
+			ReceivePackageBytesError::Decrypt(_)
+		)),
+	));
+	Ok(())
+}
@@ -0,0 +1 @@
+DROP TABLE prekeys;
@@ -0,0 +1,11 @@
+-- # Explanation of some fields
+--
+-- `.recipient_public_key_pem_hash` - hash of the public key PEM of the
+-- mailbox owner. Needed for easy search.
+-- `.signed_prekey_bytes` - serialized `common::crypto::SignedPrekey`.
+CREATE TABLE prekeys (
+	id SERIAL PRIMARY KEY,
+	recipient_public_key_pem_hash BYTEA NOT NULL,
+	signed_prekey_bytes BYTEA NOT NULL,
+	created_at TIMESTAMP NOT NULL DEFAULT NOW()
+)
@@ -0,0 +1 @@
+ALTER TABLE prekeys DROP COLUMN last_resort;
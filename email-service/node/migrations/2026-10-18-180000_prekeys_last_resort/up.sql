@@ -0,0 +1,5 @@
+-- # Explanation of some fields
+--
+-- `.last_resort` - whether the prekey is given when the mailbox has no
+-- one-time prekeys, it is never deleted, only replaced.
+ALTER TABLE prekeys ADD COLUMN last_resort BOOLEAN NOT NULL DEFAULT FALSE
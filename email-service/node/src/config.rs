@@ -5,22 +5,32 @@ use anyhow::{Context as _, Result};
 #[non_exhaustive]
 pub(crate) struct Config {
 	password: Option<String>,
+	/// X25519 private key PEM. See [`common::crypto::IdentityKey`].
+	identity_key: Option<String>,
 	other_nodes: Option<std::collections::HashSet<OtherNode>>,
+	#[serde(default)]
+	limits: common::config::Limits,
 }
 
 impl Config {
 	common::accessor!(as_deref password -> Option<&str>);
 
+	common::accessor!(as_deref identity_key -> Option<&str>);
+
 	common::accessor!(
 		as_ref other_nodes -> Option<&std::collections::HashSet<OtherNode>>
 	);
 
+	common::accessor!(& limits -> &common::config::Limits);
+
 	pub async fn load() -> Result<Self> {
-		common::helpers::deserialize_json_from_file(
+		let config: Self = common::helpers::deserialize_json_from_file(
 			crate::consts::CONFIG_PATH.as_path(),
 		)
 		.await
-		.context("Failed to deserialize config from file.")
+		.context("Failed to deserialize config from file.")?;
+		config.limits.validate().context("Invalid limits.")?;
+		Ok(config)
 	}
 }
 
@@ -28,18 +38,25 @@ impl Config {
 pub(crate) struct OtherNode {
 	address: std::net::SocketAddr,
 	password: Option<String>,
+	identity_key: Option<String>,
 }
 
 impl OtherNode {
 	common::accessor!(copy address -> std::net::SocketAddr);
 
 	common::accessor!(as_deref password -> Option<&str>);
+
+	common::accessor!(as_deref identity_key -> Option<&str>);
 }
 
-impl From<OtherNode> for (std::net::SocketAddr, Option<String>) {
+impl From<OtherNode>
+	for (std::net::SocketAddr, Option<String>, Option<String>)
+{
 	#[inline]
 	#[must_use]
-	fn from(n: OtherNode) -> (std::net::SocketAddr, Option<String>) {
-		(n.address, n.password)
+	fn from(
+		n: OtherNode,
+	) -> (std::net::SocketAddr, Option<String>, Option<String>) {
+		(n.address, n.password, n.identity_key)
 	}
 }
@@ -4,3 +4,8 @@ lazy_static::lazy_static! {
 }
 
 pub(crate) const CONTAINER_ADDRESS: &str = "0.0.0.0:8000";
+pub(crate) const EMAIL_IDS_MAX_LIMIT: i64 = 1024;
+pub(crate) const PREKEYS_MAX_COUNT: i64 = 256;
+pub(crate) const PREKEY_TAKES_INTERVAL: std::time::Duration =
+	std::time::Duration::from_hours(1);
+pub(crate) const PREKEY_TAKES_MAX_COUNT: u32 = 16;
@@ -11,7 +11,7 @@ impl Db {
 
 	pub(crate) async fn get_email_bytes(
 		&self,
-		index: i64,
+		id: i32,
 		recipient_public_key_hash: &[u8; 32],
 	) -> Result<Vec<u8>> {
 		use {
@@ -23,11 +23,11 @@ impl Db {
 		let mut connection =
 			self.0.get().await.context("Failed to get a connection.")?;
 		let bytes: Vec<u8> = table
+			.filter(dsl::id.eq(id))
 			.filter(
 				dsl::recipient_public_key_pem_hash
 					.eq(recipient_public_key_hash.to_vec()),
 			)
-			.offset(index)
 			.select(dsl::email_bytes)
 			.first(&mut connection)
 			.await
@@ -35,6 +35,62 @@ impl Db {
 		Ok(bytes)
 	}
 
+	pub(crate) async fn get_email_ids(
+		&self,
+		after_id: i32,
+		limit: i64,
+		recipient_public_key_hash: &[u8; 32],
+	) -> Result<Vec<i32>> {
+		use {
+			crate::schema::emails::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		let ids: Vec<i32> = table
+			.filter(
+				dsl::recipient_public_key_pem_hash
+					.eq(recipient_public_key_hash.to_vec()),
+			)
+			.filter(dsl::id.gt(after_id))
+			.order(dsl::id)
+			.limit(limit)
+			.select(dsl::id)
+			.load(&mut connection)
+			.await
+			.context("Failed to execute a query.")?;
+		Ok(ids)
+	}
+
+	pub(crate) async fn get_emails_bytes(
+		&self,
+		ids: &[i32],
+		recipient_public_key_hash: &[u8; 32],
+	) -> Result<Vec<(i32, Vec<u8>)>> {
+		use {
+			crate::schema::emails::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		let emails: Vec<(i32, Vec<u8>)> = table
+			.filter(
+				dsl::recipient_public_key_pem_hash
+					.eq(recipient_public_key_hash.to_vec()),
+			)
+			.filter(dsl::id.eq_any(ids))
+			.order(dsl::id)
+			.select((dsl::id, dsl::email_bytes))
+			.load(&mut connection)
+			.await
+			.context("Failed to execute a query.")?;
+		Ok(emails)
+	}
+
 	pub(crate) async fn get_emails_count(
 		&self,
 		recipient_public_key_hash: &[u8; 32],
@@ -59,9 +115,9 @@ impl Db {
 		Ok(count)
 	}
 
-	/// # Debug panic
-	///
-	/// If `email.check_encrypted_integrity()` is `false`.
+	/// The `email` must be checked with
+	/// [`check_encrypted_integrity`](common::email::Email::check_encrypted_integrity)
+	/// before.
 	pub(crate) async fn add_email(
 		&self,
 		email: &common::email::Email,
@@ -70,8 +126,6 @@ impl Db {
 			crate::schema::emails::table, diesel_async::RunQueryDsl as _,
 			std::convert::TryFrom as _,
 		};
-		debug_assert!(email.check_encrypted_integrity());
-
 		let new_email = crate::models::NewEmail::try_from(email)
 			.context("Failed to create a new email.")?;
 		let mut connection =
@@ -104,4 +158,214 @@ impl Db {
 			.context("Failed to execute a query.")?;
 		Ok(())
 	}
+
+	/// Adds serialized signed one-time prekeys of the mailbox, unless the
+	/// mailbox would have more than `max_count` of them, and replaces its
+	/// last-resort prekey with the serialized `last_resort_prekey`. Returns
+	/// `false` if the prekeys were not added.
+	///
+	/// The count and the writes are in one transaction that locks the
+	/// mailbox, so concurrent calls can't exceed `max_count` and the mailbox
+	/// is never left without its last-resort prekey.
+	pub(crate) async fn add_prekeys(
+		&self,
+		recipient_public_key_hash: &[u8; 32],
+		signed_prekeys_bytes: Vec<Vec<u8>>,
+		last_resort_prekey: Option<Vec<u8>>,
+		max_count: i64,
+	) -> Result<bool> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::{
+				scoped_futures::ScopedFutureExt as _, AsyncConnection as _,
+				RunQueryDsl as _,
+			},
+		};
+
+		let new_count = <i64 as std::convert::TryFrom<usize>>::try_from(
+			signed_prekeys_bytes.len(),
+		)
+		.context("Too many prekeys.")?;
+		let hash = recipient_public_key_hash.to_vec();
+		let mut lock_key_bytes = [0; 8];
+		lock_key_bytes.copy_from_slice(&hash[..8]);
+		let lock_key = i64::from_be_bytes(lock_key_bytes);
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		connection
+			.transaction::<_, anyhow::Error, _>(|c| {
+				async move {
+					// Lock the mailbox until the end of the transaction
+					diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+						.bind::<diesel::sql_types::BigInt, _>(lock_key)
+						.execute(c)
+						.await?;
+					let count = table
+						.filter(dsl::recipient_public_key_pem_hash.eq(&hash))
+						.filter(dsl::last_resort.eq(false))
+						.count()
+						.get_result::<i64>(c)
+						.await?;
+					if count + new_count > max_count {
+						return Ok(false);
+					}
+					if let Some(b) = last_resort_prekey {
+						let filter = table
+							.filter(
+								dsl::recipient_public_key_pem_hash.eq(&hash),
+							)
+							.filter(dsl::last_resort.eq(true));
+						diesel::delete(filter).execute(c).await?;
+						diesel::insert_into(table)
+							.values((
+								dsl::recipient_public_key_pem_hash.eq(&hash),
+								dsl::signed_prekey_bytes.eq(b),
+								dsl::last_resort.eq(true),
+							))
+							.execute(c)
+							.await?;
+					}
+					let new_prekeys: Vec<_> = signed_prekeys_bytes
+						.into_iter()
+						.map(|b| {
+							(
+								dsl::recipient_public_key_pem_hash.eq(&hash),
+								dsl::signed_prekey_bytes.eq(b),
+							)
+						})
+						.collect();
+					diesel::insert_into(table)
+						.values(new_prekeys)
+						.execute(c)
+						.await?;
+					Ok(true)
+				}
+				.scope_boxed()
+			})
+			.await
+			.context("Failed to execute a transaction.")
+	}
+
+	/// Returns the number of one-time prekeys of the mailbox.
+	pub(crate) async fn get_prekeys_count(
+		&self,
+		recipient_public_key_hash: &[u8; 32],
+	) -> Result<i64> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		let count = table
+			.filter(
+				dsl::recipient_public_key_pem_hash
+					.eq(recipient_public_key_hash.to_vec()),
+			)
+			.filter(dsl::last_resort.eq(false))
+			.count()
+			.get_result::<i64>(&mut connection)
+			.await
+			.context("Failed to execute a query.")?;
+		Ok(count)
+	}
+
+	/// Returns the serialized last-resort prekey of the mailbox, it is not
+	/// deleted, unlike one-time ones.
+	pub(crate) async fn get_last_resort_prekey(
+		&self,
+		recipient_public_key_hash: &[u8; 32],
+	) -> Result<Option<Vec<u8>>> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{
+				ExpressionMethods as _, OptionalExtension as _, QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		let bytes = table
+			.filter(
+				dsl::recipient_public_key_pem_hash
+					.eq(recipient_public_key_hash.to_vec()),
+			)
+			.filter(dsl::last_resort.eq(true))
+			.order(dsl::id.desc())
+			.select(dsl::signed_prekey_bytes)
+			.first(&mut connection)
+			.await
+			.optional()
+			.context("Failed to execute a query.")?;
+		Ok(bytes)
+	}
+
+	/// Deletes the oldest one-time prekey of the mailbox and returns it, so
+	/// each one-time prekey is given only once.
+	pub(crate) async fn take_prekey(
+		&self,
+		recipient_public_key_hash: &[u8; 32],
+	) -> Result<Option<Vec<u8>>> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{
+				ExpressionMethods as _, OptionalExtension as _, QueryDsl as _,
+			},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		let Some(oldest_id) = table
+			.filter(
+				dsl::recipient_public_key_pem_hash
+					.eq(recipient_public_key_hash.to_vec()),
+			)
+			.filter(dsl::last_resort.eq(false))
+			.order(dsl::id)
+			.select(dsl::id)
+			.first::<i32>(&mut connection)
+			.await
+			.optional()
+			.context("Failed to execute a query.")?
+		else {
+			return Ok(None);
+		};
+		// Nothing is deleted if the prekey was taken by another session
+		let bytes: Option<Vec<u8>> =
+			diesel::delete(table.filter(dsl::id.eq(oldest_id)))
+				.returning(dsl::signed_prekey_bytes)
+				.get_result(&mut connection)
+				.await
+				.optional()
+				.context("Failed to execute a query.")?;
+		Ok(bytes)
+	}
+
+	/// Deletes one-time prekeys older than `than`.
+	pub(crate) async fn delete_old_prekeys(
+		&self,
+		than: std::time::Duration,
+	) -> Result<()> {
+		use {
+			crate::schema::prekeys::{dsl, table},
+			diesel::{ExpressionMethods as _, QueryDsl as _},
+			diesel_async::RunQueryDsl as _,
+		};
+
+		let mut connection =
+			self.0.get().await.context("Failed to get a connection.")?;
+		let filter = table
+			.filter(dsl::created_at.lt(std::time::SystemTime::now() - than))
+			.filter(dsl::last_resort.eq(false));
+		diesel::delete(filter)
+			.execute(&mut connection)
+			.await
+			.context("Failed to execute a query.")?;
+		Ok(())
+	}
 }
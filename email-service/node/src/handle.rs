@@ -1,168 +1,743 @@
 use anyhow::{Context as _, Result};
 
+/// Mailbox authentication state of a session.
+///
+/// See [`Action`](common::package::Action) for the authentication flow.
+#[derive(Default)]
+struct Mailbox {
+	/// Public key PEM and nonce of the pending challenge.
+	challenge: Option<(Vec<u8>, Vec<u8>)>,
+	/// Public key PEM hash of the authenticated mailbox.
+	public_key_pem_hash: Option<[u8; 32]>,
+}
+
+impl Mailbox {
+	#[must_use]
+	fn is_authenticated(&self, public_key_pem_hash: &[u8; 32]) -> bool {
+		self.public_key_pem_hash.as_ref() == Some(public_key_pem_hash)
+	}
+}
+
+/// Request actions handled by the node, advertised in `Action::Hello`.
+/// Password challenge actions are handled by
+/// [`Session::receive`](common::package::Session::receive).
+const SUPPORTED_ACTIONS: [common::package::Action; 16] = [
+	common::package::Action::AuthenticateMailbox,
+	common::package::Action::AuthenticatePassword,
+	common::package::Action::CheckConnection,
+	common::package::Action::GetEmail,
+	common::package::Action::GetEmailIds,
+	common::package::Action::GetEmails,
+	common::package::Action::GetEmailsCount,
+	common::package::Action::GetMailboxChallenge,
+	common::package::Action::GetPasswordChallenge,
+	common::package::Action::GetPrekey,
+	common::package::Action::GetPrekeysCount,
+	common::package::Action::GetProofOfWorkDifficulty,
+	common::package::Action::Handshake,
+	common::package::Action::Hello,
+	common::package::Action::PublishPrekeys,
+	common::package::Action::SendEmail,
+];
+
 /// Entry point for `stream` data processing.
+///
+/// Handles packages from the [`session`](common::package::Session) until it
+/// is closed.
 pub(crate) async fn stream(
-	mut stream: tokio::net::TcpStream,
+	stream: tokio::net::TcpStream,
 	from_address: std::net::SocketAddr,
-	state: &crate::state::State,
+	state: &'static crate::state::State,
 ) -> Result<()> {
-	use common::package::Action;
-	let package = common::receive_package_or_else!(
-		&mut stream,
-		from_address,
-		state.config().password(),
-		None,
-		return Ok(()),
+	use common::error::{ReceivePackageBytesError, ReceivePackageError};
+
+	let mut session = common::package::Session::with_limits(
+		stream,
+		*state.config().limits(),
 	);
+	let mut mailbox = Mailbox::default();
+	loop {
+		let package =
+			match session.receive(state.config().password(), None).await {
+				Ok(p) => p,
+				Err(ReceivePackageError::ReceiveBytes(
+					ReceivePackageBytesError::Closed,
+				)) => {
+					common::debug!("Session with {from_address} is closed.");
+					return Ok(());
+				}
+				Err(ReceivePackageError::PasswordRequired) => {
+					common::debug!(
+						"{from_address} has sent a package without a password."
+					);
+					continue;
+				}
+				Err(e) => {
+					common::debug!(
+						"Failed to receive a package from {}: {}",
+						from_address,
+						e
+					);
+					return Ok(());
+				}
+			};
+		handle_package(&mut session, &mut mailbox, state, package).await?;
+	}
+}
+
+/// Calls the handler of the `package` action.
+async fn handle_package(
+	session: &mut common::package::Session,
+	mailbox: &mut Mailbox,
+	state: &'static crate::state::State,
+	package: common::package::Package,
+) -> Result<()> {
+	use common::package::Action;
+
 	match package.action() {
-		Action::CheckConnection => check_connection(stream)
+		Action::AuthenticateMailbox => {
+			authenticate_mailbox(session, mailbox, &package)
+				.await
+				.context("Failed to handle mailbox authentication.")
+		}
+		Action::CheckConnection => check_connection(session, &package)
 			.await
 			.context("Failed to handle connection check."),
-		Action::GetEmail => get_email(stream, state, package)
+		Action::GetEmail => get_email(session, mailbox, state, &package)
 			.await
 			.context("Failed to handle email getting."),
-		Action::GetEmailsCount => get_emails_count(stream, state, package)
+		Action::GetEmailIds => {
+			get_email_ids(session, mailbox, state, &package)
+				.await
+				.context("Failed to handle email identifiers getting.")
+		}
+		Action::GetEmails => get_emails(session, mailbox, state, &package)
+			.await
+			.context("Failed to handle emails getting."),
+		Action::GetEmailsCount => {
+			get_emails_count(session, mailbox, state, &package)
+				.await
+				.context("Failed to handle emails count getting.")
+		}
+		Action::GetMailboxChallenge => {
+			get_mailbox_challenge(session, mailbox, &package)
+				.await
+				.context("Failed to handle mailbox challenge getting.")
+		}
+		Action::GetPrekey => get_prekey(session, state, &package)
+			.await
+			.context("Failed to handle prekey getting."),
+		Action::GetPrekeysCount => {
+			get_prekeys_count(session, mailbox, state, &package)
+				.await
+				.context("Failed to handle prekeys count getting.")
+		}
+		Action::GetProofOfWorkDifficulty => {
+			get_proof_of_work_difficulty(session, state, &package)
+				.await
+				.context("Failed to handle proof-of-work difficulty getting.")
+		}
+		Action::Handshake => handshake(session, state, &package)
 			.await
-			.context("Failed to handle emails count getting."),
-		Action::SendEmail => send_email(stream, state, package)
+			.context("Failed to handle handshake."),
+		Action::Hello => {
+			hello(session, &package).await.context("Failed to handle hello.")
+		}
+		Action::PublishPrekeys => {
+			publish_prekeys(session, mailbox, state, &package)
+				.await
+				.context("Failed to handle prekeys publishing.")
+		}
+		Action::SendEmail => send_email(session, state, package)
 			.await
 			.context("Failed to handle email sending."),
-		_ => Ok(()),
+		_ => unsupported_action(session, &package)
+			.await
+			.context("Failed to handle an unsupported action."),
 	}
 }
 
-async fn check_connection(mut stream: tokio::net::TcpStream) -> Result<()> {
-	common::package::Package::new(
-		None,
+/// Responds with a random nonce that must be signed by the private key of the
+/// public key PEM from the package data to authenticate the mailbox.
+async fn get_mailbox_challenge(
+	session: &mut common::package::Session,
+	mailbox: &mut Mailbox,
+	package: &common::package::Package,
+) -> Result<()> {
+	// Generate a nonce and save the challenge
+	let Ok(nonce) = common::crypto::generate_random_bytes(None) else {
+		let fail_response = common::package::Package::new(
+			common::package::Action::GetMailboxChallengeFail,
+			vec![],
+		);
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	mailbox.challenge = Some((package.data().to_vec(), nonce.clone()));
+
+	// Send response
+	let response = common::package::Package::new(
+		common::package::Action::GetMailboxChallengeSuccess,
+		nonce,
+	);
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+/// Checks the nonce signature from the package data. The challenge can be
+/// used only once.
+async fn authenticate_mailbox(
+	session: &mut common::package::Session,
+	mailbox: &mut Mailbox,
+	package: &common::package::Package,
+) -> Result<()> {
+	let authenticated = match mailbox.challenge.take() {
+		Some((public_key_pem, nonce)) => matches!(
+			common::crypto::verify_challenge(
+				&public_key_pem,
+				&nonce,
+				package.data()
+			),
+			Ok(true)
+		)
+		.then(|| common::crypto::hash(public_key_pem)),
+		None => None,
+	};
+	let response = common::package::Package::new(
+		if authenticated.is_some() {
+			common::package::Action::AuthenticateMailboxSuccess
+		} else {
+			common::package::Action::AuthenticateMailboxFail
+		},
+		vec![],
+	);
+	mailbox.public_key_pem_hash = authenticated;
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+/// Encrypts the session if the identity key is set in the config.
+async fn handshake(
+	session: &mut common::package::Session,
+	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	let Some(identity_key) = state.identity_key() else {
+		let fail_response = common::package::Package::new(
+			common::package::Action::HandshakeFail,
+			vec![],
+		);
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	session
+		.accept_handshake(package, identity_key)
+		.await
+		.context("Failed to accept handshake.")
+}
+
+/// Responds with the protocol version and `SUPPORTED_ACTIONS`.
+async fn hello(
+	session: &mut common::package::Session,
+	package: &common::package::Package,
+) -> Result<()> {
+	let data = common::package::HelloData::new(SUPPORTED_ACTIONS.into());
+	let response = common::package::Package::new(
+		common::package::Action::HelloSuccess,
+		bincode::serialize(&data).context("Failed to serialize.")?,
+	);
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+/// Responds with `Action::UnsupportedAction`, so the client can tell that the
+/// node is older (or newer) instead of waiting for a response.
+async fn unsupported_action(
+	session: &mut common::package::Session,
+	package: &common::package::Package,
+) -> Result<()> {
+	common::debug!("Unsupported action: {:?}.", package.action());
+	let response = common::package::Package::new(
+		common::package::Action::UnsupportedAction,
+		vec![],
+	);
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+async fn check_connection(
+	session: &mut common::package::Session,
+	package: &common::package::Package,
+) -> Result<()> {
+	let response = common::package::Package::new(
 		common::package::Action::CheckConnectionSuccess,
 		vec![],
-	)
-	.send(&mut stream)
-	.await
-	.context("Failed to send a package.")
+	);
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send a package.")
+}
+
+/// Responds with the proof-of-work difficulty required for new emails, so
+/// clients don't have to know it in advance.
+async fn get_proof_of_work_difficulty(
+	session: &mut common::package::Session,
+	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	let response = common::package::Package::new(
+		common::package::Action::GetProofOfWorkDifficultySuccess,
+		bincode::serialize(
+			&state.config().limits().proof_of_work_difficulty(),
+		)
+		.context("Failed to serialize.")?,
+	);
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send a package.")
 }
 
 async fn get_email(
-	mut stream: tokio::net::TcpStream,
+	session: &mut common::package::Session,
+	mailbox: &Mailbox,
 	state: &crate::state::State,
-	package: common::package::Package,
+	package: &common::package::Package,
 ) -> Result<()> {
 	let fail_response = common::package::Package::new(
-		None,
 		common::package::Action::GetEmailFail,
 		vec![],
 	);
 
 	// Deserialize package data
-	let (index, recipient_public_key_hash): (i64, [u8; 32]) =
-		match bincode::deserialize(package.data()) {
-			Ok((n, h)) => (n, h),
-			Err(_) => {
-				return fail_response
-					.send(&mut stream)
+	let (id, recipient_public_key_hash): (i32, [u8; 32]) =
+		match common::helpers::deserialize(package.data()) {
+			Ok((n, h)) if mailbox.is_authenticated(&h) => (n, h),
+			_ => {
+				return session
+					.respond(package, &fail_response)
 					.await
 					.context("Failed to send fail response.");
 			}
 		};
 
 	// Get encrypted email bytes and send response
+	let response =
+		match state.db().get_email_bytes(id, &recipient_public_key_hash).await
+		{
+			Ok(b) => common::package::Package::new(
+				common::package::Action::GetEmailSuccess,
+				b,
+			),
+			_ => fail_response,
+		};
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+async fn get_email_ids(
+	session: &mut common::package::Session,
+	mailbox: &Mailbox,
+	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	let fail_response = common::package::Package::new(
+		common::package::Action::GetEmailIdsFail,
+		vec![],
+	);
+
+	// Deserialize package data
+	let Ok(data) = common::helpers::deserialize::<
+		common::package::GetEmailIdsData,
+	>(package.data()) else {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	if !mailbox.is_authenticated(data.recipient_public_key_pem_hash()) {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	}
+
+	// Get email identifiers and send response
 	let response = match state
 		.db()
-		.get_email_bytes(index, &recipient_public_key_hash)
+		.get_email_ids(
+			data.after_id(),
+			data.limit().min(crate::consts::EMAIL_IDS_MAX_LIMIT),
+			data.recipient_public_key_pem_hash(),
+		)
 		.await
 	{
-		Ok(b) => common::package::Package::new(
-			None,
-			common::package::Action::GetEmailSuccess,
-			b,
+		Ok(ref ids) => common::package::Package::new(
+			common::package::Action::GetEmailIdsSuccess,
+			bincode::serialize(ids).context("Failed to serialize.")?,
 		),
-		_ => fail_response,
+		Err(_) => fail_response,
+	};
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+/// Responds with as many emails as fit into the requested size, but with at
+/// least one email, so a big email can't block the client.
+async fn get_emails(
+	session: &mut common::package::Session,
+	mailbox: &Mailbox,
+	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	let fail_response = common::package::Package::new(
+		common::package::Action::GetEmailsFail,
+		vec![],
+	);
+
+	// Deserialize package data
+	let Ok(data) = common::helpers::deserialize::<
+		common::package::GetEmailsData,
+	>(package.data()) else {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	if !mailbox.is_authenticated(data.recipient_public_key_pem_hash()) {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	}
+
+	// Get encrypted emails bytes
+	let Ok(emails) = state
+		.db()
+		.get_emails_bytes(data.ids(), data.recipient_public_key_pem_hash())
+		.await
+	else {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
 	};
-	response.send(&mut stream).await.context("Failed to send response.")
+
+	// Take emails within the size limit (leaving some space for the package
+	// itself) and send response
+	let max_size = data
+		.max_size()
+		.min(state.config().limits().package_max_size() as u64 - 1024);
+	let mut size = 0;
+	let emails: Vec<(i32, Vec<u8>)> = emails
+		.into_iter()
+		.enumerate()
+		.take_while(|(i, (_, e))| {
+			size += e.len() as u64 + 12;
+			*i == 0 || size <= max_size
+		})
+		.map(|(_, e)| e)
+		.collect();
+	let response = common::package::Package::new(
+		common::package::Action::GetEmailsSuccess,
+		bincode::serialize(&emails).context("Failed to serialize.")?,
+	);
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
 }
 
 async fn get_emails_count(
-	mut stream: tokio::net::TcpStream,
+	session: &mut common::package::Session,
+	mailbox: &Mailbox,
 	state: &crate::state::State,
-	package: common::package::Package,
+	package: &common::package::Package,
 ) -> Result<()> {
 	use std::convert::TryInto as _;
 
 	let fail_response = common::package::Package::new(
-		None,
 		common::package::Action::GetEmailsCountFail,
 		vec![],
 	);
 
 	// Convert package data to hash
 	let Ok(hash) = package.data().try_into() else {
-		return fail_response
-			.send(&mut stream)
+		return session
+			.respond(package, &fail_response)
 			.await
 			.context("Failed to send a fail response.");
 	};
+	if !mailbox.is_authenticated(hash) {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	}
 
 	// Get emails count and send response
 	let response = match state.db().get_emails_count(hash).await {
 		Ok(ref c) => common::package::Package::new(
-			None,
 			common::package::Action::GetEmailsCountSuccess,
 			bincode::serialize(c).context("Failed to serialize.")?,
 		),
 		Err(_) => fail_response,
 	};
-	response.send(&mut stream).await.context("Failed to send a package.")
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send a package.")
 }
 
-/// Attempts to add a email to the database. If successful, calls
-/// `common::send_email_to_nodes`.
-async fn send_email(
-	mut stream: tokio::net::TcpStream,
+/// Adds signed one-time prekeys to the authenticated mailbox, unless it would
+/// have more than `consts::PREKEYS_MAX_COUNT` of them, and replaces its
+/// last-resort prekey.
+async fn publish_prekeys(
+	session: &mut common::package::Session,
+	mailbox: &Mailbox,
+	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	let fail_response = common::package::Package::new(
+		common::package::Action::PublishPrekeysFail,
+		vec![],
+	);
+
+	// Deserialize package data
+	let Ok(data) = common::helpers::deserialize::<
+		common::package::PublishPrekeysData,
+	>(package.data()) else {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	if !mailbox.is_authenticated(data.recipient_public_key_pem_hash()) {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	}
+
+	// Add prekeys and send response
+	let signed_prekeys_bytes = data
+		.prekeys()
+		.iter()
+		.map(bincode::serialize)
+		.collect::<Result<_, _>>()
+		.context("Failed to serialize.")?;
+	let last_resort_prekey_bytes = data
+		.last_resort_prekey()
+		.map(bincode::serialize)
+		.transpose()
+		.context("Failed to serialize.")?;
+	let response = match state
+		.db()
+		.add_prekeys(
+			data.recipient_public_key_pem_hash(),
+			signed_prekeys_bytes,
+			last_resort_prekey_bytes,
+			crate::consts::PREKEYS_MAX_COUNT,
+		)
+		.await
+	{
+		Ok(true) => common::package::Package::new(
+			common::package::Action::PublishPrekeysSuccess,
+			vec![],
+		),
+		_ => fail_response,
+	};
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send response.")
+}
+
+async fn get_prekeys_count(
+	session: &mut common::package::Session,
+	mailbox: &Mailbox,
+	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	use std::convert::TryInto as _;
+
+	let fail_response = common::package::Package::new(
+		common::package::Action::GetPrekeysCountFail,
+		vec![],
+	);
+
+	// Convert package data to hash
+	let Ok(hash) = package.data().try_into() else {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	if !mailbox.is_authenticated(hash) {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	}
+
+	// Get prekeys count and send response
+	let count = async {
+		Ok::<_, anyhow::Error>(common::package::PrekeysCountData::new(
+			state.db().get_prekeys_count(hash).await?,
+			state.db().get_last_resort_prekey(hash).await?.is_some(),
+		))
+	};
+	let response = match count.await {
+		Ok(ref c) => common::package::Package::new(
+			common::package::Action::GetPrekeysCountSuccess,
+			bincode::serialize(c).context("Failed to serialize.")?,
+		),
+		Err(_) => fail_response,
+	};
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send a package.")
+}
+
+/// Responds with a signed one-time prekey of the mailbox from the package data
+/// and deletes it, so it is used only once. Doesn't require mailbox
+/// authentication, since senders take prekeys of recipients, so the
+/// last-resort prekey is given instead when there are no one-time prekeys or
+/// `consts::PREKEY_TAKES_MAX_COUNT` of them were taken recently. Missing
+/// mailboxes and ones without prekeys are responded the same way.
+async fn get_prekey(
+	session: &mut common::package::Session,
 	state: &crate::state::State,
+	package: &common::package::Package,
+) -> Result<()> {
+	use std::convert::TryInto as _;
+
+	let fail_response = common::package::Package::new(
+		common::package::Action::GetPrekeyFail,
+		vec![],
+	);
+
+	// Convert package data to hash
+	let Ok(hash) = package.data().try_into() else {
+		return session
+			.respond(package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+
+	// Take a one-time prekey or get the last-resort one and send response
+	let prekey = async {
+		if state.prekey_takes().try_take(hash) {
+			if let Some(b) = state.db().take_prekey(hash).await? {
+				return Ok(Some(b));
+			}
+		}
+		state.db().get_last_resort_prekey(hash).await
+	};
+	let response = match prekey.await {
+		Ok(Some(b)) => common::package::Package::new(
+			common::package::Action::GetPrekeySuccess,
+			b,
+		),
+		_ => fail_response,
+	};
+	session
+		.respond(package, &response)
+		.await
+		.context("Failed to send a package.")
+}
+
+/// Attempts to add a email to the database. If successful, spawns
+/// `forward_email`, so the session is not blocked while the email is
+/// forwarded.
+async fn send_email(
+	session: &mut common::package::Session,
+	state: &'static crate::state::State,
 	package: common::package::Package,
 ) -> Result<()> {
-	let email: common::email::Email =
-		if let Ok(ee) = bincode::deserialize(package.data()) {
-			ee
-		} else {
-			return Err(anyhow::anyhow!("Invalid data."));
-		};
-	if !email.check_encrypted_integrity() {
-		return Err(anyhow::anyhow!("Invalid email."));
+	let fail_response = common::package::Package::new(
+		common::package::Action::SendEmailFail,
+		vec![],
+	);
+
+	// Deserialize and check the email
+	let Ok(email) = common::email::Email::from_bytes(package.data()) else {
+		return session
+			.respond(&package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
+	};
+	if !email.check_encrypted_integrity(
+		state.config().limits().proof_of_work_difficulty(),
+	) {
+		return session
+			.respond(&package, &fail_response)
+			.await
+			.context("Failed to send a fail response.");
 	}
+
+	// Add the email and send response
 	let response = match state.db().add_email(&email).await {
 		Ok(()) => common::package::Package::new(
-			None,
 			common::package::Action::SendEmailSuccess,
 			vec![],
 		),
-		Err(_) => common::package::Package::new(
-			None,
-			common::package::Action::SendEmailFail,
-			vec![],
-		),
+		Err(_) => fail_response,
 	};
-	response.send(&mut stream).await.context("Failed to send a response")?;
+	session
+		.respond(&package, &response)
+		.await
+		.context("Failed to send a response")?;
 	if response.action() == common::package::Action::SendEmailSuccess {
 		common::debug!("The email was successfully added.");
-
-		if let Some(on) = state.config().other_nodes() {
-			match common::helpers::send_email_to_nodes(
-				package,
-				on.clone(),
-				on.len(),
-				None,
-			)
-			.await
-			.context("Failed to send email to nodes.")?
-			{
-				0 => common::debug!(
-					"The email was not forwarded to other nodes."
-				),
-				c => common::debug!(
-					"The email was successfully forwarded to {c} other nodes.",
-				),
+		tokio::spawn(async move {
+			if let Err(e) = forward_email(state, package).await {
+				common::debug!("Failed to forward the email:\n{:?}\n", e);
 			}
+		});
+	}
+	Ok(())
+}
+
+/// Calls `common::send_email_to_nodes` with other nodes from the config.
+async fn forward_email(
+	state: &crate::state::State,
+	package: common::package::Package,
+) -> Result<()> {
+	if let Some(on) = state.config().other_nodes() {
+		match common::helpers::send_email_to_nodes(
+			package,
+			on.clone(),
+			on.len(),
+			None,
+			*state.config().limits(),
+			|_| {},
+		)
+		.await
+		.context("Failed to send email to nodes.")?
+		{
+			0 => common::debug!("The email was not forwarded to other nodes."),
+			c => common::debug!(
+				"The email was successfully forwarded to {c} other nodes.",
+			),
 		}
 	}
 	Ok(())
@@ -33,6 +33,15 @@ pub async fn launch() -> Result<()> {
 			.context("Failed to make a default state.")?,
 	));
 
+	// Print the identity key, so it can be pinned by clients
+	if let Some(ik) = state.identity_key() {
+		println!(
+			"Identity key: {}",
+			ik.public_key_base64()
+				.context("Failed to get the identity public key.")?
+		);
+	}
+
 	// Spawn tasks
 	tokio::spawn(task::delete_old_emails_task(state));
 
@@ -3,8 +3,8 @@ use anyhow::{Context as _, Error, Result};
 /// # Explanation of some fields
 ///
 /// `self.recipient_public_key_pem_hash` - Duplicate of field from `Email`.
-/// `self.proof_of_work` - proof of work from `Email`. Needed to avoid
-/// duplicates.
+/// `self.proof_of_work` - proof of work hash from `Email`. Needed to avoid
+/// duplicates, even with another encrypted session.
 #[derive(diesel::prelude::Insertable)]
 #[diesel(table_name = crate::schema::emails)]
 pub(super) struct NewEmail {
@@ -16,20 +16,15 @@ pub(super) struct NewEmail {
 impl std::convert::TryFrom<&common::email::Email> for NewEmail {
 	type Error = Error;
 
-	/// # Debug panic
-	///
-	/// If `email.check_encrypted_integrity()` is `false`.
 	fn try_from(email: &common::email::Email) -> Result<Self> {
-		debug_assert!(email.check_encrypted_integrity());
-
 		let email_bytes =
-			bincode::serialize(email).context("Failed to serialize email.")?;
+			email.to_bytes().context("Failed to serialize email.")?;
 		Ok(Self {
 			email_bytes,
 			recipient_public_key_pem_hash: email
 				.recipient_public_key_pem_hash()
 				.to_vec(),
-			proof_of_work: email.compute_hash(),
+			proof_of_work: email.compute_proof_of_work_hash(),
 		})
 	}
 }
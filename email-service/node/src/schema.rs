@@ -9,3 +9,15 @@ diesel::table! {
 		created_at -> Timestamp,
 	}
 }
+
+diesel::table! {
+	prekeys (id) {
+		id -> Int4,
+		recipient_public_key_pem_hash -> Bytea,
+		signed_prekey_bytes -> Bytea,
+		created_at -> Timestamp,
+		last_resort -> Bool,
+	}
+}
+
+diesel::allow_tables_to_appear_in_same_query!(emails, prekeys,);
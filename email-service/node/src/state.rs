@@ -3,6 +3,8 @@ use anyhow::{Context as _, Result};
 pub(crate) struct State {
 	config: crate::config::Config,
 	db: crate::db::Db,
+	identity_key: Option<common::crypto::IdentityKey>,
+	prekey_takes: PrekeyTakes,
 }
 
 impl State {
@@ -10,13 +12,64 @@ impl State {
 
 	common::accessor!(& db -> &crate::db::Db);
 
+	common::accessor!(
+		as_ref identity_key -> Option<&common::crypto::IdentityKey>
+	);
+
+	common::accessor!(& prekey_takes -> &PrekeyTakes);
+
 	pub(crate) async fn try_default() -> Result<Self> {
+		let config = crate::config::Config::load()
+			.await
+			.context("Failed to load the config.")?;
+		let identity_key = match config.identity_key() {
+			Some(pem) => Some(
+				common::crypto::IdentityKey::from_pem(pem.as_bytes())
+					.context("Failed to read the identity key.")?,
+			),
+			None => None,
+		};
 		Ok(Self {
-			config: crate::config::Config::load()
-				.await
-				.context("Failed to load the config.")?,
+			config,
 			db: crate::db::Db::connect()
 				.context("Failed to connect to db.")?,
+			identity_key,
+			prekey_takes: PrekeyTakes::default(),
 		})
 	}
 }
+
+type Takes = std::collections::HashMap<[u8; 32], (std::time::Instant, u32)>;
+
+/// Numbers of one-time prekeys taken from mailboxes since the start of their
+/// `consts::PREKEY_TAKES_INTERVAL`, so anyone who doesn't authenticate can't
+/// drain them.
+#[derive(Default)]
+pub(crate) struct PrekeyTakes(std::sync::Mutex<Takes>);
+
+impl PrekeyTakes {
+	/// Counts a one-time prekey taken from the mailbox, returning `false`
+	/// without counting if `consts::PREKEY_TAKES_MAX_COUNT` were already taken
+	/// in the interval, so the last-resort one should be given.
+	pub(crate) fn try_take(
+		&self,
+		recipient_public_key_hash: &[u8; 32],
+	) -> bool {
+		let mut takes = self.takes();
+		takes.retain(|_, (start, _)| {
+			start.elapsed() < crate::consts::PREKEY_TAKES_INTERVAL
+		});
+		let (_, count) = takes
+			.entry(*recipient_public_key_hash)
+			.or_insert_with(|| (std::time::Instant::now(), 0));
+		if *count >= crate::consts::PREKEY_TAKES_MAX_COUNT {
+			return false;
+		}
+		*count += 1;
+		true
+	}
+
+	fn takes(&self) -> std::sync::MutexGuard<'_, Takes> {
+		self.0.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+	}
+}
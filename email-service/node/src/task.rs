@@ -1,20 +1,26 @@
 use anyhow::{Context as _, Result};
 
-/// Every `common::consts::CHECK_OLD_EMAILS_INTERVAL` removes emails that are
-/// older than `common:consts::EMAILS_MAX_AGE`.
+/// Every `check_old_emails_interval` from the config limits removes emails
+/// and one-time prekeys that are older than `emails_max_age`.
 pub(crate) async fn delete_old_emails_task(
 	state: &crate::state::State,
 ) -> Result<()> {
+	let limits = state.config().limits();
 	loop {
-		tokio::time::sleep(common::consts::CHECK_OLD_EMAILS_INTERVAL).await;
+		tokio::time::sleep(limits.check_old_emails_interval()).await;
 		state
 			.db()
-			.delete_old_emails(common::consts::EMAILS_MAX_AGE)
+			.delete_old_emails(limits.emails_max_age())
 			.await
 			.context("Failed to delete old emails.")?;
+		state
+			.db()
+			.delete_old_prekeys(limits.emails_max_age())
+			.await
+			.context("Failed to delete old prekeys.")?;
 		common::debug!(
-			"Emails older than {} seconds were deleted.",
-			common::consts::EMAILS_MAX_AGE.as_secs()
+			"Emails and prekeys older than {} seconds were deleted.",
+			limits.emails_max_age().as_secs()
 		);
 	}
 }